@@ -2,8 +2,10 @@ use std::sync::Arc;
 
 use anyhow::{Context, Result};
 use futures::lock::Mutex;
+use rbsky::filter::TimelineFilter;
 use rbsky::nvim::{BskyRequestHandler, FeedViewPostFlat};
 use rbsky::runner::Runner;
+use rbsky::subscription::Subscription;
 use rbsky::{nvim::EventHandler, surreal::SurrealDB};
 use simple_log::LogConfigBuilder;
 
@@ -20,16 +22,23 @@ async fn main() -> Result<(), anyhow::Error> {
     let db = SurrealDB::new().await?;
     let nvim_feed_reader = Arc::new(std::sync::Mutex::new(None));
     let nvim_feed_writer = nvim_feed_reader.clone();
+    let db_reader = Arc::new(Mutex::new(db));
     let bsky_request_handler = BskyRequestHandler {
         feed: nvim_feed_reader,
+        thread: Arc::new(std::sync::Mutex::new(None)),
+        notifications: Arc::new(std::sync::Mutex::new(None)),
+        db: db_reader.clone(),
+        runner: Runner::new(String::from("https://bsky.social"), false).await?,
+        timeline: Subscription::Home,
+        filter: Arc::new(std::sync::Mutex::new(TimelineFilter::default())),
+        runtime: tokio::runtime::Handle::current(),
     };
     let runner = Runner::new(String::from("https://bsky.social"), false).await?;
 
-    let db_reader = Arc::new(Mutex::new(db));
     let mut event_handler = EventHandler::new(db_reader, runner)?;
     event_handler
         .fetch_more(
-            String::from("'bafyreia4l6hu774vvvldyxaulvy2vvs7h2tkhjssikl6xzk4kixgrmj5s4'"),
+            String::from("bafyreia4l6hu774vvvldyxaulvy2vvs7h2tkhjssikl6xzk4kixgrmj5s4"),
             nvim_feed_writer,
         )
         .await?;
@@ -0,0 +1,161 @@
+use std::str::FromStr;
+
+use atrium_api::app::bsky::feed::defs::{PostView, ViewerState};
+use atrium_api::app::bsky::feed::post::ReplyRef;
+use atrium_api::com::atproto::repo::strong_ref::Main as StrongRef;
+use atrium_api::records::Record;
+use futures::lock::Mutex;
+
+use crate::commands::AtUri;
+use crate::runner::Runner;
+use crate::sql::Querier;
+use crate::surreal::SurrealDB;
+
+// Returns the current view of a post from the server, post is either the at-URI
+// of the post or the CID of a post stored in the local database
+pub async fn resolve_post(
+    db: &Mutex<SurrealDB>,
+    runner: &Runner,
+    post: &str,
+) -> Result<PostView, anyhow::Error> {
+    runner.fetch_post(&resolve_uri(db, post).await?).await
+}
+
+// Returns the at-URI of post, the CID of a stored post is looked up with the
+// database locked for the query only
+pub async fn resolve_uri(db: &Mutex<SurrealDB>, post: &str) -> Result<String, anyhow::Error> {
+    if post.starts_with("at://") {
+        return Ok(post.to_string());
+    }
+    let db_lock = db.lock().await;
+    let _ = db_lock.db.use_ns("bsky").use_db("timeline").await;
+    let uri = Querier::new(db_lock.db.clone()).select_uri(post).await?;
+    drop(db_lock);
+    uri.ok_or_else(|| anyhow::Error::msg(format!("no post stored with cid {}", post)))
+}
+
+fn strong_ref(post: &PostView) -> StrongRef {
+    StrongRef {
+        cid: post.cid.clone(),
+        uri: post.uri.clone(),
+    }
+}
+
+// Returns the references of a reply to parent, the root is the root of the
+// parent when it is a reply itself
+pub fn reply_ref(parent: &PostView) -> ReplyRef {
+    let root = match &parent.record {
+        Record::AppBskyFeedPost(record) => record.reply.as_ref().map(|reply| reply.root.clone()),
+        _ => None,
+    };
+    ReplyRef {
+        parent: strong_ref(parent),
+        root: root.unwrap_or_else(|| strong_ref(parent)),
+    }
+}
+
+// Returns the references of a reply to parent, a CID is looked up in the local
+// database first and only fetched from the server when it is not stored. The
+// database is not locked while fetching.
+pub async fn resolve_reply(
+    db: &Mutex<SurrealDB>,
+    runner: &Runner,
+    parent: &str,
+) -> Result<ReplyRef, anyhow::Error> {
+    if !parent.starts_with("at://") {
+        let db_lock = db.lock().await;
+        let _ = db_lock.db.use_ns("bsky").use_db("timeline").await;
+        let stored = Querier::new(db_lock.db.clone())
+            .get_post(parent.to_string())
+            .await?;
+        drop(db_lock);
+        if let Some(stored) = stored {
+            return Ok(reply_ref(&stored.post));
+        }
+    }
+    let parent = resolve_post(db, runner, parent).await?;
+    Ok(reply_ref(&parent))
+}
+
+fn viewer(post: &mut PostView) -> &mut ViewerState {
+    post.viewer.get_or_insert(ViewerState {
+        like: None,
+        reply_disabled: None,
+        repost: None,
+    })
+}
+
+// Likes the post and stores the like record uri in post.viewer.like, the cached
+// post is updated right away as the server counts may lag behind. The database
+// is only locked between the requests.
+pub async fn like(
+    db: &Mutex<SurrealDB>,
+    runner: &Runner,
+    post: &str,
+) -> Result<PostView, anyhow::Error> {
+    let mut post = runner.fetch_post(&resolve_uri(db, post).await?).await?;
+    if viewer(&mut post).like.is_some() {
+        return Ok(post);
+    }
+    let like = runner._like(strong_ref(&post)).await?;
+    viewer(&mut post).like = Some(like);
+    post.like_count = Some(post.like_count.unwrap_or(0) + 1);
+    db.lock().await.store_post_view(post.clone()).await?;
+    Ok(post)
+}
+
+// Deletes the like record of the post, if any, and updates the cached post
+pub async fn unlike(
+    db: &Mutex<SurrealDB>,
+    runner: &Runner,
+    post: &str,
+) -> Result<PostView, anyhow::Error> {
+    let mut post = runner.fetch_post(&resolve_uri(db, post).await?).await?;
+    let like = match viewer(&mut post).like.take() {
+        Some(like) => like,
+        None => return Ok(post),
+    };
+    runner
+        ._unlike(AtUri::from_str(&like).map_err(anyhow::Error::msg)?)
+        .await?;
+    post.like_count = Some((post.like_count.unwrap_or(0) - 1).max(0));
+    db.lock().await.store_post_view(post.clone()).await?;
+    Ok(post)
+}
+
+// Reposts the post and stores the repost record uri in post.viewer.repost, the
+// database is only locked between the requests
+pub async fn repost(
+    db: &Mutex<SurrealDB>,
+    runner: &Runner,
+    post: &str,
+) -> Result<PostView, anyhow::Error> {
+    let mut post = runner.fetch_post(&resolve_uri(db, post).await?).await?;
+    if viewer(&mut post).repost.is_some() {
+        return Ok(post);
+    }
+    let repost = runner._repost(strong_ref(&post)).await?;
+    viewer(&mut post).repost = Some(repost);
+    post.repost_count = Some(post.repost_count.unwrap_or(0) + 1);
+    db.lock().await.store_post_view(post.clone()).await?;
+    Ok(post)
+}
+
+// Deletes the repost record of the post, if any, and updates the cached post
+pub async fn unrepost(
+    db: &Mutex<SurrealDB>,
+    runner: &Runner,
+    post: &str,
+) -> Result<PostView, anyhow::Error> {
+    let mut post = runner.fetch_post(&resolve_uri(db, post).await?).await?;
+    let repost = match viewer(&mut post).repost.take() {
+        Some(repost) => repost,
+        None => return Ok(post),
+    };
+    runner
+        ._unrepost(AtUri::from_str(&repost).map_err(anyhow::Error::msg)?)
+        .await?;
+    post.repost_count = Some((post.repost_count.unwrap_or(0) - 1).max(0));
+    db.lock().await.store_post_view(post.clone()).await?;
+    Ok(post)
+}
@@ -0,0 +1,509 @@
+use chrono::{DateTime, Utc};
+use futures::lock::Mutex;
+use log::{info, warn};
+
+use crate::runner::Runner;
+use crate::sql::Querier;
+use crate::subscription::Subscription;
+use crate::surreal::SurrealDB;
+
+#[derive(Debug, Clone)]
+pub struct BackfillOptions {
+    /// Number of posts requested per page
+    pub page_size: u8,
+    /// Maximum number of pages fetched by a single backfill
+    pub max_pages: usize,
+    /// Do not go further back than this date
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl Default for BackfillOptions {
+    fn default() -> Self {
+        BackfillOptions {
+            page_size: 30,
+            max_pages: 10,
+            until: None,
+        }
+    }
+}
+
+// Makes sure at least `want` posts older than `before` are stored without a gap,
+// by paging backwards from the tail of the chain that contains `before` with the
+// cursors returned by the server. Returns the createdAt of the oldest post that
+// is known to be contiguous with `before`, reading down to it never skips a post.
+// The database is only locked between the requests.
+pub async fn backfill(
+    db: &Mutex<SurrealDB>,
+    runner: &Runner,
+    subscription: &Subscription,
+    before: &str,
+    want: i32,
+    options: &BackfillOptions,
+) -> Result<Option<String>, anyhow::Error> {
+    let timeline_name = subscription.name();
+    let timeline_name = timeline_name.as_str();
+    let mut pages = 0;
+    loop {
+        let db_lock = db.lock().await;
+        let _ = db_lock.db.use_ns("bsky").use_db("timeline").await;
+        let querier = Querier::new(db_lock.db.clone());
+        let tail = match querier.chain_tail_covering(timeline_name, before).await? {
+            Some(tail) => tail,
+            None => {
+                warn!(
+                    "no stored page contains {}, nothing to backfill from",
+                    before
+                );
+                return Ok(None);
+            }
+        };
+        let oldest = tail.oldest.clone().unwrap_or_else(|| before.to_string());
+        let available = querier
+            .count_posts_between(timeline_name, &oldest, before)
+            .await?;
+        drop(db_lock);
+        info!(
+            "backfill {}: {} posts between {} and {}, chain {}",
+            timeline_name, available, oldest, before, tail.chain
+        );
+        if available >= want {
+            return Ok(Some(oldest));
+        }
+        let cursor = match tail.cursor {
+            Some(cursor) => cursor,
+            None => {
+                info!("reached the end of {}", timeline_name);
+                return Ok(Some(oldest));
+            }
+        };
+        if let (Some(until), true) = (options.until, subscription.is_chronological()) {
+            if let Ok(oldest_dt) = oldest.parse::<DateTime<Utc>>() {
+                if oldest_dt <= until {
+                    info!("backfill reached {}, stopping", until);
+                    return Ok(Some(oldest));
+                }
+            }
+        }
+        if pages >= options.max_pages {
+            info!("backfill fetched {} pages, stopping", pages);
+            return Ok(Some(oldest));
+        }
+        info!("fetching timeline with cursor: {:?}", cursor);
+        let feed_page = subscription
+            .fetch(runner, Some(cursor.clone()), options.page_size)
+            .await?;
+        let db_lock = db.lock().await;
+        db_lock
+            .store_feed_page(feed_page, timeline_name.to_string(), Some(cursor))
+            .await?;
+        drop(db_lock);
+        pages += 1;
+    }
+}
+
+// Brings the timeline up to date: pages backwards from now until a page reaches
+// the newest post stored before the sync, either by containing an already stored
+// CID or by going past its createdAt, a feed only stops on a stored CID. Every
+// page extends the chain started by the first one, so once the last page
+// overlaps the stored posts the chains merge and there is no gap left between
+// the cache and now. Returns the number of pages fetched. The database is only
+// locked between the requests.
+pub async fn fill_gap(
+    db: &Mutex<SurrealDB>,
+    runner: &Runner,
+    subscription: &Subscription,
+    options: &BackfillOptions,
+) -> Result<usize, anyhow::Error> {
+    let timeline_name = subscription.name();
+    let timeline_name = timeline_name.as_str();
+    let db_lock = db.lock().await;
+    let _ = db_lock.db.use_ns("bsky").use_db("timeline").await;
+    let newest = Querier::new(db_lock.db.clone())
+        .newest_created_at(timeline_name)
+        .await?;
+    drop(db_lock);
+    info!("filling {} from now down to {:?}", timeline_name, newest);
+    let mut cursor: Option<String> = None;
+    let mut pages = 0;
+    loop {
+        let feed_page = subscription
+            .fetch(runner, cursor.clone(), options.page_size)
+            .await?;
+        let mut cids = Vec::new();
+        for f in &feed_page.feed {
+            cids.push(
+                serde_json::to_string(&f.post.cid)?
+                    .trim_matches('"')
+                    .to_string(),
+            );
+        }
+        let next = feed_page.cursor.clone();
+        let db_lock = db.lock().await;
+        let _ = db_lock.db.use_ns("bsky").use_db("timeline").await;
+        let already_stored = Querier::new(db_lock.db.clone())
+            .count_stored_cids(timeline_name, cids)
+            .await?;
+        let page = db_lock
+            .store_feed_page(feed_page, timeline_name.to_string(), cursor.clone())
+            .await?;
+        drop(db_lock);
+        pages += 1;
+        let newest = match &newest {
+            Some(newest) => newest,
+            None => {
+                info!("{} was empty, stored the first page only", timeline_name);
+                return Ok(pages);
+            }
+        };
+        if already_stored > 0 {
+            info!(
+                "page {} overlaps {} stored posts, {} is up to date",
+                pages, already_stored, timeline_name
+            );
+            return Ok(pages);
+        }
+        let oldest = page
+            .and_then(|page| page.oldest)
+            .filter(|_| subscription.is_chronological());
+        if let Some(oldest) = oldest {
+            if oldest.as_str() <= newest.as_str() {
+                info!(
+                    "page {} reached {}, {} is up to date",
+                    pages, newest, timeline_name
+                );
+                return Ok(pages);
+            }
+        }
+        match next {
+            Some(next) => cursor = Some(next),
+            None => {
+                info!("reached the end of {}", timeline_name);
+                return Ok(pages);
+            }
+        }
+        if pages >= options.max_pages {
+            warn!(
+                "fetched {} pages without reaching {}, a gap is left in {}",
+                pages, newest, timeline_name
+            );
+            return Ok(pages);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::post_view_json;
+    use crate::subscription::FeedPage;
+    use crate::surreal::TimelineCursor;
+    use serde_json::json;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    const PAGE_SIZE: usize = 3;
+
+    // The post of minute i, pages are ordered by createdAt like the server does
+    fn created_at(i: u32) -> String {
+        format!("2024-03-01T10:{:02}:00.000Z", i)
+    }
+
+    // A getTimeline stand-in serving the posts newest first, the cursor of a page
+    // is the createdAt of its last post and the next page holds the older posts
+    struct Pds {
+        uri: String,
+        posts: Arc<std::sync::Mutex<Vec<u32>>>,
+        requests: Arc<std::sync::Mutex<Vec<Option<String>>>>,
+    }
+
+    impl Pds {
+        fn page(posts: &[u32], cursor: Option<&str>) -> serde_json::Value {
+            let mut older: Vec<u32> = posts
+                .iter()
+                .copied()
+                .filter(|i| match cursor {
+                    Some(c) => created_at(*i).as_str() < c,
+                    None => true,
+                })
+                .collect();
+            older.sort_unstable_by(|a, b| b.cmp(a));
+            let page: Vec<u32> = older.iter().copied().take(PAGE_SIZE).collect();
+            let next = match page.last() {
+                Some(last) if older.len() > page.len() => Some(created_at(*last)),
+                _ => None,
+            };
+            let feed: Vec<serde_json::Value> = page
+                .iter()
+                .map(|i| {
+                    json!({
+                        "post": post_view_json(&format!("3kp{:02}", i), "post", &created_at(*i))
+                    })
+                })
+                .collect();
+            json!({ "feed": feed, "cursor": next })
+        }
+
+        async fn serve(posts: Vec<u32>) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let posts = Arc::new(std::sync::Mutex::new(posts));
+            let requests = Arc::new(std::sync::Mutex::new(Vec::new()));
+            let pds = Pds {
+                uri: format!("http://{}", addr),
+                posts: posts.clone(),
+                requests: requests.clone(),
+            };
+            tokio::spawn(async move {
+                loop {
+                    let (mut socket, _) = listener.accept().await.unwrap();
+                    let mut request = Vec::new();
+                    let mut buffer = vec![0; 1024];
+                    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                        let n = socket.read(&mut buffer).await.unwrap();
+                        if n == 0 {
+                            break;
+                        }
+                        request.extend_from_slice(&buffer[..n]);
+                    }
+                    let request = String::from_utf8_lossy(&request).to_string();
+                    let path = request.split(' ').nth(1).unwrap_or("/");
+                    let url = reqwest::Url::parse(&format!("http://localhost{}", path)).unwrap();
+                    let cursor = url
+                        .query_pairs()
+                        .find(|(k, _)| k == "cursor")
+                        .map(|(_, v)| v.to_string());
+                    requests.lock().unwrap().push(cursor.clone());
+                    let body = {
+                        let posts = posts.lock().unwrap();
+                        Self::page(&posts, cursor.as_deref()).to_string()
+                    };
+                    let head = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                        body.len()
+                    );
+                    socket.write_all(head.as_bytes()).await.unwrap();
+                    socket.write_all(body.as_bytes()).await.unwrap();
+                }
+            });
+            pds
+        }
+
+        // The cursors of the requests received so far, None for the first page
+        fn requests(&self) -> Vec<Option<String>> {
+            std::mem::take(&mut *self.requests.lock().unwrap())
+        }
+
+        fn feed_page(&self, cursor: Option<&str>) -> FeedPage {
+            let posts = self.posts.lock().unwrap();
+            serde_json::from_value::<atrium_api::app::bsky::feed::get_timeline::Output>(Self::page(
+                &posts, cursor,
+            ))
+            .map(|output| FeedPage {
+                feed: output.feed,
+                cursor: output.cursor,
+            })
+            .unwrap()
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rbsky-{}-test-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    async fn open(name: &str, pds: &Pds) -> (Mutex<SurrealDB>, Runner) {
+        let dir = temp_dir(name);
+        let db = SurrealDB::open(dir.join("bsky.db")).await.unwrap();
+        let runner = Runner::open(pds.uri.clone(), false, dir).await.unwrap();
+        (Mutex::new(db), runner)
+    }
+
+    async fn cursors(db: &Mutex<SurrealDB>) -> Vec<TimelineCursor> {
+        db.lock()
+            .await
+            .read_cursor(String::from("default"))
+            .await
+            .unwrap()
+    }
+
+    fn options(max_pages: usize, until: Option<&str>) -> BackfillOptions {
+        BackfillOptions {
+            page_size: PAGE_SIZE as u8,
+            max_pages,
+            until: until.map(|until| until.parse().unwrap()),
+        }
+    }
+
+    #[tokio::test]
+    async fn backfill_follows_the_cursor_chain() {
+        let pds = Pds::serve((1..=9).collect()).await;
+        let (db, runner) = open("backfill", &pds).await;
+        let home = Subscription::Home;
+        db.lock()
+            .await
+            .store_feed_page(pds.feed_page(None), String::from("default"), None)
+            .await
+            .unwrap();
+        let before = created_at(7);
+
+        // Stops after max_pages, the posts down to the last page are contiguous
+        let oldest = backfill(&db, &runner, &home, &before, 6, &options(1, None))
+            .await
+            .unwrap();
+        assert_eq!(oldest, Some(created_at(4)));
+        assert_eq!(pds.requests(), vec![Some(created_at(7))]);
+
+        // Does not go past until
+        let until = created_at(4);
+        let oldest = backfill(&db, &runner, &home, &before, 6, &options(10, Some(&until)))
+            .await
+            .unwrap();
+        assert_eq!(oldest, Some(created_at(4)));
+        assert!(pds.requests().is_empty());
+
+        // Resumes from the tail of the chain until enough posts are stored
+        let oldest = backfill(&db, &runner, &home, &before, 6, &options(10, None))
+            .await
+            .unwrap();
+        assert_eq!(oldest, Some(created_at(1)));
+        assert_eq!(pds.requests(), vec![Some(created_at(4))]);
+
+        // The end of the timeline was reached, nothing left to fetch
+        let oldest = backfill(&db, &runner, &home, &before, 20, &options(10, None))
+            .await
+            .unwrap();
+        assert_eq!(oldest, Some(created_at(1)));
+        assert!(pds.requests().is_empty());
+
+        let cursors = cursors(&db).await;
+        assert_eq!(cursors.len(), 3);
+        assert!(cursors.iter().all(|c| c.chain == cursors[0].chain));
+    }
+
+    #[tokio::test]
+    async fn backfill_merges_the_chain_it_reaches() {
+        let pds = Pds::serve((1..=9).collect()).await;
+        let (db, runner) = open("backfill-gap", &pds).await;
+        {
+            let db = db.lock().await;
+            db.store_feed_page(pds.feed_page(None), String::from("default"), None)
+                .await
+                .unwrap();
+            // The oldest page was stored by an earlier session, with a gap before it
+            let cursor = created_at(4);
+            db.store_feed_page(
+                pds.feed_page(Some(&cursor)),
+                String::from("default"),
+                Some(cursor),
+            )
+            .await
+            .unwrap();
+        }
+        let chains = cursors(&db).await;
+        assert_ne!(chains[0].chain, chains[1].chain);
+
+        let oldest = backfill(
+            &db,
+            &runner,
+            &Subscription::Home,
+            &created_at(7),
+            6,
+            &options(10, None),
+        )
+        .await
+        .unwrap();
+        assert_eq!(oldest, Some(created_at(1)));
+        assert_eq!(
+            pds.requests(),
+            vec![Some(created_at(7)), Some(created_at(4))]
+        );
+        let cursors = cursors(&db).await;
+        assert!(cursors.iter().all(|c| c.chain == cursors[0].chain));
+    }
+
+    #[tokio::test]
+    async fn fill_gap_stops_where_the_stored_posts_start() {
+        let pds = Pds::serve((1..=9).collect()).await;
+        let (db, runner) = open("fill-gap", &pds).await;
+        let home = Subscription::Home;
+
+        // An empty timeline only gets the first page
+        let pages = fill_gap(&db, &runner, &home, &options(10, None))
+            .await
+            .unwrap();
+        assert_eq!(pages, 1);
+        assert_eq!(pds.requests(), vec![None]);
+
+        // Stops on the page that contains a stored post, the chains are merged
+        pds.posts.lock().unwrap().extend(10..=14);
+        let pages = fill_gap(&db, &runner, &home, &options(10, None))
+            .await
+            .unwrap();
+        assert_eq!(pages, 2);
+        assert_eq!(pds.requests(), vec![None, Some(created_at(12))]);
+        let cursors = cursors(&db).await;
+        assert!(cursors.iter().all(|c| c.chain == cursors[0].chain));
+
+        // The stored posts were deleted, stops once the page goes past them
+        pds.posts.lock().unwrap().retain(|i| *i < 10);
+        pds.posts.lock().unwrap().extend([15, 16]);
+        let pages = fill_gap(&db, &runner, &home, &options(10, None))
+            .await
+            .unwrap();
+        assert_eq!(pages, 1);
+        assert_eq!(pds.requests(), vec![None]);
+
+        // Too many new posts, a gap is left behind the pages fetched
+        pds.posts.lock().unwrap().extend(20..=29);
+        let pages = fill_gap(&db, &runner, &home, &options(2, None))
+            .await
+            .unwrap();
+        assert_eq!(pages, 2);
+        assert_eq!(pds.requests(), vec![None, Some(created_at(27))]);
+        let cursors = cursors(&db).await;
+        let chain = |newest: String| {
+            cursors
+                .iter()
+                .find(|c| c.newest.as_ref() == Some(&newest))
+                .map(|c| c.chain.clone())
+                .unwrap()
+        };
+        assert_ne!(chain(created_at(29)), chain(created_at(16)));
+    }
+
+    #[tokio::test]
+    async fn fill_gap_only_stops_on_a_stored_post_of_a_feed() {
+        let pds = Pds::serve((1..=9).collect()).await;
+        let (db, runner) = open("fill-gap-feed", &pds).await;
+        let uri = "at://did:plc:alice/app.bsky.feed.generator/hot";
+        let feed = Subscription::Feed(uri.parse().unwrap());
+        let pages = fill_gap(&db, &runner, &feed, &options(10, None))
+            .await
+            .unwrap();
+        assert_eq!(pages, 1);
+        assert_eq!(pds.requests(), vec![None]);
+
+        // Going past the date of the stored posts does not stop a feed
+        pds.posts.lock().unwrap().retain(|i| *i < 7);
+        pds.posts.lock().unwrap().extend(10..=12);
+        let pages = fill_gap(&db, &runner, &feed, &options(10, None))
+            .await
+            .unwrap();
+        assert_eq!(pages, 3);
+        assert_eq!(
+            pds.requests(),
+            vec![None, Some(created_at(10)), Some(created_at(4))]
+        );
+
+        pds.posts.lock().unwrap().extend(13..=14);
+        let pages = fill_gap(&db, &runner, &feed, &options(10, None))
+            .await
+            .unwrap();
+        assert_eq!(pages, 1);
+        assert_eq!(pds.requests(), vec![None]);
+    }
+}
@@ -1,10 +1,15 @@
-use atrium_api::app::bsky::feed;
+use atrium_api::app::bsky::feed::post::ReplyRef;
 use clap::Parser;
-use env_logger;
-use log::info;
+use rbsky::commands::{PostArgs, RepoCommand};
+use rbsky::composer::{graphemes, split};
+use rbsky::daemon::socket_path;
+use rbsky::ipc::{Backend, Method};
+use rbsky::markdown::to_post;
+use rbsky::repo::export_repo;
 use rbsky::{commands::Command, runner::Runner};
+use std::fmt::Debug;
 use std::io::Write;
-use std::{fmt::Debug, os::unix::fs::FileExt};
+use std::path::PathBuf;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about)]
@@ -16,141 +21,138 @@ struct Args {
     #[arg(short, long, default_value_t = false)]
     debug: bool,
 
+    /// Daemon socket path, defaults to ~/.config/bsky/rbsky.sock
+    #[arg(long)]
+    socket: Option<PathBuf>,
+
     #[command(subcommand)]
     // command: Command,
     command: rbsky::commands::Command,
 }
 
+// Returns the references of a reply to reply_to, a CID is looked up in the posts
+// stored by the daemon or in the local database
+async fn resolve_reply_to(
+    socket: Option<PathBuf>,
+    pds_host: String,
+    debug: bool,
+    reply_to: Option<String>,
+) -> Result<Option<ReplyRef>, anyhow::Error> {
+    let post = match reply_to {
+        Some(post) => post,
+        None => return Ok(None),
+    };
+    let socket = match socket {
+        Some(socket) => socket,
+        None => socket_path()?,
+    };
+    let mut backend = Backend::connect(&socket, pds_host, debug).await?;
+    Ok(Some(
+        backend
+            .call(Method::ResolveReply(PostArgs { post }))
+            .await?,
+    ))
+}
+
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
     env_logger::init();
     let args = Args::parse();
-    let runner = Runner::new(args.pds_host, args.debug).await?;
 
     let command = args.command;
 
     match command {
-        Command::Login(args) => runner._login(args).await,
-        Command::GetTimeline(args) => {
-            let res: feed::get_timeline::Output = runner._get_timeline(args).await?;
-            let json = serde_json::to_string_pretty(&res);
-            if let Ok(d) = json {
-                println!("{}", d);
-            }
-            Ok(())
-        }
-        Command::GetAuthorFeed(args) => {
-            let res: feed::get_author_feed::Output = runner._get_author_feed(args).await?;
-            let json = serde_json::to_string_pretty(&res);
-            if let Ok(d) = json {
-                println!("{}", d);
-            }
-            Ok(())
+        Command::Login(login) => {
+            Runner::new(args.pds_host, args.debug)
+                .await?
+                ._login(login)
+                .await
         }
-        Command::GetLikes(args) => {
-            let res = runner._get_likes(args).await?;
-            let json = serde_json::to_string_pretty(&res);
-            if let Ok(d) = json {
-                println!("{}", d);
+        Command::CreatePost(post) => {
+            let reply = resolve_reply_to(
+                args.socket,
+                args.pds_host.clone(),
+                args.debug,
+                post.reply_to.clone(),
+            )
+            .await?;
+            let runner = Runner::new(args.pds_host, args.debug).await?;
+            match reply {
+                Some(reply) => runner._create_reply(post, reply).await,
+                None => runner._create_post(post).await,
             }
-            Ok(())
         }
-        Command::GetPosts(args) => {
-            let res = runner._get_post(args).await?;
-            let json = serde_json::to_string_pretty(&res);
-            if let Ok(d) = json {
-                println!("{}", d);
+        Command::CreateThread(thread) => {
+            let reply = resolve_reply_to(
+                args.socket,
+                args.pds_host.clone(),
+                args.debug,
+                thread.post.reply_to.clone(),
+            )
+            .await?;
+            let uris = Runner::new(args.pds_host, args.debug)
+                .await?
+                ._create_thread(thread.post, thread.numbered, reply)
+                .await?;
+            for uri in uris {
+                println!("{}", uri);
             }
             Ok(())
         }
-        Command::GetRepostedBy(args) => {
-            let res = runner._get_reposted_by(args).await?;
-            let json = serde_json::to_string_pretty(&res);
-            if let Ok(d) = json {
-                println!("{}", d);
+        Command::PreviewThread(thread) => {
+            // The posts are cut on the text as it is posted
+            let text = if thread.post.markdown {
+                to_post(&thread.post.text)?.0
+            } else {
+                thread.post.text
+            };
+            let segments = split(&text, thread.numbered);
+            for (i, segment) in segments.iter().enumerate() {
+                println!(
+                    "--- {}/{} ({} graphemes)\n{}",
+                    i + 1,
+                    segments.len(),
+                    graphemes(segment),
+                    segment
+                );
             }
             Ok(())
         }
-        Command::GetActorFeeds(args) => {
-            let res = runner._get_actor_feed(args).await?;
-            let json = serde_json::to_string_pretty(&res);
-            if let Ok(d) = json {
-                println!("{}", d);
-            }
-            Ok(())
+        Command::DeletePost(post) => {
+            Runner::new(args.pds_host, args.debug)
+                .await?
+                ._delete_post(post)
+                .await
         }
-        Command::GetFeed(args) => {
-            let res = runner._get_feed(args).await?;
-            let json = serde_json::to_string_pretty(&res);
-            if let Ok(d) = json {
-                println!("{}", d);
-            }
+        Command::Repo(RepoCommand::Export(export)) => {
+            let runner = Runner::new(args.pds_host, args.debug).await?;
+            let counts = export_repo(&runner, export).await?;
+            println!("{}", serde_json::to_string_pretty(&counts)?);
             Ok(())
         }
-        Command::GetListFeed(args) => {
-            let res = runner._get_list_feed(args).await?;
-            let json = serde_json::to_string_pretty(&res);
-            if let Ok(d) = json {
-                println!("{}", d);
+        command => {
+            let socket = match args.socket {
+                Some(socket) => socket,
+                None => socket_path()?,
+            };
+            let mut backend = Backend::connect(&socket, args.pds_host, args.debug).await?;
+            match Method::try_from(command)? {
+                Method::GetBlob(blob) => {
+                    let res: Vec<u8> = backend.call(Method::GetBlob(blob)).await?;
+                    let file_path = "/tmp/file.jpeg";
+                    let mut file = std::fs::File::create(file_path)?;
+                    file.write_all(&res)?;
+                    Ok(())
+                }
+                method => {
+                    let res = backend.call_value(method).await?;
+                    let json = serde_json::to_string_pretty(&res);
+                    if let Ok(d) = json {
+                        println!("{}", d);
+                    }
+                    Ok(())
+                }
             }
-            Ok(())
-        }
-        Command::GetFollows(args) => {
-            let res = runner._get_follows(args).await?;
-            let json = serde_json::to_string_pretty(&res);
-            if let Ok(d) = json {
-                println!("{}", d);
-            }
-            Ok(())
-        }
-        Command::GetFollowers(args) => {
-            let res = runner._get_followers(args).await?;
-            let json = serde_json::to_string_pretty(&res);
-            if let Ok(d) = json {
-                println!("{}", d);
-            }
-            Ok(())
-        }
-        Command::GetLists(args) => {
-            let res = runner._get_lists(args).await?;
-            let json = serde_json::to_string_pretty(&res);
-            if let Ok(d) = json {
-                println!("{}", d);
-            }
-            Ok(())
-        }
-        Command::GetList(args) => {
-            let res = runner._get_list(args).await?;
-            let json = serde_json::to_string_pretty(&res);
-            if let Ok(d) = json {
-                println!("{}", d);
-            }
-            Ok(())
-        }
-        Command::GetProfile(args) => {
-            let res = runner._get_profile(args).await?;
-            let json = serde_json::to_string_pretty(&res);
-            if let Ok(d) = json {
-                println!("{}", d);
-            }
-            Ok(())
-        }
-        Command::GetBlob(args) => {
-            let res = runner._get_blob(args).await?;
-            let file_path = "/tmp/file.jpeg";
-            let mut file = std::fs::File::create(file_path)?;
-            file.write_all(&res)?;
-            Ok(())
-        }
-        Command::ListNotifications(args) => {
-            let res = runner._list_notifications(args).await?;
-            let json = serde_json::to_string_pretty(&res);
-            if let Ok(d) = json {
-                println!("{}", d);
-            }
-            Ok(())
         }
-        Command::CreatePost(args) => runner._create_post(args).await,
-        Command::DeletePost(args) => runner._delete_post(args).await,
     }
 }
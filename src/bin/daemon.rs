@@ -0,0 +1,122 @@
+use anyhow::{Context, Result};
+use clap::Parser;
+use log::{error, info};
+use rbsky::commands::LoginArgs;
+use rbsky::daemon::{socket_path, Daemon};
+use rbsky::runner::Runner;
+use rbsky::subscription::Subscription;
+use rbsky::surreal::SurrealDB;
+use simple_log::LogConfigBuilder;
+use std::path::PathBuf;
+use tokio::fs::create_dir_all;
+use tokio::time::Duration;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about)]
+struct Args {
+    #[arg(short, long, default_value = "https://bsky.social")]
+    pds_host: String,
+
+    #[arg(short, long, default_value_t = false)]
+    debug: bool,
+
+    /// Use environment variables BSKYUSERNAME and BSKYPASSWORD to login on startup
+    #[arg(long, default_value_t = false)]
+    login_from_env: bool,
+
+    #[arg(long, default_value_t = 300)]
+    timeline_interval: u64,
+
+    #[arg(long, default_value_t = 60)]
+    notification_interval: u64,
+
+    /// Timeline kept in sync next to the home timeline, feed:<uri>, list:<uri> or
+    /// author:<actor>, can be repeated
+    #[arg(long)]
+    subscribe: Vec<Subscription>,
+
+    /// Unix socket path, defaults to ~/.config/bsky/rbsky.sock
+    #[arg(long)]
+    socket: Option<PathBuf>,
+
+    #[arg(long, default_value = "info")]
+    log_level: String,
+}
+
+async fn init(log_level: &str) -> Result<(), anyhow::Error> {
+    let config_dir =
+        dirs::config_dir().with_context(|| format!("No config dir: {:?}", dirs::config_dir()))?;
+    let dir = config_dir.join("bsky");
+    create_dir_all(&dir).await?;
+    let path = dir.join("rbsky-daemon.log");
+    if let Some(path) = path.to_str() {
+        let config = LogConfigBuilder::builder()
+            .path(String::from(path))
+            .level(log_level)
+            .size(100)
+            .roll_count(10)
+            .output_file()
+            .build();
+        let _ = simple_log::new(config);
+    }
+    info!("Logger Initialized");
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), anyhow::Error> {
+    let args = Args::parse();
+    init(&args.log_level).await?;
+    let db = SurrealDB::new().await?;
+    let runner = Runner::new(args.pds_host, args.debug).await?;
+    if args.login_from_env {
+        runner
+            ._login(LoginArgs {
+                from_env: true,
+                identifier: None,
+                password: None,
+            })
+            .await?;
+    }
+    let mut subscriptions = Vec::new();
+    for subscription in args.subscribe {
+        subscriptions.push(subscription.resolve(&runner).await?);
+    }
+    let mut daemon = Daemon::new(db, runner);
+    daemon.subscriptions = subscriptions;
+
+    let timeline_daemon = daemon.clone();
+    let timeline_interval = Duration::from_secs(args.timeline_interval);
+    tokio::spawn(async move {
+        timeline_daemon
+            .auto_refresh_timeline(timeline_interval)
+            .await
+    });
+
+    let notification_daemon = daemon.clone();
+    let notification_interval = Duration::from_secs(args.notification_interval);
+    tokio::spawn(async move {
+        notification_daemon
+            .auto_refresh_notifications(notification_interval)
+            .await
+    });
+
+    let path = match args.socket {
+        Some(path) => path,
+        None => socket_path()?,
+    };
+    tokio::select! {
+        res = daemon.serve(path.clone()) => {
+            if let Err(e) = res {
+                error!("daemon stopped: {:?}", e);
+                return Err(e);
+            }
+        }
+        _ = tokio::signal::ctrl_c() => {
+            info!("received ctrl-c, shutting down");
+        }
+    }
+    let _ = std::fs::remove_file(&path);
+    info!("daemon, done!");
+    Ok(())
+}
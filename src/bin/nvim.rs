@@ -1,14 +1,20 @@
 use std::sync::Arc;
 
 use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
 use clap::Parser;
 use futures::lock::Mutex;
 use log::{error, info};
+use rbsky::backfill::BackfillOptions;
 use rbsky::commands::LoginArgs;
-use rbsky::nvim::{BskyRequestHandler, FeedViewPostFlat};
+use rbsky::filter::TimelineFilter;
+use rbsky::jetstream::JETSTREAM_URL;
+use rbsky::nvim::{BskyRequestHandler, FeedViewPostFlat, Incoming};
 use rbsky::runner::Runner;
+use rbsky::subscription::Subscription;
 use rbsky::{nvim::EventHandler, surreal::SurrealDB};
 use simple_log::LogConfigBuilder;
+use std::sync::mpsc::{channel, Sender};
 use tokio::fs::create_dir_all;
 use tokio::time::Duration;
 
@@ -29,6 +35,30 @@ struct Args {
 
     #[arg(long, default_value = "info")]
     log_level: String,
+
+    /// Number of posts loaded when neovim asks for more
+    #[arg(long, default_value_t = 30)]
+    backfill_page_size: u8,
+
+    /// Maximum number of pages fetched from the server for a single load more
+    #[arg(long, default_value_t = 10)]
+    backfill_depth: usize,
+
+    /// Never backfill posts older than this RFC3339 date
+    #[arg(long)]
+    backfill_until: Option<DateTime<Utc>>,
+
+    /// Timeline shown in neovim, default, feed:<uri>, list:<uri> or author:<actor>
+    #[arg(long, default_value = "default")]
+    timeline: Subscription,
+
+    /// Add the posts of the followed accounts to the home timeline as they are created
+    #[arg(long, default_value_t = false)]
+    realtime: bool,
+
+    /// Jetstream subscribe endpoint used by --realtime
+    #[arg(long, default_value = JETSTREAM_URL)]
+    jetstream_url: String,
 }
 
 async fn init(log_level: &str) -> Result<(), anyhow::Error> {
@@ -56,9 +86,15 @@ async fn auto_update(
     runner: Runner,
     nvim_feed: Arc<std::sync::Mutex<Option<Vec<FeedViewPostFlat>>>>,
     update_interval: u64,
+    timeline: Subscription,
+    filter: Arc<std::sync::Mutex<TimelineFilter>>,
+    alerts: Sender<Incoming>,
 ) -> Result<(), anyhow::Error> {
     let task_interval = Duration::from_secs(update_interval);
     let mut event_handler_bg = EventHandler::new(db, runner)?;
+    event_handler_bg.timeline = timeline;
+    event_handler_bg.filter = filter;
+    event_handler_bg.alerts = Some(alerts);
     tokio::spawn(async move {
         if let Err(e) = event_handler_bg
             .auto_refresh_timeline(task_interval, nvim_feed)
@@ -70,6 +106,25 @@ async fn auto_update(
     Ok(())
 }
 
+async fn realtime(
+    db: Arc<Mutex<SurrealDB>>,
+    runner: Runner,
+    nvim_feed: Arc<std::sync::Mutex<Option<Vec<FeedViewPostFlat>>>>,
+    timeline: Subscription,
+    filter: Arc<std::sync::Mutex<TimelineFilter>>,
+    url: String,
+) -> Result<(), anyhow::Error> {
+    let mut event_handler_stream = EventHandler::new(db, runner)?;
+    event_handler_stream.timeline = timeline;
+    event_handler_stream.filter = filter;
+    tokio::spawn(async move {
+        if let Err(e) = event_handler_stream.stream_timeline(&url, nvim_feed).await {
+            error!("Error in stream_timeline: {:?}", e);
+        }
+    });
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
     let args = Args::parse();
@@ -77,12 +132,11 @@ async fn main() -> Result<(), anyhow::Error> {
     let db = SurrealDB::new().await?;
     let nvim_feed_reader = Arc::new(std::sync::Mutex::new(None));
     let nvim_feed_writer = nvim_feed_reader.clone();
+    let nvim_feed_stream = nvim_feed_reader.clone();
 
     let db_reader = Arc::new(Mutex::new(db));
     let db_writer = db_reader.clone();
-    let bsky_request_handler = BskyRequestHandler {
-        feed: nvim_feed_reader,
-    };
+    let db_stream = db_reader.clone();
 
     let pds_host = args.pds_host;
     let runner = Runner::new(pds_host.clone(), args.debug).await?;
@@ -94,8 +148,28 @@ async fn main() -> Result<(), anyhow::Error> {
             password: None,
         })
         .await?;
+    let timeline = args.timeline.clone().resolve(&runner).await?;
 
+    let filter = Arc::new(std::sync::Mutex::new(TimelineFilter::default()));
+    let bsky_request_handler = BskyRequestHandler {
+        feed: nvim_feed_reader,
+        thread: Arc::new(std::sync::Mutex::new(None)),
+        notifications: Arc::new(std::sync::Mutex::new(None)),
+        db: db_reader.clone(),
+        runner: Runner::new(pds_host.clone(), args.debug).await?,
+        timeline: timeline.clone(),
+        filter: filter.clone(),
+        runtime: tokio::runtime::Handle::current(),
+    };
     let mut event_handler = EventHandler::new(db_reader, runner)?;
+    event_handler.backfill = BackfillOptions {
+        page_size: args.backfill_page_size,
+        max_pages: args.backfill_depth,
+        until: args.backfill_until,
+    };
+    event_handler.timeline = timeline.clone();
+    event_handler.filter = filter.clone();
+    let (sender, incoming) = channel();
     if args.auto_update {
         info!("Starting auto update");
         let _ = auto_update(
@@ -103,10 +177,28 @@ async fn main() -> Result<(), anyhow::Error> {
             runner_bg,
             nvim_feed_writer,
             args.auto_update_interval,
+            timeline.clone(),
+            filter.clone(),
+            sender.clone(),
         )
         .await;
     }
-    event_handler.recv(bsky_request_handler).await?;
+    if args.realtime {
+        info!("Starting realtime updates from {}", args.jetstream_url);
+        let runner_stream = Runner::new(pds_host.clone(), args.debug).await?;
+        let _ = realtime(
+            db_stream,
+            runner_stream,
+            nvim_feed_stream,
+            timeline,
+            filter,
+            args.jetstream_url,
+        )
+        .await;
+    }
+    event_handler
+        .recv(bsky_request_handler, sender, incoming)
+        .await?;
     info!("event_handler, done!");
     Ok(())
 }
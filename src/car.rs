@@ -0,0 +1,459 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::Cursor;
+
+use anyhow::Context;
+use libipld_core::cid::Cid;
+use libipld_core::ipld::Ipld;
+use libipld_core::multibase::{encode, Base};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+// Multicodec codes of the blocks found in a repository
+const DAG_CBOR: u64 = 0x71;
+const RAW: u64 = 0x55;
+const SHA2_256: u64 = 0x12;
+
+#[derive(Deserialize, Debug)]
+struct Header {
+    version: u64,
+    roots: Vec<Cid>,
+}
+
+// A CARv1 file, every block is checked against its CID while parsing
+#[derive(Debug)]
+pub struct Car {
+    pub roots: Vec<Cid>,
+    pub blocks: HashMap<Cid, Vec<u8>>,
+}
+
+// Reads the unsigned LEB128 varint at the start of bytes, returns it with the
+// number of bytes it took
+fn read_varint(bytes: &[u8]) -> Result<(usize, usize), anyhow::Error> {
+    let mut value: usize = 0;
+    for (i, byte) in bytes.iter().enumerate().take(9) {
+        value |= ((byte & 0x7f) as usize) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok((value, i + 1));
+        }
+    }
+    Err(anyhow::Error::msg("Invalid varint"))
+}
+
+impl Car {
+    pub fn parse(bytes: &[u8]) -> Result<Self, anyhow::Error> {
+        let (length, offset) = read_varint(bytes)?;
+        let header_bytes = bytes
+            .get(offset..offset + length)
+            .with_context(|| "Truncated CAR header")?;
+        let header: Header = serde_ipld_dagcbor::from_slice(header_bytes)?;
+        if header.version != 1 {
+            return Err(anyhow::Error::msg(format!(
+                "Unsupported CAR version {}",
+                header.version
+            )));
+        }
+        let mut blocks = HashMap::new();
+        let mut rest = &bytes[offset + length..];
+        while !rest.is_empty() {
+            let (length, offset) = read_varint(rest)?;
+            let section = rest
+                .get(offset..offset + length)
+                .with_context(|| "Truncated CAR block")?;
+            let mut cursor = Cursor::new(section);
+            let cid = Cid::read_bytes(&mut cursor)?;
+            let data = &section[cursor.position() as usize..];
+            verify_block(&cid, data)?;
+            blocks.insert(cid, data.to_vec());
+            rest = &rest[offset + length..];
+        }
+        Ok(Car {
+            roots: header.roots,
+            blocks,
+        })
+    }
+
+    fn get<'a, T: Deserialize<'a>>(&'a self, cid: &Cid) -> Result<T, anyhow::Error> {
+        let block = self
+            .blocks
+            .get(cid)
+            .with_context(|| format!("Missing block {}", cid))?;
+        serde_ipld_dagcbor::from_slice(block).with_context(|| format!("Invalid block {}", cid))
+    }
+}
+
+fn verify_block(cid: &Cid, data: &[u8]) -> Result<(), anyhow::Error> {
+    if cid.codec() != DAG_CBOR && cid.codec() != RAW {
+        return Err(anyhow::Error::msg(format!(
+            "Unexpected codec {:#x} for {}",
+            cid.codec(),
+            cid
+        )));
+    }
+    if cid.hash().code() != SHA2_256 {
+        return Err(anyhow::Error::msg(format!("Unexpected hash for {}", cid)));
+    }
+    if cid.hash().digest() != Sha256::digest(data).as_slice() {
+        return Err(anyhow::Error::msg(format!(
+            "Block {} does not match its CID",
+            cid
+        )));
+    }
+    Ok(())
+}
+
+// The signed commit at the root of a repository, the signature is not checked
+#[derive(Deserialize, Debug, Clone)]
+pub struct Commit {
+    pub did: String,
+    pub version: u64,
+    pub data: Cid,
+    pub rev: String,
+}
+
+// A node of the Merkle Search Tree, keys are compressed against the previous
+// entry of the node
+#[derive(Deserialize, Debug)]
+struct Node {
+    l: Option<Cid>,
+    e: Vec<Entry>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Entry {
+    p: usize,
+    #[serde(with = "serde_bytes")]
+    k: Vec<u8>,
+    v: Cid,
+    t: Option<Cid>,
+}
+
+// Returns the MST layer of a key: the number of leading zeros of its SHA-256
+// counted in pairs of bits
+pub fn layer(key: &[u8]) -> u32 {
+    let mut zeros = 0;
+    for byte in Sha256::digest(key) {
+        if byte < 64 {
+            zeros += 1;
+        }
+        if byte < 16 {
+            zeros += 1;
+        }
+        if byte < 4 {
+            zeros += 1;
+        }
+        if byte != 0 {
+            break;
+        }
+        zeros += 1;
+    }
+    zeros
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RepoRecord {
+    pub collection: String,
+    pub rkey: String,
+    pub cid: Cid,
+    pub value: serde_json::Value,
+}
+
+// A repository read from a CAR export, records are ordered by key
+#[derive(Debug)]
+pub struct Repo {
+    pub commit: Commit,
+    pub records: Vec<RepoRecord>,
+}
+
+impl Repo {
+    // Checks the blocks against their CIDs, the layout of the MST and that
+    // every record it references is in the CAR
+    pub fn from_car(bytes: &[u8]) -> Result<Self, anyhow::Error> {
+        let car = Car::parse(bytes)?;
+        let root = car.roots.first().with_context(|| "The CAR has no root")?;
+        let commit: Commit = car.get(root)?;
+        let mut keys = Vec::new();
+        walk(&car, &commit.data, None, &mut keys)?;
+        if keys.windows(2).any(|pair| pair[0].0 >= pair[1].0) {
+            return Err(anyhow::Error::msg("MST keys are out of order"));
+        }
+        let mut records = Vec::new();
+        for (key, cid) in keys {
+            let (collection, rkey) = key
+                .split_once('/')
+                .with_context(|| format!("Invalid record key {}", key))?;
+            let value: Ipld = car.get(&cid)?;
+            records.push(RepoRecord {
+                collection: collection.to_string(),
+                rkey: rkey.to_string(),
+                cid,
+                value: to_json(value),
+            });
+        }
+        Ok(Repo { commit, records })
+    }
+}
+
+// Collects the keys of the subtree at cid in order, every entry of a node is on
+// the same layer and its subtrees are below it
+fn walk(
+    car: &Car,
+    cid: &Cid,
+    above: Option<u32>,
+    keys: &mut Vec<(String, Cid)>,
+) -> Result<(), anyhow::Error> {
+    let node: Node = car.get(cid)?;
+    let mut node_layer = None;
+    let mut entries = Vec::new();
+    let mut previous: Vec<u8> = Vec::new();
+    for entry in node.e {
+        let mut key = previous
+            .get(..entry.p)
+            .with_context(|| format!("Invalid key prefix in {}", cid))?
+            .to_vec();
+        key.extend_from_slice(&entry.k);
+        let key_layer = layer(&key);
+        if *node_layer.get_or_insert(key_layer) != key_layer
+            || above.is_some_and(|above| key_layer >= above)
+        {
+            return Err(anyhow::Error::msg(format!("MST node {} is misplaced", cid)));
+        }
+        previous = key.clone();
+        entries.push((String::from_utf8(key)?, entry.v, entry.t));
+    }
+    let below = node_layer.or(above);
+    if let Some(left) = node.l {
+        walk(car, &left, below, keys)?;
+    }
+    for (key, value, tree) in entries {
+        keys.push((key, value));
+        if let Some(tree) = tree {
+            walk(car, &tree, below, keys)?;
+        }
+    }
+    Ok(())
+}
+
+// Converts a record to its JSON form, links become {"$link"} and bytes
+// {"$bytes"} objects
+pub fn to_json(ipld: Ipld) -> serde_json::Value {
+    match ipld {
+        Ipld::Null => serde_json::Value::Null,
+        Ipld::Bool(b) => b.into(),
+        Ipld::Integer(i) => i64::try_from(i)
+            .map(serde_json::Value::from)
+            .unwrap_or(serde_json::Value::Null),
+        Ipld::Float(f) => f.into(),
+        Ipld::String(s) => s.into(),
+        Ipld::Bytes(bytes) => {
+            // Multibase prefixes base64 with 'm'
+            let encoded = encode(Base::Base64, bytes);
+            serde_json::json!({ "$bytes": &encoded[1..] })
+        }
+        Ipld::List(list) => list.into_iter().map(to_json).collect(),
+        Ipld::Map(map) => serde_json::Value::Object(
+            map.into_iter()
+                .map(|(key, value)| (key, to_json(value)))
+                .collect(),
+        ),
+        Ipld::Link(cid) => serde_json::json!({ "$link": cid.to_string() }),
+    }
+}
+
+// Records of a repository counted by collection
+pub fn count_collections(repo: &Repo) -> BTreeMap<String, usize> {
+    let mut counts = BTreeMap::new();
+    for record in &repo.records {
+        *counts.entry(record.collection.clone()).or_insert(0) += 1;
+    }
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libipld_core::multihash::Multihash;
+    use serde::Serialize;
+
+    fn cid_of(data: &[u8]) -> Cid {
+        let digest = Sha256::digest(data);
+        Cid::new_v1(DAG_CBOR, Multihash::wrap(SHA2_256, &digest).unwrap())
+    }
+
+    fn write_varint(mut value: usize, out: &mut Vec<u8>) {
+        while value >= 0x80 {
+            out.push((value as u8 & 0x7f) | 0x80);
+            value >>= 7;
+        }
+        out.push(value as u8);
+    }
+
+    #[derive(Serialize)]
+    struct TestEntry {
+        p: usize,
+        #[serde(with = "serde_bytes")]
+        k: Vec<u8>,
+        v: Cid,
+        t: Option<Cid>,
+    }
+
+    #[derive(Serialize)]
+    struct TestNode {
+        l: Option<Cid>,
+        e: Vec<TestEntry>,
+    }
+
+    #[derive(Serialize)]
+    struct TestCommit {
+        did: String,
+        version: u64,
+        data: Cid,
+        rev: String,
+        prev: Option<Cid>,
+        #[serde(with = "serde_bytes")]
+        sig: Vec<u8>,
+    }
+
+    #[derive(Serialize)]
+    struct TestHeader {
+        version: u64,
+        roots: Vec<Cid>,
+    }
+
+    // Builds a single node repository of the layer 0 keys among the candidates
+    fn car(candidates: &[(&str, serde_json::Value)]) -> (Vec<u8>, Vec<String>) {
+        let mut blocks: Vec<(Cid, Vec<u8>)> = Vec::new();
+        let mut entries = Vec::new();
+        let mut keys = Vec::new();
+        for (key, record) in candidates {
+            if layer(key.as_bytes()) != 0 {
+                continue;
+            }
+            let data = serde_ipld_dagcbor::to_vec(record).unwrap();
+            let cid = cid_of(&data);
+            blocks.push((cid, data));
+            entries.push(TestEntry {
+                p: 0,
+                k: key.as_bytes().to_vec(),
+                v: cid,
+                t: None,
+            });
+            keys.push(key.to_string());
+        }
+        let node = serde_ipld_dagcbor::to_vec(&TestNode {
+            l: None,
+            e: entries,
+        })
+        .unwrap();
+        let node_cid = cid_of(&node);
+        blocks.push((node_cid, node));
+        let commit = serde_ipld_dagcbor::to_vec(&TestCommit {
+            did: String::from("did:plc:alice"),
+            version: 3,
+            data: node_cid,
+            rev: String::from("3l3qo2vuowo2b"),
+            prev: None,
+            sig: vec![0; 64],
+        })
+        .unwrap();
+        let commit_cid = cid_of(&commit);
+        blocks.insert(0, (commit_cid, commit));
+
+        let header = serde_ipld_dagcbor::to_vec(&TestHeader {
+            version: 1,
+            roots: vec![commit_cid],
+        })
+        .unwrap();
+        let mut bytes = Vec::new();
+        write_varint(header.len(), &mut bytes);
+        bytes.extend(header);
+        for (cid, data) in blocks {
+            let cid = cid.to_bytes();
+            write_varint(cid.len() + data.len(), &mut bytes);
+            bytes.extend(cid);
+            bytes.extend(data);
+        }
+        (bytes, keys)
+    }
+
+    fn candidates() -> Vec<(String, serde_json::Value)> {
+        (0..8)
+            .map(|i| {
+                (
+                    format!("app.bsky.feed.post/3l3qo2vutsw{}b", i),
+                    serde_json::json!({
+                        "$type": "app.bsky.feed.post",
+                        "text": format!("post {}", i),
+                        "createdAt": "2024-09-09T19:46:02.102Z",
+                    }),
+                )
+            })
+            .chain([(
+                String::from("app.bsky.graph.follow/3l3qo2vutsx2b"),
+                serde_json::json!({
+                    "$type": "app.bsky.graph.follow",
+                    "subject": "did:plc:bob",
+                    "createdAt": "2024-09-09T19:46:02.102Z",
+                }),
+            )])
+            .collect()
+    }
+
+    #[test]
+    fn records_are_read_from_a_verified_car() {
+        let candidates = candidates();
+        let candidates: Vec<(&str, serde_json::Value)> = candidates
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.clone()))
+            .collect();
+        let (bytes, keys) = car(&candidates);
+        assert!(keys.len() > 1);
+
+        let repo = Repo::from_car(&bytes).unwrap();
+        assert_eq!(repo.commit.did, "did:plc:alice");
+        assert_eq!(
+            repo.records
+                .iter()
+                .map(|r| format!("{}/{}", r.collection, r.rkey))
+                .collect::<Vec<_>>(),
+            keys
+        );
+        let first = candidates.iter().find(|(key, _)| *key == keys[0]).unwrap();
+        assert_eq!(repo.records[0].value, first.1);
+
+        // A flipped byte in the last record no longer matches its CID
+        let mut corrupted = bytes.clone();
+        let last = corrupted.len() - 1;
+        corrupted[last] ^= 1;
+        assert!(Car::parse(&corrupted).is_err());
+    }
+
+    #[test]
+    fn keys_on_the_wrong_layer_are_rejected() {
+        let candidates = candidates();
+        let misplaced = candidates.iter().find(|(key, _)| layer(key.as_bytes()) > 0);
+        // The node builder skips keys above layer 0, put one back by hand
+        let (key, value) = misplaced.expect("a key above layer 0");
+        let data = serde_ipld_dagcbor::to_vec(value).unwrap();
+        let mut car = Car {
+            roots: vec![],
+            blocks: HashMap::new(),
+        };
+        let record = cid_of(&data);
+        car.blocks.insert(record, data);
+        let node = serde_ipld_dagcbor::to_vec(&TestNode {
+            l: None,
+            e: vec![TestEntry {
+                p: 0,
+                k: key.as_bytes().to_vec(),
+                v: record,
+                t: None,
+            }],
+        })
+        .unwrap();
+        let node_cid = cid_of(&node);
+        car.blocks.insert(node_cid, node);
+        let mut keys = Vec::new();
+        assert!(walk(&car, &node_cid, None, &mut keys).is_ok());
+        assert!(walk(&car, &node_cid, Some(0), &mut keys).is_err());
+    }
+}
@@ -0,0 +1,255 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use regex::Regex;
+use reqwest::Url;
+
+const META: &str = r"(?is)<meta\s[^>]*>";
+const ATTRIBUTE: &str = r#"(?s)([a-zA-Z][a-zA-Z:-]*)\s*=\s*(?:"([^"]*)"|'([^']*)')"#;
+const TITLE: &str = r"(?is)<title[^>]*>(.*?)</title>";
+// Pages and thumbnails larger than this are not used for a card
+const MAX_BODY_BYTES: usize = 5_000_000;
+
+// Fetches the pages and the thumbnails of link cards
+#[async_trait]
+pub trait Fetcher {
+    async fn fetch(&self, url: &str) -> Result<Vec<u8>, anyhow::Error>;
+}
+
+pub struct HttpFetcher {
+    client: reqwest::Client,
+}
+
+impl HttpFetcher {
+    pub fn new() -> Result<Self, anyhow::Error> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .user_agent(concat!("rbsky/", env!("CARGO_PKG_VERSION")))
+            .build()?;
+        Ok(Self { client })
+    }
+}
+
+#[async_trait]
+impl Fetcher for HttpFetcher {
+    async fn fetch(&self, url: &str) -> Result<Vec<u8>, anyhow::Error> {
+        let mut response = self.client.get(url).send().await?.error_for_status()?;
+        if let Some(length) = response.content_length() {
+            if length > MAX_BODY_BYTES as u64 {
+                return Err(anyhow::Error::msg(format!(
+                    "{} is {} bytes, at most {}",
+                    url, length, MAX_BODY_BYTES
+                )));
+            }
+        }
+        // The length is not always announced, the body is read up to the limit
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            if body.len() + chunk.len() > MAX_BODY_BYTES {
+                return Err(anyhow::Error::msg(format!(
+                    "{} is over {} bytes",
+                    url, MAX_BODY_BYTES
+                )));
+            }
+            body.extend_from_slice(&chunk);
+        }
+        Ok(body)
+    }
+}
+
+// The content of an app.bsky.embed.external card, image is the absolute url
+// of the thumbnail
+#[derive(Debug, Clone, PartialEq)]
+pub struct Card {
+    pub uri: String,
+    pub title: String,
+    pub description: String,
+    pub image: Option<String>,
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#x27;", "'")
+        .replace("&apos;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Returns the content of the meta tags of the page by property or name,
+// og:title, description, ...
+fn meta(html: &str) -> HashMap<String, String> {
+    let tag = Regex::new(META).expect("valid meta regex");
+    let attribute = Regex::new(ATTRIBUTE).expect("valid attribute regex");
+    let mut found = HashMap::new();
+    for tag in tag.find_iter(html) {
+        let attributes: HashMap<String, &str> = attribute
+            .captures_iter(tag.as_str())
+            .filter_map(|captures| {
+                let value = captures.get(2).or_else(|| captures.get(3))?;
+                Some((captures[1].to_lowercase(), value.as_str()))
+            })
+            .collect();
+        let key = attributes
+            .get("property")
+            .or_else(|| attributes.get("name"));
+        if let (Some(key), Some(content)) = (key, attributes.get("content")) {
+            // The first tag wins, as in the official clients
+            found
+                .entry(key.to_lowercase())
+                .or_insert_with(|| unescape(content));
+        }
+    }
+    found
+}
+
+// Builds the card of the page at uri from its OpenGraph tags, falling back on
+// the title and the description of the page
+pub fn parse(uri: &str, html: &str) -> Card {
+    let mut meta = meta(html);
+    let title = meta
+        .remove("og:title")
+        .or_else(|| {
+            let title = Regex::new(TITLE).expect("valid title regex");
+            title.captures(html).map(|captures| unescape(&captures[1]))
+        })
+        .filter(|title| !title.is_empty())
+        .unwrap_or_else(|| uri.to_string());
+    let description = meta
+        .remove("og:description")
+        .or_else(|| meta.remove("description"))
+        .unwrap_or_default();
+    // The image may be relative to the page
+    let image = meta.remove("og:image").and_then(|image| {
+        Url::parse(uri)
+            .and_then(|base| base.join(&image))
+            .ok()
+            .map(String::from)
+    });
+    Card {
+        uri: uri.to_string(),
+        title,
+        description,
+        image,
+    }
+}
+
+pub async fn fetch_card<F>(fetcher: &F, uri: &str) -> Result<Card, anyhow::Error>
+where
+    F: Fetcher + Sync + ?Sized,
+{
+    let body = fetcher.fetch(uri).await?;
+    Ok(parse(uri, &String::from_utf8_lossy(&body)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    const PAGE: &str = r#"<html><head>
+<title>Fallback title</title>
+<meta property="og:title" content="Rust &amp; Neovim">
+<meta content='A post client for the terminal' property='og:description'>
+<meta property="og:image" content="/static/card.png">
+</head><body></body></html>"#;
+
+    // Serves the page at /, a body over the limit at /large, announced, and at
+    // /unannounced, without a length, and the thumbnail anywhere else
+    async fn serve() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = vec![0; 1024];
+                let n = socket.read(&mut request).await.unwrap();
+                let request = String::from_utf8_lossy(&request[..n]).to_string();
+                if request.starts_with("GET /large ") {
+                    let head = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                        MAX_BODY_BYTES + 1
+                    );
+                    let _ = socket.write_all(head.as_bytes()).await;
+                    continue;
+                }
+                if request.starts_with("GET /unannounced ") {
+                    let head = "HTTP/1.1 200 OK\r\nConnection: close\r\n\r\n";
+                    let _ = socket.write_all(head.as_bytes()).await;
+                    let _ = socket.write_all(&vec![b'a'; MAX_BODY_BYTES + 1]).await;
+                    continue;
+                }
+                let body: &[u8] = if request.starts_with("GET / ") {
+                    PAGE.as_bytes()
+                } else {
+                    b"\x89PNG\x0D\x0A\x1A\x0A"
+                };
+                let head = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    body.len()
+                );
+                socket.write_all(head.as_bytes()).await.unwrap();
+                socket.write_all(body).await.unwrap();
+            }
+        });
+        format!("http://{}/", addr)
+    }
+
+    #[tokio::test]
+    async fn cards_are_read_from_the_opengraph_tags() {
+        let uri = serve().await;
+        let fetcher = HttpFetcher::new().unwrap();
+        let card = fetch_card(&fetcher, &uri).await.unwrap();
+        assert_eq!(
+            card,
+            Card {
+                uri: uri.clone(),
+                title: String::from("Rust & Neovim"),
+                description: String::from("A post client for the terminal"),
+                image: Some(format!("{}static/card.png", uri)),
+            }
+        );
+        let thumb = fetcher.fetch(&card.image.unwrap()).await.unwrap();
+        assert!(thumb.starts_with(b"\x89PNG"));
+    }
+
+    #[tokio::test]
+    async fn bodies_over_the_limit_are_rejected() {
+        let uri = serve().await;
+        let fetcher = HttpFetcher::new().unwrap();
+        let err = fetcher.fetch(&format!("{}large", uri)).await.unwrap_err();
+        assert!(err.to_string().ends_with(&format!(
+            "is {} bytes, at most {}",
+            MAX_BODY_BYTES + 1,
+            MAX_BODY_BYTES
+        )));
+        let err = fetcher
+            .fetch(&format!("{}unannounced", uri))
+            .await
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .ends_with(&format!("is over {} bytes", MAX_BODY_BYTES)));
+    }
+
+    #[test]
+    fn pages_without_opengraph_use_the_title() {
+        let html = r#"<title>
+  Plain page</title><meta name="description" content="Nothing &quot;social&quot;">"#;
+        let card = parse("https://example.com/a", html);
+        assert_eq!(card.title, "Plain page");
+        assert_eq!(card.description, "Nothing \"social\"");
+        assert_eq!(card.image, None);
+        assert_eq!(
+            parse("https://example.com/a", "").title,
+            "https://example.com/a"
+        );
+    }
+}
@@ -1,5 +1,7 @@
+use crate::filter::TimelineFilter;
 use atrium_api::types::string::AtIdentifier;
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 use std::str::FromStr;
 
@@ -18,11 +20,13 @@ pub enum Command {
     /// Get a list of feeds created by an actor.
     GetActorFeeds(ActorArgs),
     /// Get a view of a hydrated feed.
-    GetFeed(UriArgs),
+    GetFeed(FeedArgs),
     /// Get a view of a specified list,
     GetPosts(UriListArgs),
+    /// Get posts in a thread.
+    GetPostThread(UriArgsU16),
     /// Get a view of a specified list,
-    GetListFeed(UriArgs),
+    GetListFeed(FeedArgs),
     /// Get a list of who an actor follows.
     GetFollows(ActorArgs),
     /// Get a list of an actor's followers.
@@ -37,10 +41,53 @@ pub enum Command {
     GetProfile(ActorArgs),
     /// Get a list of notifications.
     ListNotifications(ListNotificationsArgs),
+    /// Store the notifications newer than the last synced ones in the local database.
+    SyncNotifications(SyncNotificationsArgs),
+    /// Read the notifications stored in the local database, likes and reposts grouped by post.
+    ReadNotifications(ReadNotificationsArgs),
+    /// Mark the notifications as seen.
+    MarkSeen(MarkSeenArgs),
     /// Create a new post.
     CreatePost(CreatePostArgs),
+    /// Create a thread from a text too long for a single post.
+    CreateThread(ThreadArgs),
+    /// Print the posts a thread would be split into without posting them.
+    PreviewThread(ThreadArgs),
     /// Delete a post.
     DeletePost(UriArgs),
+    /// Like a post.
+    Like(PostArgs),
+    /// Remove the like of a post.
+    Unlike(PostArgs),
+    /// Repost a post.
+    Repost(PostArgs),
+    /// Remove the repost of a post.
+    Unrepost(PostArgs),
+    /// Read a timeline from the local database.
+    ReadTimeline(ReadTimelineArgs),
+    /// Drop the posts older than a date from a local timeline.
+    PruneTimeline(PruneTimelineArgs),
+    /// Search the posts stored in the local database.
+    Search(SearchArgs),
+    /// Export or import a repository as a CAR file.
+    #[command(subcommand)]
+    Repo(RepoCommand),
+    /// List the records of a collection.
+    ListRecords(ListRecordsArgs),
+    /// Get a record of any collection.
+    GetRecord(GetRecordArgs),
+    /// Create or replace a record of any collection.
+    PutRecord(PutRecordArgs),
+    /// Delete a record of any collection.
+    DeleteRecord(RecordArgs),
+}
+
+#[derive(Subcommand, Debug)]
+pub enum RepoCommand {
+    /// Download the repository of an account, verify it and write it to a CAR file.
+    Export(RepoExportArgs),
+    /// Read the posts, likes and follows of a CAR file into the local database.
+    ImportLocal(RepoImportArgs),
 }
 
 #[derive(Parser, Debug)]
@@ -56,7 +103,7 @@ pub struct LoginArgs {
     pub password: Option<String>,
 }
 
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Serialize, Deserialize)]
 pub struct GetTimelineArgs {
     #[arg(long, default_value_t = String::from("reverse-chronological"))]
     pub algorithm: String,
@@ -66,7 +113,7 @@ pub struct GetTimelineArgs {
     pub limit: u8,
 }
 
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Serialize, Deserialize)]
 pub struct GetAuthorFeedArgs {
     /// Actor's handle or did
     #[arg(short, long, value_parser)]
@@ -78,9 +125,13 @@ pub struct GetAuthorFeedArgs {
     pub(crate) filter: Option<String>,
     #[arg(long, default_value_t = 10)]
     pub(crate) limit: u8,
+    /// Store the posts in the local database under the author:<actor> timeline
+    #[arg(long, default_value_t = false)]
+    #[serde(default)]
+    pub(crate) store: bool,
 }
 
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Serialize, Deserialize)]
 pub struct GetCidDidArgs {
     ///The CID of the blob to fetch
     #[arg(short, long, value_parser)]
@@ -90,7 +141,7 @@ pub struct GetCidDidArgs {
     pub(crate) did: atrium_api::types::string::Did,
 }
 
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Serialize, Deserialize)]
 pub struct GetCidUriArgs {
     /// Actor's handle or did
     /// atrium_api::types::string::Cid
@@ -104,7 +155,7 @@ pub struct GetCidUriArgs {
     pub(crate) uri: AtUri,
 }
 
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Serialize, Deserialize)]
 pub struct ActorArgs {
     #[arg(long)]
     pub cursor: Option<String>,
@@ -115,7 +166,7 @@ pub struct ActorArgs {
     pub actor: Option<AtIdentifier>,
 }
 
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Serialize, Deserialize)]
 pub struct UriArgs {
     #[arg(long)]
     pub(crate) cursor: Option<String>,
@@ -126,13 +177,24 @@ pub struct UriArgs {
     pub(crate) uri: AtUri,
 }
 
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Serialize, Deserialize)]
+pub struct FeedArgs {
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub(crate) uri: UriArgs,
+    /// Store the posts in the local database under the feed:<uri> or list:<uri> timeline
+    #[arg(long, default_value_t = false)]
+    #[serde(default)]
+    pub(crate) store: bool,
+}
+
+#[derive(Parser, Debug, Serialize, Deserialize)]
 pub struct UriListArgs {
     #[arg(short, long, value_parser)]
     pub(crate) uri: Vec<String>,
 }
 
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Serialize, Deserialize)]
 pub struct UriArgsU16 {
     #[arg(long, default_value_t = 10)]
     pub parent_height: u16,
@@ -141,9 +203,13 @@ pub struct UriArgsU16 {
     /// Record's URI
     #[arg(short, long, value_parser)]
     pub uri: AtUri,
+    /// Store the posts of the thread in the local database
+    #[arg(long, default_value_t = false)]
+    #[serde(default)]
+    pub store: bool,
 }
 
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Serialize, Deserialize)]
 pub struct ListNotificationsArgs {
     #[arg(long)]
     pub(crate) cursor: Option<String>,
@@ -152,17 +218,133 @@ pub struct ListNotificationsArgs {
     /// Record's URI
     // TODO: CHECK the seen_at since it is a string format datetime
     #[arg(short, long, value_parser)]
-    pub(crate) seen_at: atrium_api::types::string::Datetime,
+    pub(crate) seen_at: Option<atrium_api::types::string::Datetime>,
+    /// Store the notifications in the local database
+    #[arg(long, default_value_t = false)]
+    #[serde(default)]
+    pub(crate) store: bool,
+}
+
+#[derive(Parser, Debug, Serialize, Deserialize)]
+pub struct SyncNotificationsArgs {
+    /// Maximum number of pages fetched
+    #[arg(long, default_value_t = 10)]
+    pub(crate) pages: usize,
+}
+
+#[derive(Parser, Debug, Serialize, Deserialize)]
+pub struct ReadNotificationsArgs {
+    #[arg(long, default_value_t = 50)]
+    pub(crate) limit: i32,
+    /// Only the notifications not seen yet
+    #[arg(long, default_value_t = false)]
+    #[serde(default)]
+    pub(crate) unread: bool,
+}
+
+#[derive(Parser, Debug, Serialize, Deserialize)]
+pub struct MarkSeenArgs {
+    /// Mark the notifications up to this date as seen, defaults to now
+    #[arg(short, long, value_parser)]
+    pub(crate) seen_at: Option<atrium_api::types::string::Datetime>,
+}
+
+#[derive(Parser, Debug, Serialize, Deserialize)]
+pub struct ReadTimelineArgs {
+    /// Timeline name
+    #[arg(long, default_value_t = String::from("default"))]
+    pub timeline: String,
+    #[arg(long)]
+    pub limit: Option<i32>,
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub filter: TimelineFilter,
+}
+
+#[derive(Parser, Debug, Serialize, Deserialize)]
+pub struct PruneTimelineArgs {
+    /// Timeline name
+    #[arg(long, default_value_t = String::from("default"))]
+    pub timeline: String,
+    /// RFC3339 date, posts created before it are removed from the timeline
+    #[arg(long)]
+    pub older_than: String,
+}
+
+#[derive(Parser, Debug, Serialize, Deserialize)]
+pub struct SearchArgs {
+    /// Words to look for in the post text and the author name
+    pub query: String,
+    #[arg(long, default_value_t = 20)]
+    pub limit: i32,
 }
 
 #[derive(Parser, Debug)]
 pub struct CreatePostArgs {
     /// Post text
     #[arg(short, long)]
-    pub(crate) text: String,
+    pub text: String,
     /// Images to embed
     #[arg(short, long)]
     pub(crate) images: Vec<PathBuf>,
+    /// Alt text of the images, in the order of the images
+    #[arg(short, long)]
+    pub(crate) alt: Vec<String>,
+    /// at-URI of a post to quote
+    #[arg(short, long)]
+    pub(crate) quote: Option<String>,
+    /// CID of a post stored in the local database or at-URI of the post to reply to
+    #[arg(short, long)]
+    pub reply_to: Option<String>,
+    /// Read the text as Markdown, [text](url) becomes a link on the text
+    #[arg(long, default_value_t = false)]
+    pub markdown: bool,
+    /// Downscale and re-encode images over the size limit instead of failing
+    #[arg(long, default_value_t = false)]
+    pub(crate) resize: bool,
+    /// Do not attach a card for the first link of the post
+    #[arg(long, default_value_t = false)]
+    pub(crate) no_card: bool,
+    /// Language of the post, e.g. en or pt-BR, repeat it for several languages
+    #[arg(short, long = "lang")]
+    pub(crate) langs: Vec<String>,
+    /// Detect the language of the post when none is given
+    #[arg(long, default_value_t = false)]
+    pub(crate) detect_lang: bool,
+}
+
+impl CreatePostArgs {
+    // A text only post written in Markdown, as composed in the editor
+    pub fn markdown(text: String) -> Self {
+        CreatePostArgs {
+            text,
+            images: vec![],
+            alt: vec![],
+            quote: None,
+            reply_to: None,
+            markdown: true,
+            resize: false,
+            no_card: false,
+            langs: vec![],
+            detect_lang: false,
+        }
+    }
+}
+
+#[derive(Parser, Debug)]
+pub struct ThreadArgs {
+    #[command(flatten)]
+    pub post: CreatePostArgs,
+    /// End each post with its position in the thread, e.g. 1/3
+    #[arg(short, long, default_value_t = false)]
+    pub numbered: bool,
+}
+
+#[derive(Parser, Debug, Serialize, Deserialize)]
+pub struct PostArgs {
+    /// CID of a post stored in the local database or at-URI of any post
+    #[arg(short, long)]
+    pub post: String,
 }
 
 #[derive(Debug, Clone)]
@@ -175,22 +357,148 @@ pub struct AtUri {
 impl FromStr for AtUri {
     type Err = String;
 
+    // The rkey is empty for the uri of a collection, at://<did>/<collection>
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let parts = s
-            .strip_prefix("at://did:plc:")
-            .ok_or(r#"record uri must start with "at://did:plc:""#)?
-            .splitn(3, '/')
-            .collect::<Vec<_>>();
+        let mut parts = s
+            .strip_prefix("at://")
+            .ok_or(r#"record uri must start with "at://""#)?
+            .splitn(3, '/');
+        let did = parts
+            .next()
+            .filter(|did| did.starts_with("did:"))
+            .ok_or("record uri must start with a did")?;
+        let collection = parts
+            .next()
+            .filter(|collection| !collection.is_empty())
+            .ok_or("record uri must name a collection")?;
         Ok(Self {
-            did: format!("did:plc:{}", parts[0]),
-            collection: parts[1].to_string(),
-            rkey: parts[2].to_string(),
+            did: did.to_string(),
+            collection: collection.to_string(),
+            rkey: parts.next().unwrap_or_default().to_string(),
         })
     }
 }
 
+impl AtUri {
+    // The rkey of the record the uri names, a collection uri names no record
+    pub(crate) fn record_key(&self) -> Result<String, anyhow::Error> {
+        if self.rkey.is_empty() {
+            anyhow::bail!("{} names a collection, not a record", self);
+        }
+        Ok(self.rkey.clone())
+    }
+}
+
 impl std::fmt::Display for AtUri {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.rkey.is_empty() {
+            return write!(f, "at://{}/{}", self.did, self.collection);
+        }
         write!(f, "at://{}/{}/{}", self.did, self.collection, self.rkey)
     }
 }
+
+impl Serialize for AtUri {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for AtUri {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        AtUri::from_str(&s).map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Parser, Debug, Serialize, Deserialize)]
+pub struct ListRecordsArgs {
+    /// Collection's URI, at://<did>/<collection>
+    #[arg(short, long, value_parser)]
+    pub(crate) uri: AtUri,
+    #[arg(long)]
+    pub(crate) cursor: Option<String>,
+    #[arg(long, default_value_t = 50)]
+    pub(crate) limit: u8,
+    /// List from the oldest record
+    #[arg(long, default_value_t = false)]
+    #[serde(default)]
+    pub(crate) reverse: bool,
+}
+
+#[derive(Parser, Debug, Serialize, Deserialize)]
+pub struct GetRecordArgs {
+    /// Record's URI
+    #[arg(short, long, value_parser)]
+    pub(crate) uri: AtUri,
+    /// Version of the record to get, the latest when it is not set
+    #[arg(short, long, value_parser)]
+    pub(crate) cid: Option<atrium_api::types::string::Cid>,
+}
+
+#[derive(Parser, Debug, Serialize, Deserialize)]
+pub struct RecordArgs {
+    /// Record's URI
+    #[arg(short, long, value_parser)]
+    pub(crate) uri: AtUri,
+    /// Only write if the record is still at this CID
+    #[arg(long, value_parser)]
+    pub(crate) swap_record: Option<atrium_api::types::string::Cid>,
+    /// Only write if the repository is still at this commit CID
+    #[arg(long, value_parser)]
+    pub(crate) swap_commit: Option<atrium_api::types::string::Cid>,
+}
+
+#[derive(Parser, Debug, Serialize, Deserialize)]
+pub struct PutRecordArgs {
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub(crate) target: RecordArgs,
+    /// Record as JSON, its $type must be the collection of the URI
+    #[arg(short, long)]
+    pub(crate) record: String,
+}
+
+#[derive(Parser, Debug)]
+pub struct RepoExportArgs {
+    /// Actor's handle or did, defaults to the logged in account
+    #[arg(short, long, value_parser)]
+    pub actor: Option<AtIdentifier>,
+    /// Path of the CAR file to write
+    #[arg(short, long)]
+    pub output: PathBuf,
+}
+
+#[derive(Parser, Debug, Serialize, Deserialize)]
+pub struct RepoImportArgs {
+    /// Path of the CAR file to read
+    pub path: PathBuf,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn at_uris_name_a_record_or_a_collection() {
+        let uri = AtUri::from_str("at://did:web:example.com/app.bsky.actor.profile/self").unwrap();
+        assert_eq!(
+            (uri.did.as_str(), uri.collection.as_str(), uri.rkey.as_str()),
+            ("did:web:example.com", "app.bsky.actor.profile", "self")
+        );
+        assert_eq!(uri.record_key().unwrap(), "self");
+        let uri = AtUri::from_str("at://did:plc:abc/app.bsky.graph.list").unwrap();
+        assert_eq!(uri.rkey, "");
+        assert_eq!(uri.to_string(), "at://did:plc:abc/app.bsky.graph.list");
+        assert!(uri.record_key().is_err());
+        assert!(AtUri::from_str("at://did:plc:abc").is_err());
+        assert!(AtUri::from_str("at://alice.bsky.social/app.bsky.feed.post/3k").is_err());
+        assert!(AtUri::from_str("https://bsky.app").is_err());
+    }
+}
@@ -0,0 +1,282 @@
+use regex::Regex;
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::richtext::Span;
+
+// Longest post text accepted by the server, counted in graphemes
+pub const MAX_GRAPHEMES: usize = 300;
+
+const PARAGRAPH: &str = r"\n[ \t]*\n\s*";
+const SENTENCE_END: &str = r#"[.!?…]+["')\]]*(\s+)"#;
+
+pub fn graphemes(text: &str) -> usize {
+    text.graphemes(true).count()
+}
+
+// A piece of the text to post, start is its byte offset in the text and the
+// separator is what comes before it once the pieces are joined again
+struct Part {
+    separator: String,
+    start: usize,
+    text: String,
+}
+
+// A post of the thread, parts holds the (start, end) range of each of its parts
+// in the text along with the offset of the part in the post
+#[derive(Default)]
+struct Segment {
+    text: String,
+    parts: Vec<(usize, usize, usize)>,
+}
+
+impl Segment {
+    fn push(&mut self, separator: &str, part: Part) {
+        self.text.push_str(separator);
+        let offset = self.text.len();
+        self.text.push_str(&part.text);
+        self.parts
+            .push((part.start, part.start + part.text.len(), offset));
+    }
+
+    // Moves the spans of the text to the post, a span cut by the split is kept
+    // on both sides and the spans of the other posts are dropped
+    fn spans(&self, spans: &[Span]) -> Vec<Span> {
+        let mut moved = Vec::new();
+        for span in spans {
+            let mut range: Option<(usize, usize)> = None;
+            for &(start, end, offset) in &self.parts {
+                let (from, to) = (span.start.max(start), span.end.min(end));
+                if from < to {
+                    let (from, to) = (offset + from - start, offset + to - start);
+                    range = Some(range.map_or((from, to), |(first, _)| (first, to)));
+                }
+            }
+            if let Some((start, end)) = range {
+                moved.push(Span {
+                    start,
+                    end,
+                    kind: span.kind.clone(),
+                });
+            }
+        }
+        moved
+    }
+}
+
+// Splits text into pieces along with the separator that came before each of
+// them, paragraphs first, then sentences
+fn pieces(text: &str) -> Vec<Part> {
+    let paragraph = Regex::new(PARAGRAPH).expect("valid paragraph regex");
+    let sentence_end = Regex::new(SENTENCE_END).expect("valid sentence regex");
+    let trimmed = text.trim();
+    let base = text.len() - text.trim_start().len();
+    let mut paragraphs = Vec::new();
+    let mut last = 0;
+    for found in paragraph.find_iter(trimmed) {
+        paragraphs.push((base + last, &trimmed[last..found.start()]));
+        last = found.end();
+    }
+    paragraphs.push((base + last, &trimmed[last..]));
+    let mut pieces = Vec::new();
+    for (start, para) in paragraphs {
+        let mut separator = if pieces.is_empty() { "" } else { "\n\n" };
+        let mut last = 0;
+        for captures in sentence_end.captures_iter(para) {
+            let space = captures.get(1).expect("space group");
+            pieces.push(Part {
+                separator: separator.to_string(),
+                start: start + last,
+                text: para[last..space.start()].to_string(),
+            });
+            separator = space.as_str();
+            last = space.end();
+        }
+        if last < para.len() {
+            pieces.push(Part {
+                separator: separator.to_string(),
+                start: start + last,
+                text: para[last..].to_string(),
+            });
+        }
+    }
+    pieces
+}
+
+// Breaks a piece that does not fit in a post on words, and words that do not
+// fit on graphemes
+fn break_piece(piece: Part, budget: usize) -> Vec<Part> {
+    let word = Regex::new(r"\S+").expect("valid word regex");
+    let mut broken = Vec::new();
+    let mut separator = piece.separator;
+    for found in word.find_iter(&piece.text) {
+        let start = piece.start + found.start();
+        if graphemes(found.as_str()) <= budget {
+            broken.push(Part {
+                separator,
+                start,
+                text: found.as_str().to_string(),
+            });
+        } else {
+            let chars: Vec<(usize, &str)> = found.as_str().grapheme_indices(true).collect();
+            for chunk in chars.chunks(budget) {
+                broken.push(Part {
+                    separator,
+                    start: start + chunk[0].0,
+                    text: chunk.iter().map(|(_, g)| *g).collect(),
+                });
+                separator = String::new();
+            }
+        }
+        separator = String::from(" ");
+    }
+    broken
+}
+
+fn pack(text: &str, budget: usize) -> Vec<Segment> {
+    let mut segments = Vec::new();
+    let mut current = Segment::default();
+    for piece in pieces(text) {
+        let parts = if graphemes(&piece.text) <= budget {
+            vec![piece]
+        } else {
+            break_piece(piece, budget)
+        };
+        for part in parts {
+            let candidate = format!("{}{}{}", current.text, part.separator, part.text);
+            if current.text.is_empty() {
+                current.push("", part);
+            } else if graphemes(&candidate) <= budget {
+                let separator = part.separator.clone();
+                current.push(&separator, part);
+            } else {
+                segments.push(std::mem::take(&mut current));
+                current.push("", part);
+            }
+        }
+    }
+    if !current.text.is_empty() {
+        segments.push(current);
+    }
+    segments
+}
+
+// Splits text into the posts of a thread, at paragraph then sentence boundaries
+// and only within a sentence when it is too long for a single post. Numbered
+// posts end with " i/n", the numbering is left out when the text fits in one post.
+pub fn split(text: &str, numbered: bool) -> Vec<String> {
+    split_spans(text, &[], numbered)
+        .into_iter()
+        .map(|(text, _)| text)
+        .collect()
+}
+
+// Splits text as split does and returns each post with the spans of text that
+// fall in it, moved to the offsets of the post
+pub fn split_spans(text: &str, spans: &[Span], numbered: bool) -> Vec<(String, Vec<Span>)> {
+    let mut segments = pack(text, MAX_GRAPHEMES);
+    if numbered && segments.len() > 1 {
+        // The suffix of the last post is the longest, grow it until the count is stable
+        let mut count = segments.len();
+        loop {
+            let suffix = graphemes(&format!(" {}/{}", count, count));
+            segments = pack(text, MAX_GRAPHEMES - suffix);
+            if segments.len() == count {
+                break;
+            }
+            count = segments.len();
+        }
+        for (i, segment) in segments.iter_mut().enumerate() {
+            segment.text = format!("{} {}/{}", segment.text, i + 1, count);
+        }
+    }
+    segments
+        .iter()
+        .map(|segment| (segment.text.clone(), segment.spans(spans)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::richtext::SpanKind;
+
+    #[test]
+    fn short_text_is_a_single_post() {
+        assert_eq!(split("Hello 🦀 world.", true), vec!["Hello 🦀 world."]);
+    }
+
+    #[test]
+    fn long_text_splits_on_sentences_and_paragraphs() {
+        let sentence = "Ceci est une phrase assez longue qui parle de Rust et de Neovim. ";
+        let text = format!("{}\n\nSecond paragraph.", sentence.repeat(8).trim());
+        let segments = split(&text, true);
+        let count = segments.len();
+        assert!(count > 1);
+        for (i, segment) in segments.iter().enumerate() {
+            assert!(graphemes(segment) <= MAX_GRAPHEMES, "{}", segment);
+            assert!(segment.ends_with(&format!(" {}/{}", i + 1, count)));
+            assert!(segment.starts_with("Ceci est"), "{}", segment);
+        }
+        // The paragraph break is kept between the sentences of a post
+        assert!(segments[count - 1]
+            .ends_with(&format!("Neovim.\n\nSecond paragraph. {}/{}", count, count)));
+    }
+
+    #[test]
+    fn words_longer_than_a_post_are_cut_on_graphemes() {
+        let word = "👩‍👩‍👧".repeat(MAX_GRAPHEMES + 10);
+        let segments = split(&word, false);
+        assert_eq!(segments.len(), 2);
+        assert_eq!(graphemes(&segments[0]), MAX_GRAPHEMES);
+        assert_eq!(graphemes(&segments[1]), 10);
+    }
+
+    #[test]
+    fn spans_follow_their_text_into_the_posts() {
+        let sentence = "Ceci est une phrase assez longue qui parle de Rust et de Neovim. ";
+        let input = format!(
+            "Ask @alice.test about it. {}\n\nSee [the docs](https://example.com).",
+            sentence.repeat(8).trim()
+        );
+        let (text, spans) = crate::markdown::to_post(&input).unwrap();
+        let posts = split_spans(&text, &spans, true);
+        assert!(posts.len() > 1);
+        let slices: Vec<(&str, &SpanKind)> = posts
+            .iter()
+            .flat_map(|(text, spans)| {
+                spans
+                    .iter()
+                    .map(move |span| (&text[span.start..span.end], &span.kind))
+            })
+            .collect();
+        assert_eq!(
+            slices,
+            vec![
+                (
+                    "@alice.test",
+                    &SpanKind::Mention(String::from("alice.test"))
+                ),
+                (
+                    "the docs",
+                    &SpanKind::Link(String::from("https://example.com"))
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn spans_cut_by_the_split_are_kept_on_both_sides() {
+        let text = "word ".repeat(100);
+        let spans = vec![Span {
+            start: 0,
+            end: text.trim_end().len(),
+            kind: SpanKind::Link(String::from("https://example.com")),
+        }];
+        let posts = split_spans(&text, &spans, false);
+        assert_eq!(posts.len(), 2);
+        for (text, spans) in &posts {
+            assert_eq!(spans.len(), 1);
+            assert_eq!(&text[spans[0].start..spans[0].end], text.as_str());
+        }
+    }
+}
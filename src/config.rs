@@ -0,0 +1,86 @@
+use std::path::Path;
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use tokio::fs::File;
+use tokio::io::AsyncReadExt;
+
+// Settings read from ~/.config/bsky/config.json, every setting is optional
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct Config {
+    // Languages of the posts that do not name any, e.g. ["en", "fr"]
+    pub langs: Vec<String>,
+    // Detect the language of the posts that do not name any
+    pub detect_lang: bool,
+    // Desktop and neovim alerts for new notifications
+    pub notify: NotifyConfig,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct NotifyConfig {
+    // Program and arguments run for each alert, the title and the body are
+    // appended, e.g. ["notify-send", "-a", "bsky"]
+    pub command: Vec<String>,
+    // Show the alerts with nvim_notify in neovim
+    pub nvim: bool,
+    // Reasons of the notifications that raise an alert
+    pub reasons: Vec<String>,
+}
+
+impl Default for NotifyConfig {
+    fn default() -> Self {
+        NotifyConfig {
+            command: vec![],
+            nvim: false,
+            reasons: vec![
+                String::from("mention"),
+                String::from("reply"),
+                String::from("quote"),
+            ],
+        }
+    }
+}
+
+impl Config {
+    // Returns the default settings when the file does not exist
+    pub async fn load(path: &Path) -> Result<Self, anyhow::Error> {
+        let mut file = match File::open(path).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut buffer = Vec::new();
+        file.read_to_end(&mut buffer).await?;
+        serde_json::from_slice(&buffer).with_context(|| format!("Invalid config: {:?}", path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn missing_settings_take_their_default() {
+        let dir = std::env::temp_dir().join(format!("rbsky-config-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.json");
+        assert_eq!(Config::load(&path).await.unwrap(), Config::default());
+
+        std::fs::write(&path, r#"{"langs": ["fr", "en"]}"#).unwrap();
+        let config = Config::load(&path).await.unwrap();
+        assert_eq!(config.langs, vec!["fr", "en"]);
+        assert!(!config.detect_lang);
+        assert_eq!(config.notify.reasons, vec!["mention", "reply", "quote"]);
+
+        std::fs::write(&path, r#"{"notify": {"nvim": true, "reasons": ["reply"]}}"#).unwrap();
+        let config = Config::load(&path).await.unwrap();
+        assert!(config.notify.nvim && config.notify.command.is_empty());
+        assert_eq!(config.notify.reasons, vec!["reply"]);
+
+        std::fs::write(&path, r#"{"langs": "fr"}"#).unwrap();
+        assert!(Config::load(&path).await.is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
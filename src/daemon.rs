@@ -0,0 +1,303 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::Context;
+use futures::lock::Mutex;
+use log::{error, info, trace, warn};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::time::{self, Duration};
+
+use crate::backfill::{fill_gap, BackfillOptions};
+use crate::ipc::{
+    Method, Request, Response, INVALID_REQUEST, JSONRPC_VERSION, METHOD_FAILED, PROTOCOL_VERSION,
+    UNSUPPORTED_VERSION,
+};
+use crate::notifications::sync_notifications;
+use crate::notifier::{command_notifiers, notify_new, Notifier};
+use crate::runner::Runner;
+use crate::subscription::Subscription;
+use crate::surreal::SurrealDB;
+
+pub fn socket_path() -> Result<PathBuf, anyhow::Error> {
+    let config_dir =
+        dirs::config_dir().with_context(|| format!("No config dir: {:?}", dirs::config_dir()))?;
+    Ok(config_dir.join("bsky").join("rbsky.sock"))
+}
+
+// The daemon owns the only handle on the RocksDB database and the authenticated
+// session, frontends talk to it over a unix socket instead of opening their own.
+#[derive(Clone)]
+pub struct Daemon {
+    pub db: Arc<Mutex<SurrealDB>>,
+    pub runner: Arc<Runner>,
+    pub unread_count: Arc<Mutex<i64>>,
+    // Feeds, lists and authors kept in sync next to the home timeline
+    pub subscriptions: Vec<Subscription>,
+    pub backfill: BackfillOptions,
+}
+
+impl Daemon {
+    pub fn new(db: SurrealDB, runner: Runner) -> Self {
+        Daemon {
+            db: Arc::new(Mutex::new(db)),
+            runner: Arc::new(runner),
+            unread_count: Arc::new(Mutex::new(0)),
+            subscriptions: vec![],
+            backfill: BackfillOptions::default(),
+        }
+    }
+
+    // This function updates the timeline in the db
+    pub async fn update_timeline(&self) -> Result<(), anyhow::Error> {
+        Method::Refresh
+            .execute(&self.runner, Some(self.db.clone()))
+            .await?;
+        for subscription in &self.subscriptions {
+            match fill_gap(&self.db, &self.runner, subscription, &self.backfill).await {
+                Ok(pages) => info!("{} synced with {} pages", subscription, pages),
+                Err(e) => error!("error while syncing {}: {:?}", subscription, e),
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn update_unread_count(&self) -> Result<(), anyhow::Error> {
+        let unread = self.runner._get_unread_count().await?;
+        info!("unread notifications: {}", unread.count);
+        let mut count_lock = self.unread_count.lock().await;
+        *count_lock = unread.count;
+        Ok(())
+    }
+
+    pub async fn auto_refresh_timeline(&self, task_interval: Duration) {
+        let mut interval = time::interval(task_interval);
+        loop {
+            interval.tick().await;
+            trace!("executed timeline background task");
+            if let Err(e) = self.update_timeline().await {
+                error!("error while refreshing the timeline {:?}", e);
+            }
+        }
+    }
+
+    // Stores the new notifications and runs the notify command for the new
+    // ones, the unread count still comes from the server
+    pub async fn update_notifications(&self) -> Result<(), anyhow::Error> {
+        let notify = &self.runner.config().notify;
+        let mut notifiers = command_notifiers(notify);
+        let mut notifiers: Vec<&mut dyn Notifier> = notifiers
+            .iter_mut()
+            .map(|notifier| notifier.as_mut() as &mut dyn Notifier)
+            .collect();
+        let stored = sync_notifications(&self.db, &self.runner, 10).await?;
+        notify_new(&self.db, &mut notifiers, &notify.reasons).await?;
+        trace!("stored {} notifications", stored);
+        Ok(())
+    }
+
+    pub async fn auto_refresh_notifications(&self, task_interval: Duration) {
+        let mut interval = time::interval(task_interval);
+        loop {
+            interval.tick().await;
+            trace!("executed notification background task");
+            if let Err(e) = self.update_notifications().await {
+                error!("error while syncing notifications {:?}", e);
+            }
+            if let Err(e) = self.update_unread_count().await {
+                error!("error while refreshing notifications {:?}", e);
+            }
+        }
+    }
+
+    pub async fn handle_request(&self, request: Request) -> Response {
+        trace!("daemon received request: {:?}", request);
+        if request.jsonrpc != JSONRPC_VERSION {
+            return Response::error(
+                Some(request.id),
+                INVALID_REQUEST,
+                format!("unsupported jsonrpc version {:?}", request.jsonrpc),
+            );
+        }
+        if request.version != PROTOCOL_VERSION {
+            return Response::error(
+                Some(request.id),
+                UNSUPPORTED_VERSION,
+                format!(
+                    "unsupported protocol version {}, daemon speaks {}",
+                    request.version, PROTOCOL_VERSION
+                ),
+            );
+        }
+        let res = match request.method {
+            Method::GetUnreadCount => Ok(serde_json::json!({
+                "count": *self.unread_count.lock().await
+            })),
+            Method::MarkSeen(args) => {
+                let res = Method::MarkSeen(args)
+                    .execute(&self.runner, Some(self.db.clone()))
+                    .await;
+                if res.is_ok() {
+                    *self.unread_count.lock().await = 0;
+                }
+                res
+            }
+            method => method.execute(&self.runner, Some(self.db.clone())).await,
+        };
+        match res {
+            Ok(value) => Response::result(request.id, value),
+            Err(e) => Response::error(Some(request.id), METHOD_FAILED, e.to_string()),
+        }
+    }
+
+    // One json request per line, one json response per line
+    async fn handle_connection(&self, stream: UnixStream) -> Result<(), anyhow::Error> {
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        while let Some(line) = lines.next_line().await? {
+            let response = match serde_json::from_str::<Request>(&line) {
+                Ok(request) => self.handle_request(request).await,
+                Err(e) => {
+                    let id = serde_json::from_str::<serde_json::Value>(&line)
+                        .ok()
+                        .and_then(|v| v.get("id").and_then(|id| id.as_u64()));
+                    Response::error(id, INVALID_REQUEST, format!("invalid request: {e}"))
+                }
+            };
+            let mut buffer = serde_json::to_vec(&response)?;
+            buffer.push(b'\n');
+            writer.write_all(&buffer).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn serve(&self, path: PathBuf) -> Result<(), anyhow::Error> {
+        if path.exists() {
+            if UnixStream::connect(&path).await.is_ok() {
+                return Err(anyhow::Error::msg(format!(
+                    "a daemon is already listening on {:?}",
+                    path
+                )));
+            }
+            warn!("removing stale socket {:?}", path);
+            std::fs::remove_file(&path)?;
+        }
+        let listener = UnixListener::bind(&path)?;
+        info!("daemon listening on {:?}", path);
+        loop {
+            let (stream, _addr) = listener.accept().await?;
+            let daemon = self.clone();
+            tokio::spawn(async move {
+                if let Err(e) = daemon.handle_connection(stream).await {
+                    error!("error while handling connection {:?}", e);
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::ReadTimelineArgs;
+    use crate::filter::TimelineFilter;
+    use crate::fixtures::{feed_view_post, CREATED_AT};
+    use crate::ipc::Backend;
+    use crate::subscription::FeedPage;
+
+    // Writes one line and reads the response line
+    async fn exchange(stream: &mut BufReader<UnixStream>, line: &str) -> Response {
+        let mut buffer = line.as_bytes().to_vec();
+        buffer.push(b'\n');
+        stream.get_mut().write_all(&buffer).await.unwrap();
+        let mut response = String::new();
+        stream.read_line(&mut response).await.unwrap();
+        serde_json::from_str(&response).unwrap()
+    }
+
+    fn request(jsonrpc: &str, version: u32, id: u64) -> String {
+        serde_json::to_string(&Request {
+            jsonrpc: jsonrpc.to_string(),
+            version,
+            id,
+            method: Method::GetUnreadCount,
+        })
+        .unwrap()
+    }
+
+    fn read_timeline() -> Method {
+        Method::ReadTimeline(ReadTimelineArgs {
+            timeline: String::from("default"),
+            limit: None,
+            filter: TimelineFilter::default(),
+        })
+    }
+
+    #[tokio::test]
+    async fn requests_round_trip_over_the_socket() -> Result<(), anyhow::Error> {
+        let dir = std::env::temp_dir().join(format!("rbsky-daemon-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let db = SurrealDB::open(dir.join("bsky.db")).await?;
+        let feed_page = FeedPage {
+            feed: vec![feed_view_post("3kabc", "hello", CREATED_AT)],
+            cursor: None,
+        };
+        db.store_feed_page(feed_page, String::from("default"), None)
+            .await?;
+        // Nothing listens on the port, none of the methods below reach the server
+        let pds_host = String::from("http://127.0.0.1:9");
+        let runner = Runner::open(pds_host.clone(), false, dir.clone()).await?;
+        let daemon = Daemon::new(db, runner);
+        let socket = dir.join("rbsky.sock");
+        assert!(Backend::daemon(&socket).await.is_none());
+
+        let server = {
+            let daemon = daemon.clone();
+            let socket = socket.clone();
+            tokio::spawn(async move { daemon.serve(socket).await })
+        };
+        let stream = loop {
+            match UnixStream::connect(&socket).await {
+                Ok(stream) => break stream,
+                Err(_) => time::sleep(Duration::from_millis(10)).await,
+            }
+        };
+        let mut stream = BufReader::new(stream);
+
+        let response = exchange(&mut stream, &request(JSONRPC_VERSION, PROTOCOL_VERSION, 1)).await;
+        assert_eq!(response.id, Some(1));
+        assert_eq!(response.result, Some(serde_json::json!({ "count": 0 })));
+
+        let response = exchange(&mut stream, &request(JSONRPC_VERSION, 99, 2)).await;
+        assert_eq!(response.id, Some(2));
+        assert_eq!(response.error.map(|e| e.code), Some(UNSUPPORTED_VERSION));
+
+        let response = exchange(&mut stream, &request("1.0", PROTOCOL_VERSION, 3)).await;
+        assert_eq!(response.id, Some(3));
+        assert_eq!(response.error.map(|e| e.code), Some(INVALID_REQUEST));
+
+        let unknown = r#"{"jsonrpc": "2.0", "version": 1, "id": 4, "method": "no_such_method"}"#;
+        let response = exchange(&mut stream, unknown).await;
+        assert_eq!(response.id, Some(4));
+        assert_eq!(response.error.map(|e| e.code), Some(INVALID_REQUEST));
+
+        let response = exchange(&mut stream, "not json").await;
+        assert_eq!(response.id, None);
+        assert_eq!(response.error.map(|e| e.code), Some(INVALID_REQUEST));
+
+        // The daemon and the direct mode it stands in for give the same answer
+        let mut backend = Backend::daemon(&socket).await.expect("a running daemon");
+        let from_daemon = backend.call_value(read_timeline()).await?;
+        let mut direct = Backend::Direct {
+            runner: Runner::open(pds_host, false, dir.clone()).await?,
+            db: Some(daemon.db.clone()),
+        };
+        let from_direct = direct.call_value(read_timeline()).await?;
+        assert_eq!(from_daemon, from_direct);
+        assert_eq!(from_daemon.as_array().map(|feed| feed.len()), Some(1));
+
+        server.abort();
+        std::fs::remove_dir_all(dir).ok();
+        Ok(())
+    }
+}
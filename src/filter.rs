@@ -0,0 +1,359 @@
+use atrium_api::app::bsky::feed::defs::PostView;
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::sql::Bindings;
+
+const MEDIA_EMBEDS: [&str; 2] = [
+    "app.bsky.embed.images#view",
+    "app.bsky.embed.recordWithMedia#view",
+];
+const REASON_REPOST: &str = "app.bsky.feed.defs#reasonRepost";
+
+// Conditions on the posts of a timeline, compiled to a WHERE clause that only
+// references bound parameters so the values never end up in the query text.
+// Every condition that is set must match, e.g. posts with images from a list of
+// authors in the last day:
+//
+//     TimelineFilter::new()
+//         .authors(follows)
+//         .has_media(true)
+//         .created_since(yesterday)
+#[derive(Parser, Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct TimelineFilter {
+    /// Only posts from these authors, DIDs or handles
+    #[arg(long = "author")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub authors: Vec<String>,
+    /// Only posts with (true) or without (false) images
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub has_media: Option<bool>,
+    /// Only replies (true) or only top level posts (false)
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub is_reply: Option<bool>,
+    /// Only reposts (true) or only original posts (false)
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub is_repost: Option<bool>,
+    /// Only posts tagged with one of these languages
+    #[arg(long = "language")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub languages: Vec<String>,
+    /// Only posts carrying one of these labels
+    #[arg(long = "label")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub labels: Vec<String>,
+    /// Only posts created strictly before this RFC3339 date
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_before: Option<String>,
+    /// Only posts created at or after this RFC3339 date
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_since: Option<String>,
+    /// Only posts whose text contains this string, case insensitive
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text_contains: Option<String>,
+    /// Only posts with at least this many likes
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_likes: Option<i64>,
+}
+
+impl TimelineFilter {
+    pub fn new() -> Self {
+        TimelineFilter::default()
+    }
+
+    pub fn authors<I, S>(mut self, authors: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.authors.extend(authors.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn has_media(mut self, has_media: bool) -> Self {
+        self.has_media = Some(has_media);
+        self
+    }
+
+    pub fn is_reply(mut self, is_reply: bool) -> Self {
+        self.is_reply = Some(is_reply);
+        self
+    }
+
+    pub fn is_repost(mut self, is_repost: bool) -> Self {
+        self.is_repost = Some(is_repost);
+        self
+    }
+
+    pub fn language(mut self, language: impl Into<String>) -> Self {
+        self.languages.push(language.into());
+        self
+    }
+
+    pub fn label(mut self, label: impl Into<String>) -> Self {
+        self.labels.push(label.into());
+        self
+    }
+
+    pub fn created_before(mut self, created_at: impl Into<String>) -> Self {
+        self.created_before = Some(created_at.into());
+        self
+    }
+
+    pub fn created_since(mut self, created_at: impl Into<String>) -> Self {
+        self.created_since = Some(created_at.into());
+        self
+    }
+
+    pub fn text_contains(mut self, text: impl Into<String>) -> Self {
+        self.text_contains = Some(text.into());
+        self
+    }
+
+    pub fn min_likes(mut self, min_likes: i64) -> Self {
+        self.min_likes = Some(min_likes);
+        self
+    }
+
+    // Returns the conditions joined with AND, None when the filter matches everything
+    pub fn to_sql(&self) -> Option<String> {
+        let mut conditions = Vec::new();
+        if !self.authors.is_empty() {
+            conditions.push(
+                "(post.author.did IN $filter_authors OR post.author.handle IN $filter_authors)",
+            );
+        }
+        match self.has_media {
+            Some(true) => conditions.push("post.embed.⟨$type⟩ IN $filter_media_embeds"),
+            Some(false) => conditions.push("post.embed.⟨$type⟩ NOT IN $filter_media_embeds"),
+            None => {}
+        }
+        match self.is_reply {
+            Some(true) => conditions.push("post.record.reply != NONE"),
+            Some(false) => conditions.push("post.record.reply == NONE"),
+            None => {}
+        }
+        match self.is_repost {
+            Some(true) => conditions.push("reason.⟨$type⟩ == $filter_reason_repost"),
+            Some(false) => conditions.push("reason.⟨$type⟩ != $filter_reason_repost"),
+            None => {}
+        }
+        if !self.languages.is_empty() {
+            conditions.push("post.record.langs CONTAINSANY $filter_languages");
+        }
+        if !self.labels.is_empty() {
+            conditions.push("post.labels.val CONTAINSANY $filter_labels");
+        }
+        if self.created_before.is_some() {
+            conditions.push("post.record.createdAt < $filter_created_before");
+        }
+        if self.created_since.is_some() {
+            conditions.push("post.record.createdAt >= $filter_created_since");
+        }
+        if self.text_contains.is_some() {
+            conditions.push("string::lowercase(post.record.text) CONTAINS $filter_text");
+        }
+        if self.min_likes.is_some() {
+            conditions.push("post.likeCount >= $filter_min_likes");
+        }
+        if conditions.is_empty() {
+            None
+        } else {
+            Some(conditions.join(" AND "))
+        }
+    }
+
+    pub fn bindings(&self) -> Bindings {
+        let mut bindings = Bindings::new();
+        if !self.authors.is_empty() {
+            bindings.insert("filter_authors", self.authors.clone().into());
+        }
+        if self.has_media.is_some() {
+            bindings.insert("filter_media_embeds", MEDIA_EMBEDS.to_vec().into());
+        }
+        if self.is_repost.is_some() {
+            bindings.insert("filter_reason_repost", REASON_REPOST.into());
+        }
+        if !self.languages.is_empty() {
+            bindings.insert("filter_languages", self.languages.clone().into());
+        }
+        if !self.labels.is_empty() {
+            bindings.insert("filter_labels", self.labels.clone().into());
+        }
+        if let Some(created_before) = &self.created_before {
+            bindings.insert("filter_created_before", created_before.clone().into());
+        }
+        if let Some(created_since) = &self.created_since {
+            bindings.insert("filter_created_since", created_since.clone().into());
+        }
+        if let Some(text) = &self.text_contains {
+            bindings.insert("filter_text", text.to_lowercase().into());
+        }
+        if let Some(min_likes) = self.min_likes {
+            bindings.insert("filter_min_likes", min_likes.into());
+        }
+        bindings
+    }
+
+    // Returns whether a post that is not a repost passes the filter, the
+    // conditions of to_sql are checked on the post as the server returned it
+    pub fn matches(&self, post: &PostView) -> bool {
+        let post = match serde_json::to_value(post) {
+            Ok(post) => post,
+            Err(_) => return false,
+        };
+        let field = |path: &[&str]| path.iter().fold(&post, |value, key| &value[*key]);
+        let text = |path: &[&str]| field(path).as_str().map(String::from);
+        let any_of = |values: &Value, wanted: &[String]| match values.as_array() {
+            Some(values) => values
+                .iter()
+                .filter_map(Value::as_str)
+                .any(|value| wanted.iter().any(|w| w == value)),
+            None => false,
+        };
+        if !self.authors.is_empty() {
+            let did = text(&["author", "did"]);
+            let handle = text(&["author", "handle"]);
+            if !self
+                .authors
+                .iter()
+                .any(|author| Some(author) == did.as_ref() || Some(author) == handle.as_ref())
+            {
+                return false;
+            }
+        }
+        if let Some(has_media) = self.has_media {
+            let media = text(&["embed", "$type"])
+                .is_some_and(|embed| MEDIA_EMBEDS.contains(&embed.as_str()));
+            if media != has_media {
+                return false;
+            }
+        }
+        if let Some(is_reply) = self.is_reply {
+            if field(&["record", "reply"]).is_null() == is_reply {
+                return false;
+            }
+        }
+        if self.is_repost == Some(true) {
+            return false;
+        }
+        if !self.languages.is_empty() && !any_of(field(&["record", "langs"]), &self.languages) {
+            return false;
+        }
+        if !self.labels.is_empty() {
+            let labels: Vec<Value> = match field(&["labels"]).as_array() {
+                Some(labels) => labels.iter().map(|label| label["val"].clone()).collect(),
+                None => Vec::new(),
+            };
+            if !any_of(&Value::Array(labels), &self.labels) {
+                return false;
+            }
+        }
+        let created_at = text(&["record", "createdAt"]);
+        if let Some(created_before) = &self.created_before {
+            if !matches!(&created_at, Some(c) if c < created_before) {
+                return false;
+            }
+        }
+        if let Some(created_since) = &self.created_since {
+            if !matches!(&created_at, Some(c) if c >= created_since) {
+                return false;
+            }
+        }
+        if let Some(wanted) = &self.text_contains {
+            let text = text(&["record", "text"]).unwrap_or_default();
+            if !text.to_lowercase().contains(&wanted.to_lowercase()) {
+                return false;
+            }
+        }
+        if let Some(min_likes) = self.min_likes {
+            if !matches!(field(&["likeCount"]).as_i64(), Some(likes) if likes >= min_likes) {
+                return false;
+            }
+        }
+        true
+    }
+
+    // Returns a filter with the conditions of both, other wins where both are set
+    pub fn merge(mut self, other: TimelineFilter) -> Self {
+        self.authors.extend(other.authors);
+        self.languages.extend(other.languages);
+        self.labels.extend(other.labels);
+        self.has_media = other.has_media.or(self.has_media);
+        self.is_reply = other.is_reply.or(self.is_reply);
+        self.is_repost = other.is_repost.or(self.is_repost);
+        self.created_before = other.created_before.or(self.created_before);
+        self.created_since = other.created_since.or(self.created_since);
+        self.text_contains = other.text_contains.or(self.text_contains);
+        self.min_likes = other.min_likes.or(self.min_likes);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{post_view_json, CREATED_AT};
+
+    #[test]
+    fn every_condition_is_bound() {
+        let filter = TimelineFilter::new()
+            .authors(["did:plc:abc", "alice.bsky.social"])
+            .has_media(true)
+            .is_reply(false)
+            .is_repost(false)
+            .language("fr")
+            .label("nsfw")
+            .created_since("2024-03-01T00:00:00Z")
+            .text_contains("Rust')")
+            .min_likes(5);
+        let sql = filter.to_sql().expect("conditions");
+        assert_eq!(sql.matches(" AND ").count(), 8);
+        for (name, _) in filter.bindings() {
+            assert!(sql.contains(&format!("${}", name)), "{} is not used", name);
+        }
+        assert_eq!(filter.bindings()["filter_text"], "rust')");
+        assert!(TimelineFilter::default().to_sql().is_none());
+    }
+
+    #[test]
+    fn posts_are_matched_on_the_conditions_of_the_query() {
+        let mut value = post_view_json("3kabc", "Learning Rust today", CREATED_AT);
+        value["likeCount"] = 3.into();
+        value["record"]["langs"] = serde_json::json!(["en"]);
+        let post: PostView = serde_json::from_value(value).unwrap();
+
+        assert!(TimelineFilter::default().matches(&post));
+        let filter = TimelineFilter::new()
+            .authors(["alice.bsky.social"])
+            .has_media(false)
+            .is_reply(false)
+            .is_repost(false)
+            .language("en")
+            .created_since("2024-03-01T00:00:00Z")
+            .text_contains("RUST")
+            .min_likes(3);
+        assert!(filter.matches(&post));
+        assert!(!TimelineFilter::new()
+            .authors(["did:plc:bob"])
+            .matches(&post));
+        assert!(!TimelineFilter::new().has_media(true).matches(&post));
+        assert!(!TimelineFilter::new().is_reply(true).matches(&post));
+        assert!(!TimelineFilter::new().is_repost(true).matches(&post));
+        assert!(!TimelineFilter::new().language("fr").matches(&post));
+        assert!(!TimelineFilter::new().label("nsfw").matches(&post));
+        assert!(!TimelineFilter::new()
+            .created_before(CREATED_AT)
+            .matches(&post));
+        assert!(!TimelineFilter::new().text_contains("python").matches(&post));
+        assert!(!TimelineFilter::new().min_likes(4).matches(&post));
+    }
+}
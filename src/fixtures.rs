@@ -0,0 +1,59 @@
+// Records shared by the tests, written the way the server returns them
+use atrium_api::app::bsky::feed::defs::{FeedViewPost, PostView};
+use libipld_core::cid::Cid;
+use libipld_core::multihash::Multihash;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+pub const CREATED_AT: &str = "2024-03-01T10:00:00.000Z";
+
+// A dag-cbor CID derived from seed, distinct seeds give distinct CIDs
+pub fn cid(seed: &str) -> String {
+    let digest = Sha256::digest(seed.as_bytes());
+    let hash = Multihash::wrap(0x12, &digest).expect("sha2-256 digest");
+    Cid::new_v1(0x71, hash).to_string()
+}
+
+// A post of alice.bsky.social, its CID is derived from rkey
+pub fn post_view_json(rkey: &str, text: &str, created_at: &str) -> Value {
+    json!({
+        "uri": format!("at://did:plc:alice/app.bsky.feed.post/{}", rkey),
+        "cid": cid(rkey),
+        "author": { "did": "did:plc:alice", "handle": "alice.bsky.social" },
+        "record": {
+            "$type": "app.bsky.feed.post",
+            "text": text,
+            "createdAt": created_at
+        },
+        "indexedAt": created_at
+    })
+}
+
+pub fn post_view(rkey: &str, text: &str, created_at: &str) -> PostView {
+    serde_json::from_value(post_view_json(rkey, text, created_at)).expect("valid post view")
+}
+
+pub fn feed_view_post(rkey: &str, text: &str, created_at: &str) -> FeedViewPost {
+    serde_json::from_value(json!({ "post": post_view_json(rkey, text, created_at) }))
+        .expect("valid feed view post")
+}
+
+// A notification sent by did about the post rkey of the logged in account
+pub fn notification_json(reason: &str, did: &str, rkey: &str, is_read: bool) -> Value {
+    let collection = match reason {
+        "like" => "app.bsky.feed.like",
+        "repost" => "app.bsky.feed.repost",
+        "follow" => "app.bsky.graph.follow",
+        _ => "app.bsky.feed.post",
+    };
+    let uri = format!("at://{}/{}/3k{}", did, collection, rkey);
+    json!({
+        "uri": uri,
+        "cid": cid(&uri),
+        "reason": reason,
+        "reasonSubject": format!("at://did:plc:me/app.bsky.feed.post/{}", rkey),
+        "isRead": is_read,
+        "indexedAt": CREATED_AT,
+        "author": { "did": did, "handle": format!("{}.bsky.social", &did[8..]) },
+    })
+}
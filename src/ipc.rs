@@ -0,0 +1,534 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::Context;
+use atrium_api::app::bsky::feed::get_post_thread::OutputThreadEnum;
+use atrium_api::types::string::{AtIdentifier, Datetime};
+use futures::lock::Mutex;
+use log::{info, trace};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::UnixStream;
+
+use crate::actions::{like, repost, resolve_reply, unlike, unrepost};
+use crate::backfill::{fill_gap, BackfillOptions};
+use crate::commands::{
+    ActorArgs, Command, FeedArgs, GetAuthorFeedArgs, GetCidDidArgs, GetCidUriArgs, GetRecordArgs,
+    GetTimelineArgs, ListNotificationsArgs, ListRecordsArgs, MarkSeenArgs, PostArgs,
+    PruneTimelineArgs, PutRecordArgs, ReadNotificationsArgs, ReadTimelineArgs, RecordArgs,
+    RepoCommand, RepoImportArgs, SearchArgs, SyncNotificationsArgs, UriArgs, UriArgsU16,
+    UriListArgs,
+};
+use crate::notifications::{read_notifications, store_notifications, sync_notifications};
+use crate::repo::import_repo;
+use crate::runner::Runner;
+use crate::subscription::{FeedPage, Subscription};
+use crate::surreal::SurrealDB;
+
+// Bump this whenever a method or its params change in a non backward compatible way
+pub const PROTOCOL_VERSION: u32 = 1;
+pub const JSONRPC_VERSION: &str = "2.0";
+
+pub const INVALID_REQUEST: i32 = -32600;
+pub const UNSUPPORTED_VERSION: i32 = -32000;
+pub const METHOD_FAILED: i32 = -32001;
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "method", content = "params", rename_all = "snake_case")]
+pub enum Method {
+    GetTimeline(GetTimelineArgs),
+    GetAuthorFeed(GetAuthorFeedArgs),
+    GetLikes(GetCidUriArgs),
+    GetRepostedBy(GetCidUriArgs),
+    GetActorFeeds(ActorArgs),
+    GetFeed(FeedArgs),
+    GetPosts(UriListArgs),
+    GetPostThread(UriArgsU16),
+    GetListFeed(FeedArgs),
+    GetFollows(ActorArgs),
+    GetFollowers(ActorArgs),
+    GetLists(ActorArgs),
+    GetList(UriArgs),
+    GetProfile(ActorArgs),
+    GetBlob(GetCidDidArgs),
+    ListNotifications(ListNotificationsArgs),
+    SyncNotifications(SyncNotificationsArgs),
+    ReadNotifications(ReadNotificationsArgs),
+    MarkSeen(MarkSeenArgs),
+    GetUnreadCount,
+    ReadTimeline(ReadTimelineArgs),
+    PruneTimeline(PruneTimelineArgs),
+    Search(SearchArgs),
+    Like(PostArgs),
+    Unlike(PostArgs),
+    Repost(PostArgs),
+    Unrepost(PostArgs),
+    ResolveReply(PostArgs),
+    ImportRepo(RepoImportArgs),
+    ListRecords(ListRecordsArgs),
+    GetRecord(GetRecordArgs),
+    PutRecord(PutRecordArgs),
+    DeleteRecord(RecordArgs),
+    Refresh,
+}
+
+// JSON-RPC 2.0 envelope with an extra protocol version field
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Request {
+    pub jsonrpc: String,
+    pub version: u32,
+    pub id: u64,
+    #[serde(flatten)]
+    pub method: Method,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RpcError {
+    pub code: i32,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Response {
+    pub jsonrpc: String,
+    pub version: u32,
+    pub id: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+}
+
+impl Response {
+    pub fn result(id: u64, result: serde_json::Value) -> Self {
+        Response {
+            jsonrpc: String::from(JSONRPC_VERSION),
+            version: PROTOCOL_VERSION,
+            id: Some(id),
+            result: Some(result),
+            error: None,
+        }
+    }
+
+    pub fn error(id: Option<u64>, code: i32, message: String) -> Self {
+        Response {
+            jsonrpc: String::from(JSONRPC_VERSION),
+            version: PROTOCOL_VERSION,
+            id,
+            result: None,
+            error: Some(RpcError { code, message }),
+        }
+    }
+}
+
+impl Method {
+    pub fn needs_db(&self) -> bool {
+        match self {
+            Method::GetAuthorFeed(args) => args.store,
+            Method::GetFeed(args) | Method::GetListFeed(args) => args.store,
+            Method::GetPostThread(args) => args.store,
+            Method::ListNotifications(args) => args.store,
+            Method::ReadTimeline(_)
+            | Method::PruneTimeline(_)
+            | Method::Search(_)
+            | Method::Like(_)
+            | Method::Unlike(_)
+            | Method::Repost(_)
+            | Method::Unrepost(_)
+            | Method::ResolveReply(_)
+            | Method::SyncNotifications(_)
+            | Method::ReadNotifications(_)
+            | Method::MarkSeen(_)
+            | Method::ImportRepo(_)
+            | Method::Refresh => true,
+            _ => false,
+        }
+    }
+
+    // Runs the method against the runner and the database of the current process
+    pub async fn execute(
+        self,
+        runner: &Runner,
+        db: Option<Arc<Mutex<SurrealDB>>>,
+    ) -> Result<serde_json::Value, anyhow::Error> {
+        trace!("executing method: {:?}", self);
+        let value = match self {
+            Method::GetTimeline(args) => serde_json::to_value(runner._get_timeline(args).await?)?,
+            Method::GetAuthorFeed(args) => {
+                let store = args.store;
+                let request_cursor = args.cursor.clone();
+                let actor = args.actor.clone();
+                let output = runner._get_author_feed(args).await?;
+                if store {
+                    let feed_page = FeedPage {
+                        feed: output.feed.clone(),
+                        cursor: output.cursor.clone(),
+                    };
+                    let did = runner.resolve_actor(actor).await?;
+                    let subscription = Subscription::Author(AtIdentifier::Did(did));
+                    store_page(db, &subscription, feed_page, request_cursor).await?;
+                }
+                serde_json::to_value(output)?
+            }
+            Method::GetLikes(args) => serde_json::to_value(runner._get_likes(args).await?)?,
+            Method::GetRepostedBy(args) => {
+                serde_json::to_value(runner._get_reposted_by(args).await?)?
+            }
+            Method::GetActorFeeds(args) => {
+                serde_json::to_value(runner._get_actor_feed(args).await?)?
+            }
+            Method::GetFeed(args) => {
+                let subscription = Subscription::Feed(args.uri.uri.clone());
+                let request_cursor = args.uri.cursor.clone();
+                let output = runner._get_feed(args.uri).await?;
+                if args.store {
+                    let feed_page = FeedPage {
+                        feed: output.feed.clone(),
+                        cursor: output.cursor.clone(),
+                    };
+                    store_page(db, &subscription, feed_page, request_cursor).await?;
+                }
+                serde_json::to_value(output)?
+            }
+            Method::GetPosts(args) => serde_json::to_value(runner._get_post(args).await?)?,
+            Method::GetPostThread(args) => {
+                let store = args.store;
+                let output = runner._get_post_thread(args).await?;
+                if let (true, OutputThreadEnum::AppBskyFeedDefsThreadViewPost(thread)) =
+                    (store, &output.thread)
+                {
+                    let db = db.ok_or_else(|| anyhow::Error::msg("--store needs a database"))?;
+                    let db_lock = db.lock().await;
+                    db_lock.store_thread(thread).await?;
+                    drop(db_lock);
+                }
+                serde_json::to_value(output)?
+            }
+            Method::GetListFeed(args) => {
+                let subscription = Subscription::List(args.uri.uri.clone());
+                let request_cursor = args.uri.cursor.clone();
+                let output = runner._get_list_feed(args.uri).await?;
+                if args.store {
+                    let feed_page = FeedPage {
+                        feed: output.feed.clone(),
+                        cursor: output.cursor.clone(),
+                    };
+                    store_page(db, &subscription, feed_page, request_cursor).await?;
+                }
+                serde_json::to_value(output)?
+            }
+            Method::GetFollows(args) => serde_json::to_value(runner._get_follows(args).await?)?,
+            Method::GetFollowers(args) => serde_json::to_value(runner._get_followers(args).await?)?,
+            Method::GetLists(args) => serde_json::to_value(runner._get_lists(args).await?)?,
+            Method::GetList(args) => serde_json::to_value(runner._get_list(args).await?)?,
+            Method::GetProfile(args) => serde_json::to_value(runner._get_profile(args).await?)?,
+            Method::GetBlob(args) => serde_json::to_value(runner._get_blob(args).await?)?,
+            Method::ListNotifications(args) => {
+                let store = args.store;
+                let output = runner._list_notifications(args).await?;
+                if store {
+                    let db = db.ok_or_else(|| anyhow::Error::msg("--store needs a database"))?;
+                    store_notifications(&db, runner, &output.notifications).await?;
+                }
+                serde_json::to_value(output)?
+            }
+            Method::SyncNotifications(args) => {
+                let db =
+                    db.ok_or_else(|| anyhow::Error::msg("sync_notifications needs a database"))?;
+                let stored = sync_notifications(&db, runner, args.pages).await?;
+                serde_json::json!({ "stored": stored })
+            }
+            Method::ReadNotifications(args) => {
+                let db =
+                    db.ok_or_else(|| anyhow::Error::msg("read_notifications needs a database"))?;
+                let db_lock = db.lock().await;
+                let view = read_notifications(&db_lock, args.limit, args.unread).await?;
+                drop(db_lock);
+                serde_json::to_value(view)?
+            }
+            Method::MarkSeen(args) => {
+                let db = db.ok_or_else(|| anyhow::Error::msg("mark_seen needs a database"))?;
+                let seen_at = args.seen_at.unwrap_or_else(Datetime::now);
+                runner._update_seen(seen_at.clone()).await?;
+                let db_lock = db.lock().await;
+                db_lock
+                    .mark_notifications_read(
+                        serde_json::to_string(&seen_at)?
+                            .trim_matches('"')
+                            .to_string(),
+                    )
+                    .await?;
+                drop(db_lock);
+                serde_json::Value::Null
+            }
+            Method::GetUnreadCount => serde_json::to_value(runner._get_unread_count().await?)?,
+            Method::ListRecords(args) => serde_json::to_value(runner._list_records(args).await?)?,
+            Method::GetRecord(args) => serde_json::to_value(runner._get_record(args).await?)?,
+            Method::PutRecord(args) => serde_json::to_value(runner._put_record(args).await?)?,
+            Method::DeleteRecord(args) => {
+                runner._delete_record(args).await?;
+                serde_json::Value::Null
+            }
+            Method::ReadTimeline(args) => {
+                let db = db.ok_or_else(|| anyhow::Error::msg("read_timeline needs a database"))?;
+                let timeline = timeline_name(runner, args.timeline).await?;
+                let db_lock = db.lock().await;
+                let feed = db_lock
+                    .read_timeline(timeline, args.filter, args.limit)
+                    .await?;
+                drop(db_lock);
+                serde_json::to_value(feed)?
+            }
+            Method::PruneTimeline(args) => {
+                let db = db.ok_or_else(|| anyhow::Error::msg("prune_timeline needs a database"))?;
+                let timeline = timeline_name(runner, args.timeline).await?;
+                let db_lock = db.lock().await;
+                db_lock.prune_timeline(timeline, args.older_than).await?;
+                drop(db_lock);
+                serde_json::Value::Null
+            }
+            Method::Search(args) => {
+                let db = db.ok_or_else(|| anyhow::Error::msg("search needs a database"))?;
+                let db_lock = db.lock().await;
+                let results = db_lock.search_posts(args.query, args.limit).await?;
+                drop(db_lock);
+                serde_json::to_value(results)?
+            }
+            // The references of a reply to the post, for the frontends that post
+            // themselves
+            Method::ResolveReply(args) => {
+                let db = db.ok_or_else(|| anyhow::Error::msg("resolve_reply needs a database"))?;
+                let reply = resolve_reply(&db, runner, &args.post).await?;
+                serde_json::to_value(reply)?
+            }
+            Method::Like(args) => {
+                let db = db.ok_or_else(|| anyhow::Error::msg("like needs a database"))?;
+                let post = like(&db, runner, &args.post).await?;
+                serde_json::to_value(post)?
+            }
+            Method::Unlike(args) => {
+                let db = db.ok_or_else(|| anyhow::Error::msg("unlike needs a database"))?;
+                let post = unlike(&db, runner, &args.post).await?;
+                serde_json::to_value(post)?
+            }
+            Method::Repost(args) => {
+                let db = db.ok_or_else(|| anyhow::Error::msg("repost needs a database"))?;
+                let post = repost(&db, runner, &args.post).await?;
+                serde_json::to_value(post)?
+            }
+            Method::Unrepost(args) => {
+                let db = db.ok_or_else(|| anyhow::Error::msg("unrepost needs a database"))?;
+                let post = unrepost(&db, runner, &args.post).await?;
+                serde_json::to_value(post)?
+            }
+            Method::ImportRepo(args) => {
+                let db = db.ok_or_else(|| anyhow::Error::msg("import_repo needs a database"))?;
+                let db_lock = db.lock().await;
+                let summary = import_repo(&db_lock, &args.path).await?;
+                drop(db_lock);
+                serde_json::to_value(summary)?
+            }
+            Method::Refresh => {
+                let db = db.ok_or_else(|| anyhow::Error::msg("refresh needs a database"))?;
+                let pages = fill_gap(
+                    &db,
+                    runner,
+                    &Subscription::Home,
+                    &BackfillOptions::default(),
+                )
+                .await?;
+                serde_json::json!({ "pages": pages })
+            }
+        };
+        Ok(value)
+    }
+}
+
+// Names the timeline the way it is stored, author:<handle> is read from the
+// timeline of the DID
+async fn timeline_name(runner: &Runner, timeline: String) -> Result<String, anyhow::Error> {
+    match timeline.parse::<Subscription>() {
+        Ok(subscription) => Ok(subscription.resolve(runner).await?.name()),
+        Err(_) => Ok(timeline),
+    }
+}
+
+// Writes a page returned to a frontend under the timeline of its source
+async fn store_page(
+    db: Option<Arc<Mutex<SurrealDB>>>,
+    subscription: &Subscription,
+    feed_page: FeedPage,
+    request_cursor: Option<String>,
+) -> Result<(), anyhow::Error> {
+    let db = db.ok_or_else(|| anyhow::Error::msg("--store needs a database"))?;
+    let db_lock = db.lock().await;
+    db_lock
+        .store_feed_page(feed_page, subscription.name(), request_cursor)
+        .await?;
+    drop(db_lock);
+    Ok(())
+}
+
+impl TryFrom<Command> for Method {
+    type Error = anyhow::Error;
+
+    fn try_from(command: Command) -> Result<Self, Self::Error> {
+        match command {
+            Command::GetTimeline(args) => Ok(Method::GetTimeline(args)),
+            Command::GetAuthorFeed(args) => Ok(Method::GetAuthorFeed(args)),
+            Command::GetLikes(args) => Ok(Method::GetLikes(args)),
+            Command::GetRepostedBy(args) => Ok(Method::GetRepostedBy(args)),
+            Command::GetActorFeeds(args) => Ok(Method::GetActorFeeds(args)),
+            Command::GetFeed(args) => Ok(Method::GetFeed(args)),
+            Command::GetPosts(args) => Ok(Method::GetPosts(args)),
+            Command::GetPostThread(args) => Ok(Method::GetPostThread(args)),
+            Command::GetListFeed(args) => Ok(Method::GetListFeed(args)),
+            Command::GetFollows(args) => Ok(Method::GetFollows(args)),
+            Command::GetFollowers(args) => Ok(Method::GetFollowers(args)),
+            Command::GetLists(args) => Ok(Method::GetLists(args)),
+            Command::GetList(args) => Ok(Method::GetList(args)),
+            Command::GetBlob(args) => Ok(Method::GetBlob(args)),
+            Command::GetProfile(args) => Ok(Method::GetProfile(args)),
+            Command::ListNotifications(args) => Ok(Method::ListNotifications(args)),
+            Command::SyncNotifications(args) => Ok(Method::SyncNotifications(args)),
+            Command::ReadNotifications(args) => Ok(Method::ReadNotifications(args)),
+            Command::MarkSeen(args) => Ok(Method::MarkSeen(args)),
+            Command::ReadTimeline(args) => Ok(Method::ReadTimeline(args)),
+            Command::PruneTimeline(args) => Ok(Method::PruneTimeline(args)),
+            Command::Search(args) => Ok(Method::Search(args)),
+            Command::Like(args) => Ok(Method::Like(args)),
+            Command::Unlike(args) => Ok(Method::Unlike(args)),
+            Command::Repost(args) => Ok(Method::Repost(args)),
+            Command::Unrepost(args) => Ok(Method::Unrepost(args)),
+            Command::ListRecords(args) => Ok(Method::ListRecords(args)),
+            Command::GetRecord(args) => Ok(Method::GetRecord(args)),
+            Command::PutRecord(args) => Ok(Method::PutRecord(args)),
+            Command::DeleteRecord(args) => Ok(Method::DeleteRecord(args)),
+            // The daemon may not share the working directory of the frontend
+            Command::Repo(RepoCommand::ImportLocal(args)) => {
+                Ok(Method::ImportRepo(RepoImportArgs {
+                    path: std::fs::canonicalize(&args.path)
+                        .with_context(|| format!("Unable to read {:?}", args.path))?,
+                }))
+            }
+            other => Err(anyhow::Error::msg(format!(
+                "{:?} has no daemon method",
+                other
+            ))),
+        }
+    }
+}
+
+pub struct Client {
+    lines: Lines<BufReader<OwnedReadHalf>>,
+    writer: OwnedWriteHalf,
+    next_id: u64,
+}
+
+impl Client {
+    pub async fn connect(path: &Path) -> Result<Self, anyhow::Error> {
+        let stream = UnixStream::connect(path).await?;
+        let (reader, writer) = stream.into_split();
+        Ok(Client {
+            lines: BufReader::new(reader).lines(),
+            writer,
+            next_id: 0,
+        })
+    }
+
+    pub async fn call_value(&mut self, method: Method) -> Result<serde_json::Value, anyhow::Error> {
+        self.next_id += 1;
+        let request = Request {
+            jsonrpc: String::from(JSONRPC_VERSION),
+            version: PROTOCOL_VERSION,
+            id: self.next_id,
+            method,
+        };
+        let mut buffer = serde_json::to_vec(&request)?;
+        buffer.push(b'\n');
+        self.writer.write_all(&buffer).await?;
+        let line = self
+            .lines
+            .next_line()
+            .await?
+            .ok_or_else(|| anyhow::Error::msg("daemon closed the connection"))?;
+        let response: Response = serde_json::from_str(&line)?;
+        if response.id != Some(self.next_id) {
+            return Err(anyhow::Error::msg(format!(
+                "unexpected response id {:?}, expected {}",
+                response.id, self.next_id
+            )));
+        }
+        match (response.result, response.error) {
+            (_, Some(e)) => Err(anyhow::Error::msg(format!(
+                "daemon error {}: {}",
+                e.code, e.message
+            ))),
+            (Some(result), None) => Ok(result),
+            (None, None) => Ok(serde_json::Value::Null),
+        }
+    }
+
+    pub async fn call<T: DeserializeOwned>(&mut self, method: Method) -> Result<T, anyhow::Error> {
+        let value = self.call_value(method).await?;
+        Ok(serde_json::from_value(value)?)
+    }
+}
+
+// Talks to a running daemon when there is one, otherwise runs the methods in process
+pub enum Backend {
+    Daemon(Client),
+    Direct {
+        runner: Runner,
+        db: Option<Arc<Mutex<SurrealDB>>>,
+    },
+}
+
+impl Backend {
+    pub async fn connect(
+        socket: &Path,
+        pds_host: String,
+        debug: bool,
+    ) -> Result<Self, anyhow::Error> {
+        match Backend::daemon(socket).await {
+            Some(backend) => Ok(backend),
+            None => Ok(Backend::Direct {
+                runner: Runner::new(pds_host, debug).await?,
+                db: None,
+            }),
+        }
+    }
+
+    // Returns None when no daemon listens on socket
+    pub async fn daemon(socket: &Path) -> Option<Self> {
+        match Client::connect(socket).await {
+            Ok(client) => {
+                info!("connected to daemon on {:?}", socket);
+                Some(Backend::Daemon(client))
+            }
+            Err(e) => {
+                info!("no daemon on {:?} ({}), running in direct mode", socket, e);
+                None
+            }
+        }
+    }
+
+    pub async fn call_value(&mut self, method: Method) -> Result<serde_json::Value, anyhow::Error> {
+        match self {
+            Backend::Daemon(client) => client.call_value(method).await,
+            Backend::Direct { runner, db } => {
+                if method.needs_db() && db.is_none() {
+                    *db = Some(Arc::new(Mutex::new(SurrealDB::new().await?)));
+                }
+                method.execute(runner, db.clone()).await
+            }
+        }
+    }
+
+    pub async fn call<T: DeserializeOwned>(&mut self, method: Method) -> Result<T, anyhow::Error> {
+        let value = self.call_value(method).await?;
+        Ok(serde_json::from_value(value)?)
+    }
+}
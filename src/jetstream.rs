@@ -0,0 +1,257 @@
+use std::collections::HashSet;
+
+use futures::StreamExt;
+use log::{info, warn};
+use reqwest::Url;
+use serde::Deserialize;
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+
+use crate::commands::ActorArgs;
+use crate::runner::Runner;
+
+pub const JETSTREAM_URL: &str = "wss://jetstream2.us-east.bsky.network/subscribe";
+const POST_COLLECTION: &str = "app.bsky.feed.post";
+// Most DIDs sent in wantedDids, each one adds about 45 bytes to the url so past
+// this the url outgrows what the server accepts and the stream is subscribed to
+// unfiltered, leaving the follows to the author filter on the frames
+const MAX_WANTED_DIDS: usize = 100;
+// Follows requested per page
+const FOLLOWS_PAGE_SIZE: u8 = 100;
+
+// A Jetstream event, the commit is only set for the commit kind
+#[derive(Deserialize, Debug)]
+struct Frame {
+    did: String,
+    time_us: u64,
+    kind: String,
+    #[serde(default)]
+    commit: Option<Commit>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Commit {
+    operation: String,
+    collection: String,
+    rkey: String,
+    #[serde(default)]
+    cid: Option<String>,
+    #[serde(default)]
+    record: Option<serde_json::Value>,
+}
+
+// A post created by a followed account
+#[derive(Debug, Clone, PartialEq)]
+pub struct NewPost {
+    pub uri: String,
+    pub cid: String,
+    pub did: String,
+    pub time_us: u64,
+}
+
+impl Frame {
+    // Replies are left out, the timeline only shows them with their parent
+    fn new_post(self, follows: &HashSet<String>) -> Option<NewPost> {
+        let commit = self.commit?;
+        if self.kind != "commit"
+            || commit.operation != "create"
+            || commit.collection != POST_COLLECTION
+            || !follows.contains(&self.did)
+        {
+            return None;
+        }
+        if commit
+            .record
+            .as_ref()
+            .is_some_and(|record| record.get("reply").is_some())
+        {
+            return None;
+        }
+        Some(NewPost {
+            uri: format!("at://{}/{}/{}", self.did, commit.collection, commit.rkey),
+            cid: commit.cid?,
+            did: self.did,
+            time_us: self.time_us,
+        })
+    }
+}
+
+// Returns the subscribe url asking for the posts of follows, or for every post
+// when there are too many of them, replayed from cursor when it is set
+pub fn subscribe_url(
+    url: &str,
+    follows: &HashSet<String>,
+    cursor: Option<u64>,
+) -> Result<Url, anyhow::Error> {
+    let mut url = Url::parse(url)?;
+    {
+        let mut query = url.query_pairs_mut();
+        query.append_pair("wantedCollections", POST_COLLECTION);
+        if follows.len() <= MAX_WANTED_DIDS {
+            for did in follows {
+                query.append_pair("wantedDids", did);
+            }
+        }
+        if let Some(cursor) = cursor {
+            query.append_pair("cursor", &cursor.to_string());
+        }
+    }
+    Ok(url)
+}
+
+// Returns the DIDs of the accounts the logged in account follows, itself
+// included
+pub async fn follows(runner: &Runner) -> Result<HashSet<String>, anyhow::Error> {
+    let mut dids = HashSet::new();
+    let mut cursor = None;
+    loop {
+        let output = runner
+            ._get_follows(ActorArgs {
+                cursor,
+                limit: FOLLOWS_PAGE_SIZE,
+                actor: None,
+            })
+            .await?;
+        dids.insert(output.subject.did.to_string());
+        for follow in output.follows {
+            dids.insert(follow.did.to_string());
+        }
+        match output.cursor {
+            Some(next) => cursor = Some(next),
+            None => break,
+        }
+    }
+    info!("following {} accounts", dids.len() - 1);
+    Ok(dids)
+}
+
+// A Jetstream connection returning the posts of the followed accounts as they
+// are created
+pub struct Subscriber {
+    socket: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    follows: HashSet<String>,
+    // time_us of the last frame, resumes the stream after a reconnection
+    pub cursor: Option<u64>,
+}
+
+impl Subscriber {
+    pub async fn connect(
+        url: &str,
+        follows: HashSet<String>,
+        cursor: Option<u64>,
+    ) -> Result<Self, anyhow::Error> {
+        let url = subscribe_url(url, &follows, cursor)?;
+        let (socket, _response) = connect_async(url.as_str()).await?;
+        info!("subscribed to {}", url.host_str().unwrap_or_default());
+        Ok(Subscriber {
+            socket,
+            follows,
+            cursor,
+        })
+    }
+
+    // Returns the next post of a followed account, None once the server closes
+    // the connection
+    pub async fn next_post(&mut self) -> Result<Option<NewPost>, anyhow::Error> {
+        while let Some(message) = self.socket.next().await {
+            let text = match message? {
+                Message::Text(text) => text,
+                Message::Close(_) => return Ok(None),
+                _ => continue,
+            };
+            let frame: Frame = match serde_json::from_str(&text) {
+                Ok(frame) => frame,
+                Err(e) => {
+                    warn!("skipping invalid frame {:?}: {}", text, e);
+                    continue;
+                }
+            };
+            self.cursor = Some(frame.time_us);
+            if let Some(post) = frame.new_post(&self.follows) {
+                return Ok(Some(post));
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::SinkExt;
+    use tokio::net::TcpListener;
+
+    // Recorded from Jetstream, only alice is followed
+    const FRAMES: [&str; 6] = [
+        r#"{"did":"did:plc:alice","time_us":1725911162329308,"kind":"commit","commit":{"rev":"3l3qo2vuowo2b","operation":"create","collection":"app.bsky.feed.post","rkey":"3l3qo2vutsw2b","record":{"$type":"app.bsky.feed.post","createdAt":"2024-09-09T19:46:02.102Z","langs":["en"],"text":"hello"},"cid":"bafyreidwaivazkwu67xztlmuobx35hs2lnfh3kolmgfmucldvhd3sgzcqi"}}"#,
+        r#"{"did":"did:plc:alice","time_us":1725911162339308,"kind":"commit","commit":{"rev":"3l3qo2vuowo3b","operation":"create","collection":"app.bsky.feed.post","rkey":"3l3qo2vutsw3b","record":{"$type":"app.bsky.feed.post","createdAt":"2024-09-09T19:46:02.202Z","text":"a reply","reply":{"parent":{"cid":"bafyreidwaivazkwu67xztlmuobx35hs2lnfh3kolmgfmucldvhd3sgzcqi","uri":"at://did:plc:bob/app.bsky.feed.post/3l3qo2vutsw1b"},"root":{"cid":"bafyreidwaivazkwu67xztlmuobx35hs2lnfh3kolmgfmucldvhd3sgzcqi","uri":"at://did:plc:bob/app.bsky.feed.post/3l3qo2vutsw1b"}}},"cid":"bafyreie5737gdxlw5i64vzichcalba3z2v5n6icifvx5xytvske7mr3hpm"}}"#,
+        r#"{"did":"did:plc:mallory","time_us":1725911162349308,"kind":"commit","commit":{"rev":"3l3qo2vuowo4b","operation":"create","collection":"app.bsky.feed.post","rkey":"3l3qo2vutsw4b","record":{"$type":"app.bsky.feed.post","createdAt":"2024-09-09T19:46:02.302Z","text":"not followed"},"cid":"bafyreidwaivazkwu67xztlmuobx35hs2lnfh3kolmgfmucldvhd3sgzcqi"}}"#,
+        r#"{"did":"did:plc:alice","time_us":1725911162359308,"kind":"commit","commit":{"rev":"3l3qo2vuowo5b","operation":"delete","collection":"app.bsky.feed.post","rkey":"3l3qo2vutsw2b"}}"#,
+        r#"{"did":"did:plc:alice","time_us":1725911162369308,"kind":"identity","identity":{"did":"did:plc:alice","handle":"alice.bsky.social","seq":1409752997,"time":"2024-09-05T06:11:04.870Z"}}"#,
+        r#"{"did":"did:plc:alice","time_us":1725911162379308,"kind":"commit","commit":{"rev":"3l3qo2vuowo6b","operation":"create","collection":"app.bsky.feed.post","rkey":"3l3qo2vutsw6b","record":{"$type":"app.bsky.feed.post","createdAt":"2024-09-09T19:46:02.402Z","text":"again"},"cid":"bafyreie5737gdxlw5i64vzichcalba3z2v5n6icifvx5xytvske7mr3hpm"}}"#,
+    ];
+
+    #[tokio::test]
+    async fn followed_posts_are_read_from_a_replayed_stream() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut socket = tokio_tungstenite::accept_async(stream).await.unwrap();
+            socket
+                .send(Message::Text(String::from("not json")))
+                .await
+                .unwrap();
+            for frame in FRAMES {
+                socket.send(Message::Text(frame.to_string())).await.unwrap();
+            }
+            socket.close(None).await.unwrap();
+        });
+
+        let follows = HashSet::from([String::from("did:plc:alice")]);
+        let url = format!("ws://{}/subscribe", addr);
+        let mut subscriber = Subscriber::connect(&url, follows, Some(1725911162000000))
+            .await
+            .unwrap();
+        let mut posts = Vec::new();
+        while let Some(post) = subscriber.next_post().await.unwrap() {
+            posts.push(post);
+        }
+        assert_eq!(
+            posts.iter().map(|p| p.uri.as_str()).collect::<Vec<_>>(),
+            vec![
+                "at://did:plc:alice/app.bsky.feed.post/3l3qo2vutsw2b",
+                "at://did:plc:alice/app.bsky.feed.post/3l3qo2vutsw6b",
+            ]
+        );
+        assert_eq!(
+            posts[0].cid,
+            "bafyreidwaivazkwu67xztlmuobx35hs2lnfh3kolmgfmucldvhd3sgzcqi"
+        );
+        assert_eq!(subscriber.cursor, Some(1725911162379308));
+        server.await.unwrap();
+        assert_eq!(
+            subscribe_url(&url, &subscriber.follows, subscriber.cursor)
+                .unwrap()
+                .as_str(),
+            format!(
+                "ws://{}/subscribe?wantedCollections=app.bsky.feed.post&wantedDids=did%3Aplc%3Aalice&cursor=1725911162379308",
+                addr
+            )
+        );
+    }
+
+    #[test]
+    fn many_follows_are_left_out_of_the_url() {
+        let follows = (0..=MAX_WANTED_DIDS)
+            .map(|i| format!("did:plc:{}", i))
+            .collect::<HashSet<_>>();
+        assert_eq!(
+            subscribe_url(JETSTREAM_URL, &follows, None)
+                .unwrap()
+                .as_str(),
+            "wss://jetstream2.us-east.bsky.network/subscribe?wantedCollections=app.bsky.feed.post"
+        );
+    }
+}
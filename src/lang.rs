@@ -0,0 +1,177 @@
+use langtag::LanguageTag;
+
+use crate::config::Config;
+
+// Most languages a post can be tagged with
+pub const MAX_LANGS: usize = 3;
+
+// Frequent short words of the languages told apart in latin script
+const STOPWORDS: [(&str, &[&str]); 7] = [
+    (
+        "en",
+        &[
+            "the", "and", "is", "are", "of", "to", "in", "that", "it", "with", "for", "this",
+            "was", "you", "not", "have", "be", "on",
+        ],
+    ),
+    (
+        "fr",
+        &[
+            "le", "la", "les", "des", "est", "et", "une", "un", "du", "que", "qui", "pas", "pour",
+            "dans", "avec", "sur", "je", "nous", "ce", "il",
+        ],
+    ),
+    (
+        "es",
+        &[
+            "el", "la", "los", "las", "es", "y", "que", "una", "un", "del", "por", "con", "para",
+            "no", "se", "en", "lo", "muy",
+        ],
+    ),
+    (
+        "de",
+        &[
+            "der", "die", "das", "und", "ist", "nicht", "ein", "eine", "ich", "mit", "zu", "den",
+            "auf", "für", "sich", "auch", "es",
+        ],
+    ),
+    (
+        "pt",
+        &[
+            "o", "a", "os", "as", "é", "e", "que", "um", "uma", "do", "da", "não", "com", "para",
+            "em", "no", "na", "se",
+        ],
+    ),
+    (
+        "it",
+        &[
+            "il", "lo", "la", "gli", "le", "è", "e", "che", "un", "una", "di", "del", "non", "per",
+            "con", "sono", "nel",
+        ],
+    ),
+    (
+        "nl",
+        &[
+            "de", "het", "een", "en", "is", "van", "niet", "dat", "ik", "met", "op", "voor",
+            "zijn", "te",
+        ],
+    ),
+];
+
+pub fn is_valid(tag: &str) -> bool {
+    LanguageTag::parse(tag).is_ok()
+}
+
+// Returns the language written in a script of its own, scripts shared by
+// several languages, e.g. cyrillic or arabic, are left undetected
+fn detect_script(text: &str) -> Option<&'static str> {
+    let (mut letters, mut kana, mut han, mut hangul) = (0, 0, 0, 0);
+    let (mut greek, mut hebrew, mut thai) = (0, 0, 0);
+    for c in text.chars().filter(|c| c.is_alphabetic()) {
+        letters += 1;
+        match c {
+            '\u{3040}'..='\u{30FF}' => kana += 1,
+            '\u{4E00}'..='\u{9FFF}' | '\u{3400}'..='\u{4DBF}' => han += 1,
+            '\u{AC00}'..='\u{D7AF}' | '\u{1100}'..='\u{11FF}' => hangul += 1,
+            '\u{0370}'..='\u{03FF}' => greek += 1,
+            '\u{0590}'..='\u{05FF}' => hebrew += 1,
+            '\u{0E00}'..='\u{0E7F}' => thai += 1,
+            _ => {}
+        }
+    }
+    // Japanese mixes kana and kanji
+    let scripts = [
+        ("ja", if kana > 0 { kana + han } else { 0 }),
+        ("zh", if kana == 0 { han } else { 0 }),
+        ("ko", hangul),
+        ("el", greek),
+        ("he", hebrew),
+        ("th", thai),
+    ];
+    scripts
+        .into_iter()
+        .find(|(_, count)| *count * 2 > letters)
+        .map(|(lang, _)| lang)
+}
+
+// Returns the language of the text when it is clear enough, None otherwise
+pub fn detect(text: &str) -> Option<&'static str> {
+    if let Some(lang) = detect_script(text) {
+        return Some(lang);
+    }
+    let words: Vec<String> = text
+        .split(|c: char| !c.is_alphabetic())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+    let mut scores: Vec<(&str, usize)> = STOPWORDS
+        .iter()
+        .map(|(lang, stopwords)| {
+            let score = words
+                .iter()
+                .filter(|word| stopwords.contains(&word.as_str()))
+                .count();
+            (*lang, score)
+        })
+        .collect();
+    scores.sort_by_key(|(_, score)| std::cmp::Reverse(*score));
+    match scores[..] {
+        [(lang, best), (_, second), ..] if best >= 2 && best > second => Some(lang),
+        _ => None,
+    }
+}
+
+// Returns the languages of a post: the ones given with the post, else the
+// detected one when detection is on, else the configured default
+pub fn post_langs(given: &[String], detect_lang: bool, config: &Config, text: &str) -> Vec<String> {
+    if !given.is_empty() {
+        return given.to_vec();
+    }
+    if detect_lang || config.detect_lang {
+        if let Some(lang) = detect(text) {
+            return vec![lang.to_string()];
+        }
+    }
+    config.langs.iter().take(MAX_LANGS).cloned().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn languages_are_detected_from_words_and_scripts() {
+        assert_eq!(
+            detect("The release is out, thanks to everyone that helped with it"),
+            Some("en")
+        );
+        assert_eq!(
+            detect("Nous avons publié la nouvelle version, merci pour les retours"),
+            Some("fr")
+        );
+        assert_eq!(
+            detect("Die neue Version ist da und sie ist schnell"),
+            Some("de")
+        );
+        assert_eq!(detect("新しいバージョンを公開しました"), Some("ja"));
+        assert_eq!(detect("새 버전이 나왔습니다"), Some("ko"));
+        assert_eq!(detect("🦀 https://example.com"), None);
+    }
+
+    #[test]
+    fn given_languages_win_over_detection_and_config() {
+        let config = Config {
+            langs: vec![String::from("pt-BR")],
+            ..Config::default()
+        };
+        let text = "Nous avons publié la nouvelle version";
+        assert_eq!(
+            post_langs(&[String::from("es")], true, &config, text),
+            vec!["es"]
+        );
+        assert_eq!(post_langs(&[], true, &config, text), vec!["fr"]);
+        assert_eq!(post_langs(&[], false, &config, text), vec!["pt-BR"]);
+        assert_eq!(post_langs(&[], true, &config, "🦀"), vec!["pt-BR"]);
+        assert!(is_valid("pt-BR") && !is_valid("not a language"));
+    }
+}
@@ -1,6 +1,29 @@
+pub mod actions;
+pub mod backfill;
+pub mod car;
+pub mod card;
 pub mod commands;
+pub mod composer;
+pub mod config;
+pub mod daemon;
+pub mod filter;
+#[cfg(test)]
+mod fixtures;
+pub mod ipc;
+pub mod jetstream;
+pub mod lang;
+pub mod markdown;
+pub mod media;
+pub mod notifications;
+pub mod notifier;
 pub mod nvim;
+pub mod record;
+pub mod repo;
+pub mod richtext;
 pub mod runner;
 pub mod sql;
 pub mod store;
+pub mod subscription;
 pub mod surreal;
+pub mod thread;
+pub mod validate;
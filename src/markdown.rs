@@ -0,0 +1,84 @@
+use regex::Regex;
+
+use crate::richtext::{detect, Span, SpanKind};
+
+const LINK: &str = r"\[([^\]\n]+)\]\((https?://[^\s)]+)\)";
+const FENCES: [&str; 2] = ["```", "~~~"];
+
+// Converts a post written in Markdown to the text of the post and the spans to
+// annotate: [text](url) keeps the visible text only and links it to url,
+// @handle, #tag and bare urls are detected as in plain text. Fenced blocks have
+// no rendering on bluesky and are rejected.
+pub fn to_post(input: &str) -> Result<(String, Vec<Span>), anyhow::Error> {
+    if let Some((number, _)) = input.lines().enumerate().find(|(_, line)| {
+        FENCES
+            .iter()
+            .any(|fence| line.trim_start().starts_with(fence))
+    }) {
+        return Err(anyhow::Error::msg(format!(
+            "line {}: fenced blocks are not supported in posts, remove the fence",
+            number + 1
+        )));
+    }
+    let link = Regex::new(LINK).expect("valid markdown link regex");
+    let mut text = String::with_capacity(input.len());
+    let mut spans = Vec::new();
+    let mut last = 0;
+    for captures in link.captures_iter(input) {
+        let whole = captures.get(0).expect("link");
+        text.push_str(&input[last..whole.start()]);
+        let start = text.len();
+        text.push_str(&captures[1]);
+        spans.push(Span {
+            start,
+            end: text.len(),
+            kind: SpanKind::Link(captures[2].to_string()),
+        });
+        last = whole.end();
+    }
+    text.push_str(&input[last..]);
+    let links = spans.clone();
+    spans.extend(detect(&text).into_iter().filter(|span| {
+        !links
+            .iter()
+            .any(|l| span.start < l.end && l.start < span.end)
+    }));
+    spans.sort_by_key(|span| span.start);
+    Ok((text, spans))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn links_keep_the_visible_text() {
+        let (text, spans) =
+            to_post("Read [the café docs](https://example.com/docs) by @alice.test #rust").unwrap();
+        assert_eq!(text, "Read the café docs by @alice.test #rust");
+        let slices: Vec<(&str, &SpanKind)> = spans
+            .iter()
+            .map(|span| (&text[span.start..span.end], &span.kind))
+            .collect();
+        assert_eq!(
+            slices,
+            vec![
+                (
+                    "the café docs",
+                    &SpanKind::Link(String::from("https://example.com/docs"))
+                ),
+                (
+                    "@alice.test",
+                    &SpanKind::Mention(String::from("alice.test"))
+                ),
+                ("#rust", &SpanKind::Tag(String::from("rust"))),
+            ]
+        );
+    }
+
+    #[test]
+    fn fenced_blocks_are_rejected() {
+        let err = to_post("look:\n```rust\nfn main() {}\n```").unwrap_err();
+        assert!(err.to_string().starts_with("line 2:"), "{}", err);
+    }
+}
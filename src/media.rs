@@ -0,0 +1,192 @@
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::ColorType;
+
+use crate::validate::{image_mime, MAX_IMAGE_BYTES};
+
+// Quality of the first jpeg encoding of a downscaled image, lowered until it fits
+const JPEG_QUALITY: [u8; 4] = [90, 80, 70, 60];
+
+// An image ready to be uploaded as a blob
+#[derive(Debug)]
+pub struct PreparedImage {
+    pub bytes: Vec<u8>,
+    pub mime: &'static str,
+    pub width: u32,
+    pub height: u32,
+}
+
+fn be16(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u16::from_be_bytes(bytes.get(at..at + 2)?.try_into().ok()?) as u32)
+}
+
+fn le16(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u16::from_le_bytes(bytes.get(at..at + 2)?.try_into().ok()?) as u32)
+}
+
+fn le24(bytes: &[u8], at: usize) -> Option<u32> {
+    let b = bytes.get(at..at + 3)?;
+    Some(b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16)
+}
+
+fn jpeg_dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    let mut at = 2;
+    while at + 9 < bytes.len() {
+        if bytes[at] != 0xFF {
+            return None;
+        }
+        let marker = bytes[at + 1];
+        // Start of frame markers, except the huffman and arithmetic tables
+        if (0xC0..=0xCF).contains(&marker) && ![0xC4, 0xC8, 0xCC].contains(&marker) {
+            return Some((be16(bytes, at + 7)?, be16(bytes, at + 5)?));
+        }
+        at += 2 + be16(bytes, at + 2)? as usize;
+    }
+    None
+}
+
+fn webp_dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    match bytes.get(12..16)? {
+        b"VP8 " => Some((le16(bytes, 26)? & 0x3FFF, le16(bytes, 28)? & 0x3FFF)),
+        b"VP8L" => {
+            let b = bytes.get(21..25)?;
+            let width = 1 + (b[0] as u32 | (b[1] as u32 & 0x3F) << 8);
+            let height = 1 + ((b[1] as u32) >> 6 | (b[2] as u32) << 2 | (b[3] as u32 & 0x0F) << 10);
+            Some((width, height))
+        }
+        b"VP8X" => Some((1 + le24(bytes, 24)?, 1 + le24(bytes, 27)?)),
+        _ => None,
+    }
+}
+
+// Returns the width and height of an image read from its header
+pub fn dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    match image_mime(bytes)? {
+        "image/png" => Some((
+            u32::from_be_bytes(bytes.get(16..20)?.try_into().ok()?),
+            u32::from_be_bytes(bytes.get(20..24)?.try_into().ok()?),
+        )),
+        "image/gif" => Some((le16(bytes, 6)?, le16(bytes, 8)?)),
+        "image/jpeg" => jpeg_dimensions(bytes),
+        "image/webp" => webp_dimensions(bytes),
+        _ => None,
+    }
+}
+
+// Downscales the image by steps of 3/4 and encodes it as jpeg until it fits
+fn shrink(bytes: &[u8]) -> Result<PreparedImage, anyhow::Error> {
+    let mut image = image::load_from_memory(bytes)?;
+    loop {
+        for quality in JPEG_QUALITY {
+            let rgb = image.to_rgb8();
+            let mut encoded = Vec::new();
+            JpegEncoder::new_with_quality(&mut encoded, quality).encode(
+                &rgb,
+                rgb.width(),
+                rgb.height(),
+                ColorType::Rgb8,
+            )?;
+            if encoded.len() as u64 <= MAX_IMAGE_BYTES {
+                return Ok(PreparedImage {
+                    bytes: encoded,
+                    mime: "image/jpeg",
+                    width: rgb.width(),
+                    height: rgb.height(),
+                });
+            }
+        }
+        if image.width() < 64 || image.height() < 64 {
+            return Err(anyhow::Error::msg(
+                "unable to shrink the image under the size limit",
+            ));
+        }
+        image = image.resize(
+            image.width() * 3 / 4,
+            image.height() * 3 / 4,
+            FilterType::Triangle,
+        );
+    }
+}
+
+// Detects the type and the size of an image, an image over the blob size limit
+// is downscaled and re-encoded as jpeg when resize is set
+pub fn prepare(bytes: Vec<u8>, resize: bool) -> Result<PreparedImage, anyhow::Error> {
+    let mime = image_mime(&bytes)
+        .ok_or_else(|| anyhow::Error::msg("not a jpeg, png, webp or gif image"))?;
+    if bytes.len() as u64 > MAX_IMAGE_BYTES {
+        if resize {
+            return shrink(&bytes);
+        }
+        return Err(anyhow::Error::msg(format!(
+            "the image is {} bytes, at most {}",
+            bytes.len(),
+            MAX_IMAGE_BYTES
+        )));
+    }
+    let (width, height) = dimensions(&bytes).unwrap_or((0, 0));
+    Ok(PreparedImage {
+        bytes,
+        mime,
+        width,
+        height,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageOutputFormat, Rgb, RgbImage};
+    use std::io::Cursor;
+
+    fn encode(image: &RgbImage, format: ImageOutputFormat) -> Vec<u8> {
+        let mut bytes = Cursor::new(Vec::new());
+        image.write_to(&mut bytes, format).unwrap();
+        bytes.into_inner()
+    }
+
+    #[test]
+    fn dimensions_are_read_from_the_header() {
+        let image = RgbImage::from_pixel(640, 360, Rgb([200, 100, 50]));
+        for format in [
+            ImageOutputFormat::Png,
+            ImageOutputFormat::Jpeg(80),
+            ImageOutputFormat::Gif,
+        ] {
+            let bytes = encode(&image, format.clone());
+            assert_eq!(dimensions(&bytes), Some((640, 360)), "{:?}", format);
+        }
+        let vp8x = [
+            b"RIFF".as_slice(),
+            &[0; 4],
+            b"WEBPVP8X",
+            &[0; 8],
+            &[0x7F, 0x02, 0x00, 0x67, 0x01, 0x00],
+        ]
+        .concat();
+        assert_eq!(dimensions(&vp8x), Some((640, 360)));
+    }
+
+    #[test]
+    fn large_images_are_shrunk_when_asked() {
+        // Noise does not compress, the png is well over the limit
+        let mut seed: u32 = 1;
+        let image = RgbImage::from_fn(1200, 900, |_, _| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            let [a, b, c, _] = seed.to_le_bytes();
+            Rgb([a, b, c])
+        });
+        let png = encode(&image, ImageOutputFormat::Png);
+        assert!(png.len() as u64 > MAX_IMAGE_BYTES);
+        assert!(prepare(png.clone(), false).is_err());
+
+        let prepared = prepare(png, true).unwrap();
+        assert_eq!(prepared.mime, "image/jpeg");
+        assert!(prepared.bytes.len() as u64 <= MAX_IMAGE_BYTES);
+        let ratio = prepared.width as f64 / prepared.height as f64;
+        assert!((ratio - 4.0 / 3.0).abs() < 0.01, "{}", ratio);
+        assert_eq!(
+            dimensions(&prepared.bytes),
+            Some((prepared.width, prepared.height))
+        );
+    }
+}
@@ -0,0 +1,250 @@
+use std::collections::HashMap;
+
+use atrium_api::app::bsky::actor::defs::ProfileViewBasic;
+use atrium_api::app::bsky::feed::defs::PostView;
+use atrium_api::app::bsky::notification::list_notifications::Notification;
+use futures::lock::Mutex;
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::commands::{ListNotificationsArgs, UriListArgs};
+use crate::runner::Runner;
+use crate::sql::Querier;
+use crate::surreal::SurrealDB;
+
+// Notifications requested per page when syncing
+const PAGE_SIZE: u8 = 50;
+// Most posts the server returns for a single getPosts call
+const POSTS_PER_REQUEST: usize = 25;
+
+// A notification as stored in the notification table, post is the post the
+// notification is about: the liked or reposted post, or the reply, mention or
+// quote itself. Follows have no post.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct StoredNotification {
+    pub uri: String,
+    pub cid: String,
+    pub reason: String,
+    #[serde(default)]
+    pub reason_subject: Option<String>,
+    pub is_read: bool,
+    pub indexed_at: String,
+    pub author: ProfileViewBasic,
+    #[serde(default)]
+    pub post: Option<PostView>,
+}
+
+// One item of the notification list, likes and reposts of the same post are
+// aggregated into a single item, authors are ordered from the newest
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct NotificationGroup {
+    pub reason: String,
+    pub subject: Option<String>,
+    pub post: Option<PostView>,
+    pub authors: Vec<ProfileViewBasic>,
+    pub count: usize,
+    pub unread: bool,
+    pub indexed_at: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NotificationsView {
+    pub unread: i32,
+    pub groups: Vec<NotificationGroup>,
+}
+
+// Returns the at-URI of the post a notification is about
+pub fn subject(notification: &Notification) -> Option<String> {
+    match notification.reason.as_str() {
+        "like" | "repost" => notification.reason_subject.clone(),
+        "reply" | "mention" | "quote" => Some(notification.uri.clone()),
+        _ => None,
+    }
+}
+
+fn is_aggregated(reason: &str) -> bool {
+    matches!(reason, "like" | "repost")
+}
+
+// Groups notifications ordered from the newest, a group takes the place of its
+// newest notification
+pub fn group(notifications: Vec<StoredNotification>) -> Vec<NotificationGroup> {
+    let mut groups: Vec<NotificationGroup> = Vec::new();
+    let mut index: HashMap<(String, String), usize> = HashMap::new();
+    for notification in notifications {
+        let subject = match notification.reason.as_str() {
+            "like" | "repost" => notification.reason_subject.clone(),
+            _ => notification.post.as_ref().map(|post| post.uri.clone()),
+        };
+        let key = match (is_aggregated(&notification.reason), &subject) {
+            (true, Some(subject)) => Some((notification.reason.clone(), subject.clone())),
+            _ => None,
+        };
+        if let Some(&i) = key.as_ref().and_then(|key| index.get(key)) {
+            let group = &mut groups[i];
+            group.count += 1;
+            group.unread |= !notification.is_read;
+            if !group
+                .authors
+                .iter()
+                .any(|author| author.did == notification.author.did)
+            {
+                group.authors.push(notification.author);
+            }
+            continue;
+        }
+        if let Some(key) = key {
+            index.insert(key, groups.len());
+        }
+        groups.push(NotificationGroup {
+            reason: notification.reason,
+            subject,
+            post: notification.post,
+            authors: vec![notification.author],
+            count: 1,
+            unread: !notification.is_read,
+            indexed_at: notification.indexed_at,
+        });
+    }
+    groups
+}
+
+// Stores the notifications along with their authors and the posts they are
+// about, the posts are fetched from the server before the database is locked
+pub async fn store_notifications(
+    db: &Mutex<SurrealDB>,
+    runner: &Runner,
+    notifications: &[Notification],
+) -> Result<(), anyhow::Error> {
+    let mut uris: Vec<String> = notifications.iter().filter_map(subject).collect();
+    uris.sort();
+    uris.dedup();
+    let mut posts = Vec::new();
+    for chunk in uris.chunks(POSTS_PER_REQUEST) {
+        let output = runner
+            ._get_post(UriListArgs {
+                uri: chunk.to_vec(),
+            })
+            .await?;
+        posts.extend(output.posts);
+    }
+    let mut cids: HashMap<String, String> = HashMap::new();
+    let db_lock = db.lock().await;
+    for post in posts {
+        let cid: String = serde_json::to_string(&post.cid)?
+            .trim_matches('"')
+            .to_string();
+        cids.insert(post.uri.clone(), cid);
+        db_lock.store_post_view(post).await?;
+    }
+    for notification in notifications {
+        let post_cid = subject(notification).and_then(|uri| cids.get(&uri).cloned());
+        db_lock
+            .store_notification(notification.clone(), post_cid)
+            .await?;
+    }
+    drop(db_lock);
+    Ok(())
+}
+
+// Fetches the notifications page by page from the newest until a page reaches
+// the notifications stored by a previous sync, or max_pages. Returns the number
+// of notifications stored. The database is only locked between the requests.
+pub async fn sync_notifications(
+    db: &Mutex<SurrealDB>,
+    runner: &Runner,
+    max_pages: usize,
+) -> Result<usize, anyhow::Error> {
+    let mut cursor = None;
+    let mut stored = 0;
+    for _ in 0..max_pages {
+        let output = runner
+            ._list_notifications(ListNotificationsArgs {
+                cursor,
+                limit: PAGE_SIZE,
+                seen_at: None,
+                store: false,
+            })
+            .await?;
+        let mut cids = Vec::new();
+        for notification in &output.notifications {
+            cids.push(
+                serde_json::to_string(&notification.cid)?
+                    .trim_matches('"')
+                    .to_string(),
+            );
+        }
+        let db_lock = db.lock().await;
+        let _ = db_lock.db.use_ns("bsky").use_db("timeline").await;
+        let known = Querier::new(db_lock.db.clone())
+            .count_stored_notifications(cids)
+            .await?;
+        drop(db_lock);
+        store_notifications(db, runner, &output.notifications).await?;
+        stored += output.notifications.len();
+        // The read state of the known notifications is refreshed by the same page
+        if known > 0 || output.cursor.is_none() {
+            break;
+        }
+        cursor = output.cursor;
+    }
+    info!("synced {} notifications", stored);
+    Ok(stored)
+}
+
+pub async fn read_notifications(
+    db: &SurrealDB,
+    limit: i32,
+    unread_only: bool,
+) -> Result<NotificationsView, anyhow::Error> {
+    let _ = db.db.use_ns("bsky").use_db("timeline").await;
+    let querier = Querier::new(db.db.clone());
+    let notifications = querier.read_notifications(limit, unread_only).await?;
+    Ok(NotificationsView {
+        unread: querier.count_unread_notifications().await?,
+        groups: group(notifications),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::notification_json;
+
+    fn notification(reason: &str, did: &str, subject: &str, is_read: bool) -> StoredNotification {
+        serde_json::from_value(notification_json(reason, did, subject, is_read))
+            .expect("valid notification")
+    }
+
+    #[test]
+    fn likes_and_reposts_are_aggregated_per_post() {
+        let groups = group(vec![
+            notification("like", "did:plc:alice", "a", false),
+            notification("repost", "did:plc:alice", "a", false),
+            notification("like", "did:plc:bob", "a", true),
+            notification("like", "did:plc:alice", "b", true),
+            notification("follow", "did:plc:carol", "", true),
+            notification("like", "did:plc:alice", "a", true),
+            notification("follow", "did:plc:dave", "", false),
+        ]);
+        let summary: Vec<(&str, usize, usize, bool)> = groups
+            .iter()
+            .map(|g| (g.reason.as_str(), g.count, g.authors.len(), g.unread))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("like", 3, 2, true),
+                ("repost", 1, 1, true),
+                ("like", 1, 1, false),
+                ("follow", 1, 1, false),
+                ("follow", 1, 1, true),
+            ]
+        );
+        assert_eq!(
+            groups[0].subject.as_deref(),
+            Some("at://did:plc:me/app.bsky.feed.post/a")
+        );
+    }
+}
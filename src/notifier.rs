@@ -0,0 +1,251 @@
+use std::process::Command;
+
+use atrium_api::records::Record;
+use futures::lock::Mutex;
+use log::{error, info};
+use neovim_lib::{Neovim, Value};
+
+use crate::config::NotifyConfig;
+use crate::notifications::StoredNotification;
+use crate::sql::Querier;
+use crate::surreal::SurrealDB;
+
+// Unread notifications looked at for new alerts on each check
+const ALERT_WINDOW: i32 = 50;
+
+// What is shown to the user for a new notification
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alert {
+    pub uri: String,
+    pub reason: String,
+    pub title: String,
+    pub body: String,
+}
+
+impl Alert {
+    pub fn new(notification: &StoredNotification) -> Self {
+        let author = match &notification.author.display_name {
+            Some(name) if !name.is_empty() => name.clone(),
+            _ => format!("@{}", notification.author.handle.as_str()),
+        };
+        let action = match notification.reason.as_str() {
+            "mention" => "mentioned you",
+            "reply" => "replied to you",
+            "quote" => "quoted your post",
+            "like" => "liked your post",
+            "repost" => "reposted your post",
+            "follow" => "followed you",
+            reason => reason,
+        };
+        let body = match notification.post.as_ref().map(|post| &post.record) {
+            Some(Record::AppBskyFeedPost(post)) => post.text.clone(),
+            _ => String::new(),
+        };
+        Alert {
+            uri: notification.uri.clone(),
+            reason: notification.reason.clone(),
+            title: format!("{} {}", author, action),
+            body,
+        }
+    }
+}
+
+pub trait Notifier: Send {
+    fn notify(&mut self, alert: &Alert) -> Result<(), anyhow::Error>;
+}
+
+// Runs a program with the title and the body of the alert as its last
+// arguments, e.g. notify-send
+pub struct CommandNotifier {
+    command: Vec<String>,
+}
+
+impl CommandNotifier {
+    pub fn new(command: Vec<String>) -> Result<Self, anyhow::Error> {
+        if command.is_empty() {
+            return Err(anyhow::Error::msg("The notify command is empty"));
+        }
+        Ok(CommandNotifier { command })
+    }
+}
+
+impl Notifier for CommandNotifier {
+    fn notify(&mut self, alert: &Alert) -> Result<(), anyhow::Error> {
+        let mut child = Command::new(&self.command[0])
+            .args(&self.command[1..])
+            .arg(&alert.title)
+            .arg(&alert.body)
+            .spawn()?;
+        // Reaps the process without blocking the refresh
+        std::thread::spawn(move || child.wait());
+        Ok(())
+    }
+}
+
+impl Notifier for Neovim {
+    fn notify(&mut self, alert: &Alert) -> Result<(), anyhow::Error> {
+        let message = if alert.body.is_empty() {
+            alert.title.clone()
+        } else {
+            format!("{}\n{}", alert.title, alert.body)
+        };
+        // 2 is vim.log.levels.INFO
+        self.session
+            .call(
+                "nvim_notify",
+                vec![
+                    Value::from(message),
+                    Value::from(2),
+                    Value::Map(vec![(Value::from("title"), Value::from("bsky"))]),
+                ],
+            )
+            .map_err(|e| anyhow::Error::msg(format!("nvim_notify failed: {}", e)))?;
+        Ok(())
+    }
+}
+
+// Returns the notifications commands configured in the settings, neovim is
+// left to the frontend since it owns the session
+pub fn command_notifiers(config: &NotifyConfig) -> Vec<Box<dyn Notifier>> {
+    match CommandNotifier::new(config.command.clone()) {
+        Ok(notifier) => vec![Box::new(notifier)],
+        Err(_) => vec![],
+    }
+}
+
+// Returns the alerts for the notifications with one of the reasons that did not
+// raise one yet, from the oldest
+pub fn alerts(
+    notifications: &[StoredNotification],
+    reasons: &[String],
+    notified: &[String],
+) -> Vec<Alert> {
+    notifications
+        .iter()
+        .rev()
+        .filter(|n| reasons.contains(&n.reason) && !notified.contains(&n.uri))
+        .map(Alert::new)
+        .collect()
+}
+
+// Raises an alert for each new unread notification with one of the reasons,
+// each notification raises at most one alert. A notification none of the
+// notifiers could show is tried again on the next check. Returns the number of
+// alerts shown. The database is not locked while the notifiers run.
+pub async fn notify_new(
+    db: &Mutex<SurrealDB>,
+    notifiers: &mut [&mut dyn Notifier],
+    reasons: &[String],
+) -> Result<usize, anyhow::Error> {
+    if notifiers.is_empty() {
+        return Ok(0);
+    }
+    let db_lock = db.lock().await;
+    let _ = db_lock.db.use_ns("bsky").use_db("timeline").await;
+    let querier = Querier::new(db_lock.db.clone());
+    let unread = querier.read_notifications(ALERT_WINDOW, true).await?;
+    let uris = unread.iter().map(|n| n.uri.clone()).collect();
+    let notified = querier.select_notified_uris(uris).await?;
+    drop(db_lock);
+    let mut shown = 0;
+    for alert in alerts(&unread, reasons, &notified) {
+        let mut delivered = false;
+        for notifier in notifiers.iter_mut() {
+            match notifier.notify(&alert) {
+                Ok(()) => delivered = true,
+                Err(e) => error!("error while notifying {}: {:?}", alert.uri, e),
+            }
+        }
+        if delivered {
+            db.lock().await.store_notified(alert.uri.clone()).await?;
+            shown += 1;
+        }
+    }
+    if shown > 0 {
+        info!("raised {} alerts", shown);
+    }
+    Ok(shown)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{notification_json, post_view_json, CREATED_AT};
+
+    fn notification(reason: &str, rkey: &str, text: Option<&str>) -> StoredNotification {
+        let mut value = notification_json(reason, "did:plc:alice", rkey, false);
+        if let Some(text) = text {
+            value["post"] = post_view_json(rkey, text, CREATED_AT);
+        }
+        serde_json::from_value(value).expect("valid notification")
+    }
+
+    #[test]
+    fn only_new_notifications_with_a_reason_raise_alerts() {
+        let notifications = vec![
+            notification("reply", "c", Some("thanks!")),
+            notification("like", "b", None),
+            notification("mention", "a", Some("hi @me")),
+            notification("reply", "z", Some("old")),
+        ];
+        let reasons = vec![String::from("mention"), String::from("reply")];
+        let notified = vec![notifications[3].uri.clone()];
+        let alerts = alerts(&notifications, &reasons, &notified);
+        assert_eq!(
+            alerts
+                .iter()
+                .map(|a| (a.title.as_str(), a.body.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("@alice.bsky.social mentioned you", "hi @me"),
+                ("@alice.bsky.social replied to you", "thanks!"),
+            ]
+        );
+    }
+
+    struct Failing;
+
+    impl Notifier for Failing {
+        fn notify(&mut self, _alert: &Alert) -> Result<(), anyhow::Error> {
+            Err(anyhow::Error::msg("no event loop"))
+        }
+    }
+
+    struct Recording(Vec<String>);
+
+    impl Notifier for Recording {
+        fn notify(&mut self, alert: &Alert) -> Result<(), anyhow::Error> {
+            self.0.push(alert.uri.clone());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn alerts_no_notifier_showed_are_raised_again() -> Result<(), anyhow::Error> {
+        let path =
+            std::env::temp_dir().join(format!("rbsky-notifier-test-{}.db", std::process::id()));
+        let db = Mutex::new(SurrealDB::open(path.clone()).await?);
+        let mut value = notification_json("mention", "did:plc:alice", "a", false);
+        value["record"] = serde_json::json!({
+            "$type": "app.bsky.feed.post",
+            "text": "hi @me",
+            "createdAt": CREATED_AT,
+        });
+        db.lock()
+            .await
+            .store_notification(serde_json::from_value(value)?, None)
+            .await?;
+        let reasons = vec![String::from("mention")];
+
+        let mut failing = Failing;
+        assert_eq!(notify_new(&db, &mut [&mut failing], &reasons).await?, 0);
+        let mut recording = Recording(Vec::new());
+        let mut notifiers: Vec<&mut dyn Notifier> = vec![&mut failing, &mut recording];
+        assert_eq!(notify_new(&db, &mut notifiers, &reasons).await?, 1);
+        assert_eq!(notify_new(&db, &mut notifiers, &reasons).await?, 0);
+        drop(notifiers);
+        assert_eq!(recording.0.len(), 1);
+        std::fs::remove_dir_all(path).ok();
+        Ok(())
+    }
+}
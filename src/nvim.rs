@@ -1,24 +1,51 @@
+use std::str::FromStr;
+use std::sync::mpsc::{Receiver, Sender};
 use std::sync::Arc;
 
+use crate::actions::{like, repost, resolve_post, resolve_reply, unlike, unrepost};
+use crate::backfill::{backfill, fill_gap, BackfillOptions};
+use crate::config::NotifyConfig;
+use crate::filter::TimelineFilter;
+use crate::jetstream::{follows, NewPost, Subscriber};
+use crate::notifications::{read_notifications, sync_notifications, NotificationsView};
+use crate::notifier::{command_notifiers, notify_new, Notifier};
 use crate::sql::Querier;
-use crate::surreal::SurrealDB;
-use crate::{commands::GetTimelineArgs, runner::Runner};
+use crate::subscription::Subscription;
+use crate::surreal::{SearchResult, SurrealDB};
+use crate::thread::{flatten_thread, ThreadLine};
+use crate::validate::validate_thread;
+use crate::{
+    commands::{AtUri, CreatePostArgs, UriArgsU16},
+    runner::Runner,
+};
 use atrium_api::app::bsky::feed::defs::PostView;
+use atrium_api::app::bsky::feed::get_post_thread::OutputThreadEnum;
 use futures::lock::Mutex;
-use log::{error, info, trace};
-use neovim_lib::{Neovim, RequestHandler, Session};
+use log::{error, info, trace, warn};
+use neovim_lib::{Neovim, NeovimApi, RequestHandler, Session};
 use serde::{Deserialize, Serialize};
 use tokio::time::{self, Duration};
 
+// Wait before reconnecting to Jetstream once the stream drops
+const STREAM_RECONNECT_DELAY: Duration = Duration::from_secs(10);
+
 enum Messages {
     Read,
     Update,
     Post,
     RePost,
+    UnRepost,
+    Quote,
+    Reply,
     Like,
     UnLike,
     FetchMore,
     Refresh,
+    Filter,
+    Search,
+    Thread,
+    Validate,
+    Notifications,
     Unknown(String),
 }
 
@@ -29,6 +56,29 @@ pub struct FeedViewPostFlat {
     pub parent: Option<PostView>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub root: Option<PostView>,
+    // Set on search results only, the post text with the matched words in **
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub highlight: Option<String>,
+}
+
+impl From<SearchResult> for FeedViewPostFlat {
+    fn from(result: SearchResult) -> Self {
+        FeedViewPostFlat {
+            post: result.post,
+            parent: None,
+            root: None,
+            highlight: result.highlight,
+        }
+    }
+}
+
+// What the main loop receives: the events sent by neovim, the alerts checks of
+// the background handlers, whose sessions have no event loop to show them, and
+// the end of the neovim event loop
+pub enum Incoming {
+    Event(String, Vec<neovim_lib::Value>),
+    Alerts,
+    Closed,
 }
 
 impl PartialEq for FeedViewPostFlat {
@@ -41,6 +91,72 @@ impl Eq for FeedViewPostFlat {}
 
 pub struct BskyRequestHandler {
     pub feed: Arc<std::sync::Mutex<Option<Vec<FeedViewPostFlat>>>>,
+    pub thread: Arc<std::sync::Mutex<Option<Vec<ThreadLine>>>>,
+    pub notifications: Arc<std::sync::Mutex<Option<NotificationsView>>>,
+    pub db: Arc<Mutex<SurrealDB>>,
+    pub runner: Runner,
+    pub timeline: Subscription,
+    // Shared with the event handlers, which read the timeline with it
+    pub filter: Arc<std::sync::Mutex<TimelineFilter>>,
+    // The requests are handled on the neovim thread, the database is read
+    // through the runtime of the event handlers
+    pub runtime: tokio::runtime::Handle,
+}
+
+// Fetches the thread of post, the CID of a stored post or an at-URI, and stores
+// its posts. A thread that is not found or blocked shows the post alone. The
+// database is only locked between the requests.
+async fn fetch_thread(
+    db: &Mutex<SurrealDB>,
+    runner: &Runner,
+    post: &str,
+) -> Result<Vec<ThreadLine>, anyhow::Error> {
+    let uri = if post.starts_with("at://") {
+        post.to_string()
+    } else {
+        let db_lock = db.lock().await;
+        let _ = db_lock.db.use_ns("bsky").use_db("timeline").await;
+        let uri = Querier::new(db_lock.db.clone()).select_uri(post).await?;
+        drop(db_lock);
+        uri.ok_or_else(|| anyhow::Error::msg(format!("no post stored with cid {}", post)))?
+    };
+    let output = runner
+        ._get_post_thread(UriArgsU16 {
+            parent_height: 10,
+            depth: 10,
+            uri: AtUri::from_str(&uri).map_err(anyhow::Error::msg)?,
+            store: true,
+        })
+        .await?;
+    let lines = match output.thread {
+        OutputThreadEnum::AppBskyFeedDefsThreadViewPost(thread_view) => {
+            db.lock().await.store_thread(&thread_view).await?;
+            flatten_thread(&thread_view)
+        }
+        _ => {
+            warn!(
+                "thread of {} is not found or blocked, showing the post",
+                uri
+            );
+            vec![ThreadLine {
+                depth: 0,
+                focus: true,
+                post: runner.fetch_post(&uri).await?,
+            }]
+        }
+    };
+    info!("loaded {} posts in the thread of {}", lines.len(), uri);
+    Ok(lines)
+}
+
+// Reads the filter sent by neovim as a json object, none or an empty string
+// clears it
+fn parse_filter(value: Option<&neovim_lib::Value>) -> Result<TimelineFilter, anyhow::Error> {
+    match value.and_then(|v| v.as_str()) {
+        Some(json) if !json.is_empty() => serde_json::from_str(json)
+            .map_err(|e| anyhow::Error::msg(format!("invalid filter {}: {}", json, e))),
+        _ => Ok(TimelineFilter::default()),
+    }
 }
 
 impl RequestHandler for BskyRequestHandler {
@@ -52,6 +168,9 @@ impl RequestHandler for BskyRequestHandler {
         trace!("Received rpcrequest name: {:?}, args: {:?}", name, args);
         match Messages::from(name) {
             Messages::Read => Ok(self.handle_read_request()),
+            Messages::Thread => self.handle_thread_request(args),
+            Messages::Validate => Ok(self.handle_validate_request(args)),
+            Messages::Notifications => Ok(self.handle_notifications_request()),
             Messages::FetchMore => {
                 error!("Uninmplemented");
                 return Ok(neovim_lib::Value::from("Unimplemented"));
@@ -68,18 +187,16 @@ impl RequestHandler for BskyRequestHandler {
                 error!("Uninmplemented");
                 return Ok(neovim_lib::Value::from("Unimplemented"));
             }
-            Messages::RePost => {
-                error!("Uninmplemented");
-                return Ok(neovim_lib::Value::from("Unimplemented"));
-            }
-            Messages::Like => {
-                error!("Uninmplemented");
-                return Ok(neovim_lib::Value::from("Unimplemented"));
-            }
-            Messages::UnLike => {
-                error!("Uninmplemented");
-                return Ok(neovim_lib::Value::from("Unimplemented"));
-            }
+            // The like or repost itself is sent by the notification of the same
+            // name, the request returns the feed with the updated counts
+            Messages::Like
+            | Messages::UnLike
+            | Messages::RePost
+            | Messages::UnRepost
+            | Messages::Quote
+            | Messages::Reply => Ok(self.handle_read_request()),
+            Messages::Filter => self.handle_filter_request(args),
+            Messages::Search => self.handle_search_request(args),
             Messages::Unknown(_event) => {
                 error!("Uninmplemented");
                 return Ok(neovim_lib::Value::from("Unimplemented"));
@@ -126,12 +243,165 @@ impl BskyRequestHandler {
             }
         }
     }
+
+    // Filters the timeline with args[0] and returns the feed read with the new
+    // filter, an invalid filter is returned as an error and the previous one kept
+    pub fn handle_filter_request(
+        &mut self,
+        args: Vec<neovim_lib::Value>,
+    ) -> Result<neovim_lib::Value, neovim_lib::Value> {
+        let filter = parse_filter(args.first()).map_err(|e| {
+            error!("{:?}", e);
+            neovim_lib::Value::from(e.to_string())
+        })?;
+        let db = Arc::clone(&self.db);
+        let timeline = self.timeline.name();
+        let read = filter.clone();
+        let feed = self.runtime.block_on(async move {
+            let db_lock = db.lock().await;
+            db_lock.read_timeline(timeline, read, Some(10)).await
+        });
+        let feed: Vec<FeedViewPostFlat> = feed.map_err(|e| {
+            error!("error while filtering the timeline {:?}", e);
+            neovim_lib::Value::from(e.to_string())
+        })?;
+        info!("filtering the timeline with {:?}", filter);
+        match (self.filter.lock(), self.feed.lock()) {
+            (Ok(mut current), Ok(mut l)) => {
+                *current = filter;
+                *l = Some(feed);
+            }
+            _ => {
+                error!("Unable to acquire the lock: keeping the filter");
+                return Err(neovim_lib::Value::from("Unable to acquire the lock"));
+            }
+        }
+        Ok(self.handle_read_request())
+    }
+
+    // Replaces the feed with the stored posts matching the words in args[0] and
+    // returns it, the next update or refresh brings the timeline back
+    pub fn handle_search_request(
+        &mut self,
+        args: Vec<neovim_lib::Value>,
+    ) -> Result<neovim_lib::Value, neovim_lib::Value> {
+        let query = match args.first().and_then(|v| v.as_str()) {
+            Some(query) => query.to_string(),
+            None => return Err(neovim_lib::Value::from("search called with no query")),
+        };
+        let db = Arc::clone(&self.db);
+        let results = self.runtime.block_on(async move {
+            let db_lock = db.lock().await;
+            db_lock.search_posts(query, 50).await
+        });
+        let found: Vec<FeedViewPostFlat> = match results {
+            Ok(results) => results.into_iter().map(FeedViewPostFlat::from).collect(),
+            Err(e) => {
+                error!("error while searching {:?}", e);
+                return Err(neovim_lib::Value::from(e.to_string()));
+            }
+        };
+        match self.feed.lock() {
+            Ok(mut l) => *l = Some(found),
+            Err(_) => return Err(neovim_lib::Value::from("Unable to acquire the lock")),
+        }
+        Ok(self.handle_read_request())
+    }
+
+    // Returns the validation of the lines in args[0] as the post event sends
+    // them, a Markdown thread, as json {graphemes, max_graphemes, posts, errors,
+    // warnings}, for a live character counter
+    pub fn handle_validate_request(&mut self, args: Vec<neovim_lib::Value>) -> neovim_lib::Value {
+        let lines: Vec<&str> = args
+            .first()
+            .and_then(|v| v.as_array())
+            .map(|lines| lines.iter().filter_map(|l| l.as_str()).collect())
+            .unwrap_or_default();
+        let validation = validate_thread(&CreatePostArgs::markdown(lines.join("\n")), true);
+        match serde_json::to_string(&validation) {
+            Ok(s) => neovim_lib::Value::from(s.as_str()),
+            Err(e) => {
+                error!("Error serializing the validation: returning nil: {e}");
+                neovim_lib::Value::from("nil")
+            }
+        }
+    }
+
+    // Loads the thread of args[0], the CID of a stored post or an at-URI, and
+    // returns it as a json list of {depth, focus, post}, the frontend indents
+    // each post by its depth. Without args returns the last thread loaded.
+    pub fn handle_thread_request(
+        &mut self,
+        args: Vec<neovim_lib::Value>,
+    ) -> Result<neovim_lib::Value, neovim_lib::Value> {
+        if let Some(post) = args.first().and_then(|v| v.as_str()) {
+            let lines = self
+                .runtime
+                .block_on(fetch_thread(&self.db, &self.runner, post))
+                .map_err(|e| {
+                    error!("error while loading the thread of {}: {:?}", post, e);
+                    neovim_lib::Value::from(e.to_string())
+                })?;
+            match self.thread.lock() {
+                Ok(mut l) => *l = Some(lines),
+                Err(_) => return Err(neovim_lib::Value::from("Unable to acquire the lock")),
+            }
+        }
+        let locked = self.thread.lock();
+        Ok(match locked {
+            Ok(l) => match l.as_ref().map(serde_json::to_string) {
+                Some(Ok(s)) => neovim_lib::Value::from(s.as_str()),
+                Some(Err(e)) => {
+                    error!("Error serializing the thread: returning nil: {e}");
+                    neovim_lib::Value::from("nil")
+                }
+                None => {
+                    error!("No thread loaded: returning nil");
+                    neovim_lib::Value::from("nil")
+                }
+            },
+            Err(_) => {
+                error!("Unable to acquire the lock: returning nil");
+                neovim_lib::Value::from("nil")
+            }
+        })
+    }
+
+    // Returns the notifications loaded by the last notifications event as json
+    // {unread, groups}, likes and reposts of a post are a single group
+    pub fn handle_notifications_request(&mut self) -> neovim_lib::Value {
+        let locked = self.notifications.lock();
+        match locked {
+            Ok(l) => match l.as_ref().map(serde_json::to_string) {
+                Some(Ok(s)) => neovim_lib::Value::from(s.as_str()),
+                Some(Err(e)) => {
+                    error!("Error serializing the notifications: returning nil: {e}");
+                    neovim_lib::Value::from("nil")
+                }
+                None => {
+                    error!("No notifications loaded: returning nil");
+                    neovim_lib::Value::from("nil")
+                }
+            },
+            Err(_) => {
+                error!("Unable to acquire the lock: returning nil");
+                neovim_lib::Value::from("nil")
+            }
+        }
+    }
 }
 
 pub struct EventHandler {
     pub nvim: Neovim,
     pub db: Arc<Mutex<SurrealDB>>,
     pub runner: Runner,
+    pub backfill: BackfillOptions,
+    pub timeline: Subscription,
+    pub filter: Arc<std::sync::Mutex<TimelineFilter>>,
+    pub notify: NotifyConfig,
+    pub notifiers: Vec<Box<dyn Notifier>>,
+    // Set on the background handlers, the alerts are raised by the main loop
+    pub alerts: Option<Sender<Incoming>>,
 }
 
 impl EventHandler {
@@ -139,23 +409,56 @@ impl EventHandler {
         let session = Session::new_parent()?;
         let nvim = Neovim::new(session);
         let db = db;
-        Ok(EventHandler { nvim, db, runner })
+        let notify = runner.config().notify.clone();
+        let notifiers = command_notifiers(&notify);
+        Ok(EventHandler {
+            nvim,
+            db,
+            runner,
+            backfill: BackfillOptions::default(),
+            timeline: Subscription::Home,
+            filter: Arc::new(std::sync::Mutex::new(TimelineFilter::default())),
+            notify,
+            notifiers,
+            alerts: None,
+        })
     }
 
-    // TODO: add args to the recv function, add timeline, which timeline should I read
-    // Add this in the setup of the binary adding clap
+    // Handles the events of neovim and the alerts sent to incoming until neovim
+    // closes, sender is the end of incoming the neovim events are forwarded to
     pub async fn recv(
         &mut self,
         bsky_request_handler: BskyRequestHandler,
+        sender: Sender<Incoming>,
+        incoming: Receiver<Incoming>,
     ) -> Result<(), anyhow::Error> {
         let feed = Arc::clone(&bsky_request_handler.feed);
+        let thread = Arc::clone(&bsky_request_handler.thread);
+        let notifications = Arc::clone(&bsky_request_handler.notifications);
         let receiver = self
             .nvim
             .session
             .start_event_loop_channel_handler(bsky_request_handler);
-        self.update_timeline(None).await?;
+        std::thread::spawn(move || {
+            for (event, values) in receiver {
+                if sender.send(Incoming::Event(event, values)).is_err() {
+                    return;
+                }
+            }
+            let _ = sender.send(Incoming::Closed);
+        });
+        self.update_timeline().await?;
         self.update_feed(feed.clone()).await?;
-        for (event, values) in receiver {
+        for message in incoming {
+            let (event, values) = match message {
+                Incoming::Event(event, values) => (event, values),
+                Incoming::Alerts => {
+                    let result = self.notify_alerts().await;
+                    self.report(result);
+                    continue;
+                }
+                Incoming::Closed => break,
+            };
             info!("Received rpcevent: {:?}, values: {:?}", event, values);
             match Messages::from(event) {
                 Messages::Read => {
@@ -197,42 +500,192 @@ impl EventHandler {
                             result_string
                         );
 
-                        self.runner
-                            ._create_post(crate::commands::CreatePostArgs {
-                                text: result_string,
-                                images: vec![],
-                            })
-                            .await?;
+                        // The buffer is Markdown, a post it cannot convert is reported
+                        // back instead of stopping the handler. A buffer too long for a
+                        // single post is sent as a numbered thread.
+                        let result = self
+                            .runner
+                            ._create_thread(CreatePostArgs::markdown(result_string), true, None)
+                            .await
+                            .map(|uris| info!("posted {} posts: {:?}", uris.len(), uris));
+                        self.report(result);
                     }
                 }
                 Messages::Update => {
                     // args: values[0] contains the first cid from that neovim sends
-                    self.update_timeline(None).await?;
+                    self.update_timeline().await?;
                     self.clean_feed(feed.clone()).await?;
                     self.update_feed(feed.clone()).await?;
                 }
                 Messages::RePost => {
-                    error!("Uninmplemented");
+                    // args: values[0] contains the cid or the at-uri of the post
+                    match values.first().and_then(|v| v.as_str()) {
+                        Some(post) => {
+                            let result = repost(&self.db, &self.runner, post).await.map(|_| ());
+                            self.report(result);
+                            let result = self.update_feed(feed.clone()).await;
+                            self.report(result);
+                        }
+                        None => error!("repost called with no post"),
+                    }
+                }
+                Messages::UnRepost => {
+                    // args: values[0] contains the cid or the at-uri of the post
+                    match values.first().and_then(|v| v.as_str()) {
+                        Some(post) => {
+                            let result = unrepost(&self.db, &self.runner, post).await.map(|_| ());
+                            self.report(result);
+                            let result = self.update_feed(feed.clone()).await;
+                            self.report(result);
+                        }
+                        None => error!("unrepost called with no post"),
+                    }
+                }
+                Messages::Quote => {
+                    // args: values[0] contains the cid or the at-uri of the quoted post,
+                    // values[1] the lines of the text
+                    match (
+                        values.first().and_then(|v| v.as_str()),
+                        values.get(1).and_then(|v| v.as_array()),
+                    ) {
+                        (Some(post), Some(lines)) => {
+                            let text: Vec<&str> = lines.iter().filter_map(|l| l.as_str()).collect();
+                            let quoted = resolve_post(&self.db, &self.runner, post).await;
+                            let quoted = match quoted {
+                                Ok(quoted) => quoted,
+                                Err(e) => {
+                                    self.report(Err(e));
+                                    continue;
+                                }
+                            };
+                            info!("quoting {} with: \n{}", quoted.uri, text.join("\n"));
+                            let result = self
+                                .runner
+                                ._create_post(CreatePostArgs {
+                                    quote: Some(quoted.uri),
+                                    ..CreatePostArgs::markdown(text.join("\n"))
+                                })
+                                .await;
+                            self.report(result);
+                        }
+                        _ => error!("quote called with no post or no content"),
+                    }
+                }
+                Messages::Reply => {
+                    // args: values[0] contains the cid or the at-uri of the parent post,
+                    // values[1] the lines of the text
+                    match (
+                        values.first().and_then(|v| v.as_str()),
+                        values.get(1).and_then(|v| v.as_array()),
+                    ) {
+                        (Some(parent), Some(lines)) => {
+                            let text: Vec<&str> = lines.iter().filter_map(|l| l.as_str()).collect();
+                            let reply = resolve_reply(&self.db, &self.runner, parent).await;
+                            let reply = match reply {
+                                Ok(reply) => reply,
+                                Err(e) => {
+                                    self.report(Err(e));
+                                    continue;
+                                }
+                            };
+                            info!(
+                                "replying to {} with: \n{}",
+                                reply.parent.uri,
+                                text.join("\n")
+                            );
+                            let result = self
+                                .runner
+                                ._create_reply(CreatePostArgs::markdown(text.join("\n")), reply)
+                                .await;
+                            self.report(result);
+                        }
+                        _ => error!("reply called with no parent or no content"),
+                    }
                 }
                 Messages::Like => {
-                    error!("Uninmplemented");
+                    // args: values[0] contains the cid or the at-uri of the post
+                    match values.first().and_then(|v| v.as_str()) {
+                        Some(post) => {
+                            let result = like(&self.db, &self.runner, post).await.map(|_| ());
+                            self.report(result);
+                            let result = self.update_feed(feed.clone()).await;
+                            self.report(result);
+                        }
+                        None => error!("like called with no post"),
+                    }
                 }
                 Messages::UnLike => {
-                    error!("Uninmplemented");
+                    // args: values[0] contains the cid or the at-uri of the post
+                    match values.first().and_then(|v| v.as_str()) {
+                        Some(post) => {
+                            let result = unlike(&self.db, &self.runner, post).await.map(|_| ());
+                            self.report(result);
+                            let result = self.update_feed(feed.clone()).await;
+                            self.report(result);
+                        }
+                        None => error!("unlike called with no post"),
+                    }
                 }
                 Messages::FetchMore => {
                     // args: values[0] contains the last cid from that neovim sends
-                    self.fetch_more(values[0].to_string(), feed.clone()).await?;
+                    let cid = values[0].as_str().unwrap_or_default().to_string();
+                    self.fetch_more(cid, feed.clone()).await?;
                 }
                 Messages::Refresh => {
                     // args: values[0] contains the first cid from that neovim sends
-                    if values.is_empty() {
-                        let now = chrono::offset::Local::now().to_rfc3339();
-                        self.update_timeline(Some(now)).await?;
-                    }
+                    self.update_timeline().await?;
                     self.clean_feed(feed.clone()).await?;
                     self.update_feed(feed.clone()).await?;
                 }
+                Messages::Filter => {
+                    // args: values[0] contains the filter as a json object, none clears it,
+                    // an invalid filter is reported and the previous one kept
+                    let filter = match parse_filter(values.first()) {
+                        Ok(filter) => filter,
+                        Err(e) => {
+                            self.report(Err(e));
+                            continue;
+                        }
+                    };
+                    info!("filtering the timeline with {:?}", filter);
+                    match self.filter.lock() {
+                        Ok(mut current) => *current = filter,
+                        Err(_) => error!("Unable to acquire the filter lock"),
+                    }
+                    self.clean_feed(feed.clone()).await?;
+                    let result = self.update_feed(feed.clone()).await;
+                    self.report(result);
+                }
+                Messages::Search => {
+                    // args: values[0] contains the words to search for
+                    match values.first().and_then(|v| v.as_str()) {
+                        Some(query) => {
+                            let result = self.search(query.to_string(), feed.clone()).await;
+                            self.report(result);
+                        }
+                        None => error!("search called with no query"),
+                    }
+                }
+                Messages::Thread => {
+                    // args: values[0] contains the cid or the at-uri of the post to show
+                    // the thread of
+                    match values.first().and_then(|v| v.as_str()) {
+                        Some(post) => {
+                            let result = self.load_thread(post.to_string(), thread.clone()).await;
+                            self.report(result);
+                        }
+                        None => error!("thread called with no post"),
+                    }
+                }
+                Messages::Validate => {
+                    error!("validate is a request, use rpcrequest");
+                }
+                Messages::Notifications => {
+                    // args: values[0] is true to only load the unread notifications
+                    let unread = values.first().and_then(|v| v.as_bool()).unwrap_or(false);
+                    self.load_notifications(unread, notifications.clone())
+                        .await?;
+                }
                 Messages::Unknown(event) => {
                     error!("Uninmplemented {}", event);
                 }
@@ -241,6 +694,24 @@ impl EventHandler {
         Ok(())
     }
 
+    // The filter the timeline is read with
+    fn filter(&self) -> TimelineFilter {
+        match self.filter.lock() {
+            Ok(filter) => filter.clone(),
+            Err(e) => e.into_inner().clone(),
+        }
+    }
+
+    // Shows the error of a failed action in neovim
+    fn report(&mut self, result: Result<(), anyhow::Error>) {
+        if let Err(e) = result {
+            error!("{:?}", e);
+            if let Err(e) = self.nvim.err_writeln(&format!("bsky: {}", e)) {
+                error!("unable to report the error to neovim: {:?}", e);
+            }
+        }
+    }
+
     async fn clean_feed(
         &mut self,
         feed: Arc<std::sync::Mutex<Option<Vec<FeedViewPostFlat>>>>,
@@ -290,8 +761,12 @@ impl EventHandler {
         Ok(())
     }
 
-    pub async fn refresh_from_cid(&mut self, cid: String) -> Result<(), anyhow::Error> {
-        info!("refresh from cid {cid} feed");
+    pub async fn fetch_more(
+        &mut self,
+        cid: String,
+        feed: Arc<std::sync::Mutex<Option<Vec<FeedViewPostFlat>>>>,
+    ) -> Result<(), anyhow::Error> {
+        info!("fetch more data into the feed handler");
         let db_lock = self.db.lock().await;
         let db = &db_lock.db;
         let _ = db.use_ns("bsky").use_db("timeline").await;
@@ -299,219 +774,92 @@ impl EventHandler {
         let cid_created_at: String = Querier::new(db.clone())
             .select_created_at(cid.as_str())
             .await?;
-
-        let new_cursor_time = chrono::DateTime::parse_from_rfc3339(&cid_created_at)?
-            .checked_add_signed(
-                chrono::Duration::try_minutes(10).expect("Unable to convert to minutes"),
-            )
-            .expect("Time calculation error")
-            .to_rfc3339();
-
-        info!(
-            "fetching timeline with new cursor at: {:?}",
-            new_cursor_time
-        );
-
-        let timeline = self
-            .runner
-            ._get_timeline(GetTimelineArgs {
-                algorithm: String::from("reverse-chronological"),
-                cursor: Some(new_cursor_time),
-                limit: 10,
-            })
-            .await;
-
-        let count_newer_than: i32 = Querier::new(db.clone())
-            .count_posts_newer_than(cid_created_at.as_str())
-            .await?;
-
-        info!(
-            "cid: {}, date: {}, count newer than: {}",
-            cid.as_str(),
-            cid_created_at,
-            count_newer_than,
-        );
-
-        match timeline {
-            Ok(data) => {
-                trace!("read timeline {:?}", data);
-                let write_res = db_lock
-                    .store_timeline(data.clone(), String::from("default"))
-                    .await;
-                match write_res {
-                    Ok(res) => {
-                        info!("data written successfully: {:?}", res)
-                    }
-                    Err(e) => error!("error while fetching data {:?}", e),
-                }
-            }
-            Err(e) => {
-                error!("error while fetching data {:?}", e);
-            }
+        drop(db_lock);
+        let want = self.backfill.page_size as i32;
+        let lower_limit = backfill(
+            &self.db,
+            &self.runner,
+            &self.timeline,
+            cid_created_at.as_str(),
+            want,
+            &self.backfill,
+        )
+        .await?;
+        let mut range = TimelineFilter::new().created_before(cid_created_at);
+        if let Some(lower_limit) = lower_limit {
+            range = range.created_since(lower_limit);
         }
+        let filter = self.filter().merge(range);
+        let db_lock = self.db.lock().await;
+        let more: Vec<FeedViewPostFlat> = db_lock
+            .read_timeline(self.timeline.name(), filter, Some(want))
+            .await?;
         drop(db_lock);
-        Ok(())
+        let more_feed_len = more.len();
+        info!("merging {more_feed_len} more items into the feed");
+        EventHandler::merge_feed(feed, more).await
     }
 
-    pub async fn fetch_more(
+    // Replaces the feed sent back to neovim with the stored posts matching query,
+    // the next update or refresh brings the timeline back
+    pub async fn search(
         &mut self,
-        cid: String,
+        query: String,
         feed: Arc<std::sync::Mutex<Option<Vec<FeedViewPostFlat>>>>,
     ) -> Result<(), anyhow::Error> {
-        info!("fetch more data into the feed handler");
         let db_lock = self.db.lock().await;
-        let db = &db_lock.db;
-        let _ = db.use_ns("bsky").use_db("timeline").await;
-
-        let iteration_timeframe = 30;
-        let cid_created_at: String = Querier::new(db.clone())
-            .select_created_at(cid.as_str())
-            .await?;
-        let mut current_count: i32 = 0;
-        let upper_limit_cursor: String = cid_created_at.clone();
-        let mut cursor: String = cid_created_at.clone();
-        let mut lower_limit_cursor = chrono::DateTime::parse_from_rfc3339(cid_created_at.as_str())?
-            .checked_sub_signed(
-                chrono::Duration::try_minutes(iteration_timeframe)
-                    .expect("Unable to convert to minutes"),
-            )
-            .expect("Time calculation error")
-            .to_rfc3339();
-
-        while current_count < 11 {
-            let timeframe_count: i32 = Querier::new(db.clone())
-                .count_recent_posts_older_than(
-                    upper_limit_cursor.as_str(),
-                    lower_limit_cursor.as_str(),
-                )
-                .await?;
+        let results = db_lock.search_posts(query, 50).await?;
+        drop(db_lock);
+        let found: Vec<FeedViewPostFlat> =
+            results.into_iter().map(FeedViewPostFlat::from).collect();
+        self.clean_feed(feed.clone()).await?;
+        EventHandler::merge_feed(feed, found).await
+    }
 
-            current_count = timeframe_count;
-            info!(
-                "cid: {}, date: {}, count older than: {}; result number: {} ",
-                cid.as_str(),
-                cid_created_at,
-                current_count,
-                lower_limit_cursor,
-            );
-            if timeframe_count > 11 {
-                let more: Vec<FeedViewPostFlat> = db_lock
-                    .read_timeline(
-                        String::from("default"),
-                        Some(format!(
-                            "post.record.createdAt <= '{}' and post.record.createdAt > '{}'",
-                            upper_limit_cursor, lower_limit_cursor
-                        )),
-                        None,
-                    )
-                    .await?;
-                // EventHandler::merge_feed(feed.clone(), more).await?;
-                let locked = feed.lock();
-                match locked {
-                    Ok(mut l) => {
-                        info!("nvim_feed_lock Acquired Lock Updating Data");
-                        if let Some(ref mut existing_feed) = *l {
-                            let existing_feed_len = existing_feed.len();
-                            let more_feed_len = more.len();
-                            info!("merging existing feed: {existing_feed_len} with more items {more_feed_len}");
-                            for item in more.clone() {
-                                if !existing_feed.contains(&item) {
-                                    existing_feed.push(item);
-                                }
-                            }
-                            let existing_feed_len = existing_feed.len();
-                            info!("existing feed merged: {existing_feed_len}");
-                        } else {
-                            *l = Some(more.clone());
-                        }
-                        info!("nvim_feed_lock Droping Lock");
-                        drop(l);
-                        return Ok(());
-                    }
-                    Err(e) => {
-                        error!("error while fetching data {:?}", e);
-                    }
-                };
-            } else {
-                cursor = lower_limit_cursor;
-
-                lower_limit_cursor = chrono::DateTime::parse_from_rfc3339(&cursor)?
-                    .checked_sub_signed(
-                        chrono::Duration::try_minutes(iteration_timeframe)
-                            .expect("Unable to convert to minutes"),
-                    )
-                    .expect("Time calculation error")
-                    .to_rfc3339();
-                info!("fetching timeline with new cursor at: {:?}", cursor);
-                let timeline = self
-                    .runner
-                    ._get_timeline(GetTimelineArgs {
-                        algorithm: String::from("reverse-chronological"),
-                        cursor: Some(cursor.to_string()),
-                        limit: 10,
-                    })
-                    .await;
-                match timeline {
-                    Ok(data) => {
-                        trace!("read timeline {:?}", data);
-                        let write_res = db_lock
-                            .store_timeline(data.clone(), String::from("default"))
-                            .await;
-                        match write_res {
-                            Ok(res) => {
-                                info!("data written successfully: {:?}", res)
-                            }
-                            Err(e) => error!("error while fetching data {:?}", e),
-                        }
-                    }
-                    Err(e) => {
-                        error!("error while fetching data {:?}", e);
-                    }
-                }
+    // Fetches the thread of post, stores its posts and replaces the thread
+    // returned by the thread request
+    pub async fn load_thread(
+        &mut self,
+        post: String,
+        thread: Arc<std::sync::Mutex<Option<Vec<ThreadLine>>>>,
+    ) -> Result<(), anyhow::Error> {
+        let lines = fetch_thread(&self.db, &self.runner, &post).await?;
+        match thread.lock() {
+            Ok(mut l) => {
+                *l = Some(lines);
+                Ok(())
             }
+            Err(_) => Err(anyhow::Error::msg("Unable to acquire the thread lock")),
         }
-        // TODO: This is a dirty fix for a the condition
-        if current_count >= 11 {
-            let more: Vec<FeedViewPostFlat> = db_lock
-                .read_timeline(
-                    String::from("default"),
-                    Some(format!(
-                        "post.record.createdAt <= '{}' and post.record.createdAt > '{}'",
-                        upper_limit_cursor, lower_limit_cursor
-                    )),
-                    None,
-                )
-                .await?;
-            // EventHandler::merge_feed(feed.clone(), more).await?;
-            let locked = feed.lock();
-            match locked {
-                Ok(mut l) => {
-                    info!("nvim_feed_lock Acquired Lock Updating Data");
-                    if let Some(ref mut existing_feed) = *l {
-                        let existing_feed_len = existing_feed.len();
-                        let more_feed_len = more.len();
-                        info!("merging existing feed: {existing_feed_len} with more items {more_feed_len}");
-                        for item in more.clone() {
-                            if !existing_feed.contains(&item) {
-                                existing_feed.push(item);
-                            }
-                        }
-                        let existing_feed_len = existing_feed.len();
-                        info!("existing feed merged: {existing_feed_len}");
-                    } else {
-                        *l = Some(more.clone());
-                    }
-                    info!("nvim_feed_lock Droping Lock");
-                    drop(l);
-                    return Ok(());
-                }
-                Err(e) => {
-                    error!("error while fetching data {:?}", e);
-                }
-            };
+    }
+
+    // Stores the new notifications and replaces the ones returned by the
+    // notifications request
+    pub async fn load_notifications(
+        &mut self,
+        unread: bool,
+        notifications: Arc<std::sync::Mutex<Option<NotificationsView>>>,
+    ) -> Result<(), anyhow::Error> {
+        if let Err(e) = sync_notifications(&self.db, &self.runner, 10).await {
+            error!("error while syncing notifications {:?}", e);
         }
+        let db_lock = self.db.lock().await;
+        let view = read_notifications(&db_lock, 50, unread).await?;
         drop(db_lock);
-        Ok(())
+        info!(
+            "loaded {} notification groups, {} unread",
+            view.groups.len(),
+            view.unread
+        );
+        match notifications.lock() {
+            Ok(mut l) => {
+                *l = Some(view);
+                Ok(())
+            }
+            Err(_) => Err(anyhow::Error::msg(
+                "Unable to acquire the notifications lock",
+            )),
+        }
     }
 
     // This function updates the feed that is sent back to neovim
@@ -522,7 +870,7 @@ impl EventHandler {
         trace!("updating read handler feed");
         let db_lock = self.db.lock().await;
         let cached_feed: Vec<FeedViewPostFlat> = db_lock
-            .read_timeline(String::from("default"), None, Some(10))
+            .read_timeline(self.timeline.name(), self.filter(), Some(10))
             .await?;
         trace!("reading the data: {:?}", cached_feed);
         let locked = feed.lock();
@@ -540,57 +888,118 @@ impl EventHandler {
         Ok(())
     }
 
-    // This function updates the timeline in the db
-    pub async fn update_timeline(
+    // This function updates the timeline in the db, it fills the gap between
+    // the newest stored post and now
+    pub async fn update_timeline(&mut self) -> Result<(), anyhow::Error> {
+        match fill_gap(&self.db, &self.runner, &self.timeline, &self.backfill).await {
+            Ok(pages) => info!("{} synced with {} pages", self.timeline, pages),
+            Err(e) => error!("error while syncing the timeline {:?}", e),
+        }
+        Ok(())
+    }
+
+    // Stores the new notifications and raises an alert for the new mentions,
+    // replies and quotes, on the main loop when the handler runs in the background
+    pub async fn raise_alerts(&mut self) -> Result<(), anyhow::Error> {
+        sync_notifications(&self.db, &self.runner, 10).await?;
+        match self.alerts.clone() {
+            Some(sender) => sender
+                .send(Incoming::Alerts)
+                .map_err(|_| anyhow::Error::msg("The main loop is closed")),
+            None => self.notify_alerts().await,
+        }
+    }
+
+    // Raises an alert for the new stored notifications through the configured
+    // notifiers, neovim only shows them from the session of the main loop
+    async fn notify_alerts(&mut self) -> Result<(), anyhow::Error> {
+        let mut notifiers: Vec<&mut dyn Notifier> = self
+            .notifiers
+            .iter_mut()
+            .map(|notifier| notifier.as_mut() as &mut dyn Notifier)
+            .collect();
+        if self.notify.nvim {
+            notifiers.push(&mut self.nvim);
+        }
+        notify_new(&self.db, &mut notifiers, &self.notify.reasons).await?;
+        Ok(())
+    }
+
+    // Adds the posts of the followed accounts to the feed sent to neovim as
+    // they are created, reconnecting when the stream drops. The posts are only
+    // stored as posts, the timeline gets them from the next refresh so that the
+    // gap before them is still filled.
+    pub async fn stream_timeline(
         &mut self,
-        mut cursor: Option<String>,
+        url: &str,
+        nvim_feed: Arc<std::sync::Mutex<Option<Vec<FeedViewPostFlat>>>>,
     ) -> Result<(), anyhow::Error> {
-        let db_lock = self.db.lock().await;
-        // TODO: This leaves the timeline stuck at a point in time
-        // I wanted to get all the data from latest_cursor to now basically
-        // But It's not yet handled well
-        match cursor {
-            Some(ref _c) => {}
-            /*
-            None => {
-                let cursor_res = db_lock.get_latest_cursor(String::from("default")).await;
-                match cursor_res {
-                    Ok(res) => {
-                        info!("found cursor at: {:?}", res);
-                        cursor = res;
+        let follows = follows(&self.runner).await?;
+        let mut cursor = None;
+        loop {
+            match Subscriber::connect(url, follows.clone(), cursor).await {
+                Ok(mut subscriber) => {
+                    loop {
+                        match subscriber.next_post().await {
+                            Ok(Some(post)) => {
+                                if let Err(e) = self.add_streamed_post(&post, &nvim_feed).await {
+                                    error!("error while adding {}: {:?}", post.uri, e);
+                                }
+                            }
+                            Ok(None) => break,
+                            Err(e) => {
+                                error!("error while reading the stream {:?}", e);
+                                break;
+                            }
+                        }
                     }
-                    Err(e) => error!("error while fetching cursor data {:?}", e),
+                    cursor = subscriber.cursor;
                 }
+                Err(e) => error!("error while connecting to {}: {:?}", url, e),
             }
-            */
-            None => {}
+            warn!(
+                "stream closed, reconnecting in {:?}",
+                STREAM_RECONNECT_DELAY
+            );
+            time::sleep(STREAM_RECONNECT_DELAY).await;
         }
+    }
 
-        let timeline = self
-            .runner
-            ._get_timeline(GetTimelineArgs {
-                algorithm: String::from("reverse-chronological"),
-                cursor,
-                limit: 10,
-            })
-            .await;
-        match timeline {
-            Ok(data) => {
-                trace!("read timeline {:?}", data);
-                let write_res = db_lock
-                    .store_timeline(data.clone(), String::from("default"))
-                    .await;
-                match write_res {
-                    Ok(res) => info!("data written successfully: {:?}", res),
-                    Err(e) => error!("error while fetching data {:?}", e),
+    // The posts of the followed accounts belong to the home timeline only, the
+    // ones the filter rejects are stored but not shown
+    async fn add_streamed_post(
+        &mut self,
+        post: &NewPost,
+        nvim_feed: &Arc<std::sync::Mutex<Option<Vec<FeedViewPostFlat>>>>,
+    ) -> Result<(), anyhow::Error> {
+        if !matches!(self.timeline, Subscription::Home) {
+            return Ok(());
+        }
+        let view = self.runner.fetch_post(&post.uri).await?;
+        let db_lock = self.db.lock().await;
+        db_lock.store_post_view(view.clone()).await?;
+        drop(db_lock);
+        if !self.filter().matches(&view) {
+            trace!("filtered out {}", post.uri);
+            return Ok(());
+        }
+        let item = FeedViewPostFlat {
+            post: view,
+            parent: None,
+            root: None,
+            highlight: None,
+        };
+        match nvim_feed.lock() {
+            Ok(mut l) => {
+                let feed = l.get_or_insert_with(Vec::new);
+                if !feed.contains(&item) {
+                    info!("streamed {}", post.uri);
+                    feed.push(item);
                 }
+                Ok(())
             }
-            Err(e) => {
-                error!("error while fetching data {:?}", e);
-            }
+            Err(_) => Err(anyhow::Error::msg("Unable to acquire the feed lock")),
         }
-        drop(db_lock);
-        Ok(())
     }
 
     pub async fn auto_refresh_timeline(
@@ -602,10 +1011,13 @@ impl EventHandler {
         loop {
             interval.tick().await;
             trace!("executed background task");
-            self.update_timeline(None).await?;
+            self.update_timeline().await?;
+            if let Err(e) = self.raise_alerts().await {
+                error!("error while raising alerts {:?}", e);
+            }
             let db_lock = self.db.lock().await;
             let data: Vec<FeedViewPostFlat> = db_lock
-                .read_timeline(String::from("default"), None, Some(10))
+                .read_timeline(self.timeline.name(), self.filter(), Some(10))
                 .await?;
             let nvim_feed_lock = nvim_feed.lock();
             match nvim_feed_lock {
@@ -637,9 +1049,18 @@ impl From<&str> for Messages {
             "post" => Messages::Post,
             "update" => Messages::Update,
             "repost" => Messages::RePost,
+            "unrepost" => Messages::UnRepost,
+            "quote" => Messages::Quote,
+            "reply" => Messages::Reply,
             "like" => Messages::Like,
+            "unlike" => Messages::UnLike,
             "more" => Messages::FetchMore,
             "refresh" => Messages::Refresh,
+            "filter" => Messages::Filter,
+            "search" => Messages::Search,
+            "thread" => Messages::Thread,
+            "validate" => Messages::Validate,
+            "notifications" => Messages::Notifications,
             _ => Messages::Unknown(event.to_string()),
         }
     }
@@ -652,9 +1073,17 @@ impl From<String> for Messages {
             "post" => Messages::Post,
             "update" => Messages::Update,
             "repost" => Messages::RePost,
+            "unrepost" => Messages::UnRepost,
+            "quote" => Messages::Quote,
+            "reply" => Messages::Reply,
             "like" => Messages::Like,
             "unlike" => Messages::UnLike,
             "more" => Messages::FetchMore,
+            "filter" => Messages::Filter,
+            "search" => Messages::Search,
+            "thread" => Messages::Thread,
+            "validate" => Messages::Validate,
+            "notifications" => Messages::Notifications,
             "" => Messages::Refresh,
             _ => Messages::Unknown(event.to_string()),
         }
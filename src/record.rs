@@ -0,0 +1,47 @@
+use anyhow::Context;
+use atrium_api::records::Record;
+
+// Parses a record given as JSON, its $type must be the collection it is
+// written to
+pub fn parse_record(collection: &str, json: &str) -> Result<Record, anyhow::Error> {
+    let value: serde_json::Value =
+        serde_json::from_str(json).with_context(|| "The record is not valid JSON")?;
+    match value.get("$type").and_then(|t| t.as_str()) {
+        Some(t) if t == collection => {}
+        Some(t) => {
+            return Err(anyhow::Error::msg(format!(
+                "The record is a {} but the URI names {}",
+                t, collection
+            )))
+        }
+        None => return Err(anyhow::Error::msg("The record has no $type")),
+    }
+    serde_json::from_value(value).with_context(|| format!("Invalid {} record", collection))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_must_match_their_collection() {
+        let profile = r#"{"$type": "app.bsky.actor.profile", "displayName": "Alice"}"#;
+        match parse_record("app.bsky.actor.profile", profile) {
+            Ok(Record::AppBskyActorProfile(profile)) => {
+                assert_eq!(profile.display_name.as_deref(), Some("Alice"))
+            }
+            other => panic!("expected a profile, got {:?}", other),
+        }
+        assert!(parse_record("app.bsky.feed.post", profile).is_err());
+        assert!(parse_record("app.bsky.actor.profile", r#"{"displayName": "Alice"}"#).is_err());
+        // A threadgate needs the post it applies to
+        assert!(parse_record(
+            "app.bsky.feed.threadgate",
+            r#"{"$type": "app.bsky.feed.threadgate", "createdAt": "2024-03-01T10:00:00.000Z"}"#
+        )
+        .is_err());
+        assert!(
+            parse_record("com.example.unknown", r#"{"$type": "com.example.unknown"}"#).is_err()
+        );
+    }
+}
@@ -0,0 +1,110 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::Context;
+use atrium_api::records::Record;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::car::{count_collections, Repo};
+use crate::commands::RepoExportArgs;
+use crate::runner::Runner;
+use crate::surreal::SurrealDB;
+
+// Records written to the local database by an import
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct ImportSummary {
+    pub did: String,
+    pub posts: usize,
+    pub likes: usize,
+    pub follows: usize,
+    // Records of the other collections
+    pub skipped: usize,
+}
+
+// Downloads the repository of an account, checks it and writes it to
+// args.output. Returns the number of records per collection.
+pub async fn export_repo(
+    runner: &Runner,
+    args: RepoExportArgs,
+) -> Result<BTreeMap<String, usize>, anyhow::Error> {
+    let did = runner.resolve_actor(args.actor).await?;
+    let bytes = runner._get_repo(did.clone()).await?;
+    let repo = Repo::from_car(&bytes).with_context(|| format!("Invalid repository {:?}", did))?;
+    if repo.commit.did != did.as_str() {
+        return Err(anyhow::Error::msg(format!(
+            "Asked for the repository of {:?}, got {}",
+            did, repo.commit.did
+        )));
+    }
+    tokio::fs::write(&args.output, &bytes)
+        .await
+        .with_context(|| format!("Unable to write {:?}", args.output))?;
+    info!(
+        "exported {} records at rev {} to {:?}",
+        repo.records.len(),
+        repo.commit.rev,
+        args.output
+    );
+    Ok(count_collections(&repo))
+}
+
+// Reads the posts, likes and follows of a CAR file into the repo_post, like
+// and follow tables
+pub async fn import_repo(db: &SurrealDB, path: &Path) -> Result<ImportSummary, anyhow::Error> {
+    let bytes = tokio::fs::read(path)
+        .await
+        .with_context(|| format!("Unable to read {:?}", path))?;
+    let repo = Repo::from_car(&bytes).with_context(|| format!("Invalid repository {:?}", path))?;
+    let did = repo.commit.did;
+    let mut summary = ImportSummary {
+        did: did.clone(),
+        ..ImportSummary::default()
+    };
+    for record in repo.records {
+        let uri = format!("at://{}/{}/{}", did, record.collection, record.rkey);
+        let cid = record.cid.to_string();
+        match serde_json::from_value::<Record>(record.value) {
+            Ok(Record::AppBskyFeedPost(post)) => {
+                let record = serde_json::to_value(Record::AppBskyFeedPost(post))?;
+                db.store_repo_post(uri, cid, did.clone(), record).await?;
+                summary.posts += 1;
+            }
+            Ok(Record::AppBskyFeedLike(like)) => {
+                db.store_repo_subject(
+                    "like",
+                    uri,
+                    cid,
+                    did.clone(),
+                    serde_json::to_value(&like.subject)?,
+                    serde_json::to_string(&like.created_at)?
+                        .trim_matches('"')
+                        .to_string(),
+                )
+                .await?;
+                summary.likes += 1;
+            }
+            Ok(Record::AppBskyGraphFollow(follow)) => {
+                db.store_repo_subject(
+                    "follow",
+                    uri,
+                    cid,
+                    did.clone(),
+                    serde_json::to_value(&follow.subject)?,
+                    serde_json::to_string(&follow.created_at)?
+                        .trim_matches('"')
+                        .to_string(),
+                )
+                .await?;
+                summary.follows += 1;
+            }
+            Ok(_) => summary.skipped += 1,
+            Err(e) => {
+                warn!("skipping {}: {}", uri, e);
+                summary.skipped += 1;
+            }
+        }
+    }
+    info!("imported {:?}", summary);
+    Ok(summary)
+}
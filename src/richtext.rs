@@ -0,0 +1,197 @@
+use atrium_api::app::bsky::richtext::facet;
+use atrium_api::types::string::Handle;
+use log::warn;
+use regex::Regex;
+
+use crate::runner::Runner;
+
+const MENTION: &str = r"(?:^|[\s(])@((?:[a-zA-Z0-9](?:[a-zA-Z0-9-]{0,61}[a-zA-Z0-9])?\.)+[a-zA-Z](?:[a-zA-Z0-9-]{0,61}[a-zA-Z0-9])?)";
+const LINK: &str = r"(?:^|[\s(])(https?://[^\s]+)";
+const TAG: &str = r"(?:^|\s)[#＃]([^\s#＃]+)";
+// Punctuation that ends a sentence rather than a link or a tag
+const TRAILING: &[char] = &['.', ',', ';', ':', '!', '?', '"', '\''];
+const MAX_TAG_LEN: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SpanKind {
+    // The handle without the @, resolved to a DID before posting
+    Mention(String),
+    Link(String),
+    // The tag without the #
+    Tag(String),
+}
+
+// A range of the text to annotate, start and end are UTF-8 byte offsets, the
+// start is inclusive and the end exclusive as in app.bsky.richtext.facet
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub kind: SpanKind,
+}
+
+fn trim_trailing(s: &str) -> &str {
+    let mut s = s.trim_end_matches(TRAILING);
+    // A closing parenthesis belongs to the link only when it opened one
+    while s.ends_with(')') && s.matches('(').count() < s.matches(')').count() {
+        s = s[..s.len() - 1].trim_end_matches(TRAILING);
+    }
+    s
+}
+
+// Returns the mentions, links and hashtags of the text ordered by position
+pub fn detect(text: &str) -> Vec<Span> {
+    let mut spans = Vec::new();
+    let mention = Regex::new(MENTION).expect("valid mention regex");
+    for captures in mention.captures_iter(text) {
+        let handle = captures.get(1).expect("handle group");
+        spans.push(Span {
+            // The span includes the @
+            start: handle.start() - 1,
+            end: handle.end(),
+            kind: SpanKind::Mention(handle.as_str().to_string()),
+        });
+    }
+    let link = Regex::new(LINK).expect("valid link regex");
+    for captures in link.captures_iter(text) {
+        let uri = captures.get(1).expect("uri group");
+        let trimmed = trim_trailing(uri.as_str());
+        if trimmed.len() <= "https://".len() {
+            continue;
+        }
+        spans.push(Span {
+            start: uri.start(),
+            end: uri.start() + trimmed.len(),
+            kind: SpanKind::Link(trimmed.to_string()),
+        });
+    }
+    let tag = Regex::new(TAG).expect("valid tag regex");
+    for captures in tag.captures_iter(text) {
+        let name = captures.get(1).expect("tag group");
+        let trimmed = trim_trailing(name.as_str()).trim_end_matches(')');
+        // Numbers alone are not tags, e.g. issue #1
+        if trimmed.is_empty()
+            || trimmed.chars().count() > MAX_TAG_LEN
+            || trimmed.chars().all(|c| c.is_ascii_digit())
+        {
+            continue;
+        }
+        // The # itself is one or three bytes wide
+        let hash = text[..name.start()]
+            .chars()
+            .next_back()
+            .map_or(1, char::len_utf8);
+        spans.push(Span {
+            start: name.start() - hash,
+            end: name.start() + trimmed.len(),
+            kind: SpanKind::Tag(trimmed.to_string()),
+        });
+    }
+    spans.sort_by_key(|span| span.start);
+    spans
+}
+
+// Returns the facets of the text, None when there is nothing to annotate
+pub async fn facets(
+    runner: &Runner,
+    text: &str,
+) -> Result<Option<Vec<facet::Main>>, anyhow::Error> {
+    resolve(runner, detect(text)).await
+}
+
+// Returns the facets of the spans, mentions of handles that do not resolve are
+// left as plain text
+pub async fn resolve(
+    runner: &Runner,
+    spans: Vec<Span>,
+) -> Result<Option<Vec<facet::Main>>, anyhow::Error> {
+    let mut facets = Vec::new();
+    for span in spans {
+        let feature = match span.kind {
+            SpanKind::Mention(handle) => {
+                let did = match Handle::new(handle.clone()) {
+                    Ok(handle) => runner._resolve_handle(handle).await,
+                    Err(e) => Err(anyhow::Error::msg(e)),
+                };
+                match did {
+                    Ok(did) => facet::MainFeaturesItem::Mention(Box::new(facet::Mention { did })),
+                    Err(e) => {
+                        warn!("not linking the mention of {}: {}", handle, e);
+                        continue;
+                    }
+                }
+            }
+            SpanKind::Link(uri) => facet::MainFeaturesItem::Link(Box::new(facet::Link { uri })),
+            SpanKind::Tag(tag) => facet::MainFeaturesItem::Tag(Box::new(facet::Tag { tag })),
+        };
+        facets.push(facet::Main {
+            features: vec![feature],
+            index: facet::ByteSlice {
+                byte_end: span.end,
+                byte_start: span.start,
+            },
+        });
+    }
+    if facets.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(facets))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slices(text: &str) -> Vec<(&str, SpanKind)> {
+        detect(text)
+            .into_iter()
+            .map(|span| (&text[span.start..span.end], span.kind))
+            .collect()
+    }
+
+    #[test]
+    fn byte_ranges_skip_emoji_and_multibyte_characters() {
+        let text = "🦀 café @alice.bsky.social → https://example.com/ß?q=1. #rust日本 #42";
+        assert_eq!(
+            slices(text),
+            vec![
+                (
+                    "@alice.bsky.social",
+                    SpanKind::Mention(String::from("alice.bsky.social"))
+                ),
+                (
+                    "https://example.com/ß?q=1",
+                    SpanKind::Link(String::from("https://example.com/ß?q=1"))
+                ),
+                ("#rust日本", SpanKind::Tag(String::from("rust日本"))),
+            ]
+        );
+    }
+
+    #[test]
+    fn sentence_punctuation_is_not_part_of_the_span() {
+        let text = "(see https://example.com/a_(b)) or @bob.test, #done!";
+        assert_eq!(
+            slices(text),
+            vec![
+                (
+                    "https://example.com/a_(b)",
+                    SpanKind::Link(String::from("https://example.com/a_(b)"))
+                ),
+                ("@bob.test", SpanKind::Mention(String::from("bob.test"))),
+                ("#done", SpanKind::Tag(String::from("done"))),
+            ]
+        );
+        assert!(detect("mail me at alice@example.com").is_empty());
+    }
+
+    #[test]
+    fn fullwidth_hash_is_part_of_the_tag() {
+        let text = "✨ ＃bluesky";
+        let spans = detect(text);
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].start, "✨ ".len());
+        assert_eq!(&text[spans[0].start..spans[0].end], "＃bluesky");
+    }
+}
@@ -1,19 +1,34 @@
+use crate::card::{fetch_card, Fetcher, HttpFetcher};
 use crate::commands::{
-    ActorArgs, Command, CreatePostArgs, GetAuthorFeedArgs, GetCidDidArgs, GetCidUriArgs,
-    GetTimelineArgs, ListNotificationsArgs, LoginArgs, UriArgs, UriArgsU16, UriListArgs,
+    ActorArgs, AtUri, Command, CreatePostArgs, GetAuthorFeedArgs, GetCidDidArgs, GetCidUriArgs,
+    GetRecordArgs, GetTimelineArgs, ListNotificationsArgs, ListRecordsArgs, LoginArgs,
+    PutRecordArgs, RecordArgs, UriArgs, UriArgsU16, UriListArgs,
 };
+use crate::composer;
+use crate::config::Config;
+use crate::lang::post_langs;
+use crate::markdown;
+use crate::media;
+use crate::record::parse_record;
+use crate::richtext::{detect, facets, resolve, Span};
 use crate::store::SimpleJsonFileSessionStore;
+use crate::validate::validate;
 use anyhow::{Context, Result};
 use atrium_api::agent::{store::SessionStore, AtpAgent};
 use atrium_api::app::bsky::actor;
+use atrium_api::app::bsky::embed;
 use atrium_api::app::bsky::feed;
+use atrium_api::app::bsky::feed::post::{RecordEmbedEnum, ReplyRef};
 use atrium_api::app::bsky::graph;
 use atrium_api::app::bsky::notification;
-use atrium_api::types::string::{AtIdentifier, Datetime, Handle};
-use atrium_api::types::{LimitedNonZeroU8, LimitedU16};
+use atrium_api::app::bsky::richtext::facet;
+use atrium_api::com::atproto::repo::strong_ref::Main as StrongRef;
+use atrium_api::types::string::{AtIdentifier, Datetime, Did, Handle, Language};
+use atrium_api::types::{BlobRef, LimitedNonZeroU8, LimitedU16, TypedBlobRef};
 use atrium_xrpc_client::reqwest::ReqwestClient;
-use log::{error, info};
+use log::{error, info, warn};
 use std::ffi::OsStr;
+use std::num::NonZeroU64;
 use std::path::PathBuf;
 use tokio::fs::{create_dir_all, File};
 use tokio::io::AsyncReadExt;
@@ -24,15 +39,23 @@ pub struct Runner {
     session_path: PathBuf,
     config_dir: PathBuf,
     handle: Option<Handle>,
+    fetcher: Box<dyn Fetcher + Send + Sync>,
+    config: Config,
 }
 
 impl Runner {
     pub async fn new(pds_host: String, debug: bool) -> Result<Self> {
         let config_dir = dirs::config_dir()
             .with_context(|| format!("No config dir: {:?}", dirs::config_dir()))?;
+        Runner::open(pds_host, debug, config_dir).await
+    }
+
+    // Reads the session and the settings from config_dir/bsky
+    pub async fn open(pds_host: String, debug: bool, config_dir: PathBuf) -> Result<Self> {
         let dir = config_dir.join("bsky");
         create_dir_all(&dir).await?;
         let session_path = dir.join("session.json");
+        let config = Config::load(&dir.join("config.json")).await?;
         let store = SimpleJsonFileSessionStore::new(session_path.clone());
         let session = store.get_session().await;
         let handle = session.as_ref().map(|s| s.handle.clone());
@@ -46,9 +69,25 @@ impl Runner {
             config_dir,
             session_path,
             handle,
+            fetcher: Box::new(HttpFetcher::new()?),
+            config,
         })
     }
 
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    // Replaces the fetcher used to build link cards
+    pub fn with_fetcher(mut self, fetcher: Box<dyn Fetcher + Send + Sync>) -> Self {
+        self.fetcher = fetcher;
+        self
+    }
+
+    pub fn handle(&self) -> Option<Handle> {
+        self.handle.clone()
+    }
+
     // TODO: Check if this reads the stored session
     pub async fn _login(&self, args: LoginArgs) -> Result<()> {
         match (args.from_env, args.identifier, args.password) {
@@ -264,7 +303,7 @@ impl Runner {
                     .actor
                     .or(self.handle.clone().map(AtIdentifier::Handle))
                     .with_context(|| "Not logged in")?,
-                cursor: None,
+                cursor: args.cursor,
                 limit: Some(limit),
             })
             .await?)
@@ -363,6 +402,27 @@ impl Runner {
             .await?)
     }
 
+    // Returns the repository of did as a CAR file
+    pub async fn _get_repo(&self, did: Did) -> Result<Vec<u8>, anyhow::Error> {
+        Ok(self
+            .agent
+            .api
+            .com
+            .atproto
+            .sync
+            .get_repo(atrium_api::com::atproto::sync::get_repo::Parameters { did, since: None })
+            .await?)
+    }
+
+    // Returns the DID of actor, of the logged in account when it is None
+    pub async fn resolve_actor(&self, actor: Option<AtIdentifier>) -> Result<Did, anyhow::Error> {
+        match actor.or(self.handle.clone().map(AtIdentifier::Handle)) {
+            Some(AtIdentifier::Did(did)) => Ok(did),
+            Some(AtIdentifier::Handle(handle)) => self._resolve_handle(handle).await,
+            None => Err(anyhow::Error::msg("Not logged in")),
+        }
+    }
+
     pub async fn _list_notifications(
         &self,
         args: ListNotificationsArgs,
@@ -378,45 +438,277 @@ impl Runner {
                 atrium_api::app::bsky::notification::list_notifications::Parameters {
                     cursor: args.cursor,
                     limit: Some(limit),
-                    seen_at: Some(args.seen_at),
+                    seen_at: args.seen_at,
                 },
             )
             .await?)
     }
 
-    // TODO: Reword this function to make create post args more flexible
+    pub async fn _update_seen(&self, seen_at: Datetime) -> Result<(), anyhow::Error> {
+        self.agent
+            .api
+            .app
+            .bsky
+            .notification
+            .update_seen(atrium_api::app::bsky::notification::update_seen::Input { seen_at })
+            .await?;
+        Ok(())
+    }
+
+    pub async fn _get_unread_count(
+        &self,
+    ) -> Result<notification::get_unread_count::Output, anyhow::Error> {
+        Ok(self
+            .agent
+            .api
+            .app
+            .bsky
+            .notification
+            .get_unread_count(
+                atrium_api::app::bsky::notification::get_unread_count::Parameters { seen_at: None },
+            )
+            .await?)
+    }
+
+    // Returns the current view of the post at uri
+    pub async fn fetch_post(&self, uri: &str) -> Result<feed::defs::PostView, anyhow::Error> {
+        let output = self
+            ._get_post(UriListArgs {
+                uri: vec![uri.to_string()],
+            })
+            .await?;
+        output
+            .posts
+            .into_iter()
+            .next()
+            .with_context(|| format!("Post not found: {}", uri))
+    }
+
+    // Posts args, args.reply_to is resolved by the caller, see _create_reply
     pub async fn _create_post(&self, args: CreatePostArgs) -> Result<(), anyhow::Error> {
+        self.publish(args, None, None).await?;
+        Ok(())
+    }
+
+    // Posts args as a reply, the references are built by the caller
+    pub async fn _create_reply(
+        &self,
+        args: CreatePostArgs,
+        reply: ReplyRef,
+    ) -> Result<(), anyhow::Error> {
+        self.publish(args, None, Some(reply)).await?;
+        Ok(())
+    }
+
+    // Posts the text of args as a thread, see composer::split, each post replies to
+    // the previous one and the first to reply when it is set. The images and the
+    // quote are attached to the first. Markdown is converted before the split so
+    // that the posts are cut on the text as it is posted. Returns the uris of the
+    // posts.
+    pub async fn _create_thread(
+        &self,
+        args: CreatePostArgs,
+        numbered: bool,
+        mut reply: Option<ReplyRef>,
+    ) -> Result<Vec<String>, anyhow::Error> {
+        let (text, spans) = if args.markdown {
+            markdown::to_post(&args.text)?
+        } else {
+            (args.text.clone(), detect(&args.text))
+        };
+        let mut uris = Vec::new();
+        for (text, spans) in composer::split_spans(&text, &spans, numbered) {
+            let first = uris.is_empty();
+            let segment = CreatePostArgs {
+                images: if first { args.images.clone() } else { vec![] },
+                alt: if first { args.alt.clone() } else { vec![] },
+                quote: if first { args.quote.clone() } else { None },
+                // Converted already, the links are in the spans
+                markdown: false,
+                resize: args.resize,
+                no_card: args.no_card,
+                langs: args.langs.clone(),
+                detect_lang: args.detect_lang,
+                ..CreatePostArgs::markdown(text)
+            };
+            let output = self.publish(segment, Some(spans), reply.clone()).await?;
+            let posted = StrongRef {
+                cid: output.cid,
+                uri: output.uri.clone(),
+            };
+            reply = Some(ReplyRef {
+                root: reply.map_or_else(|| posted.clone(), |reply| reply.root),
+                parent: posted,
+            });
+            uris.push(output.uri);
+        }
+        Ok(uris)
+    }
+
+    // Builds the card of the page at uri, the card is kept without its
+    // thumbnail when the image cannot be used
+    async fn external(&self, uri: &str) -> Result<embed::external::Main, anyhow::Error> {
+        let card = fetch_card(self.fetcher.as_ref(), uri).await?;
+        let thumb = match &card.image {
+            Some(image) => match self.upload_thumb(image).await {
+                Ok(blob) => Some(blob),
+                Err(e) => {
+                    warn!("no thumbnail for the card of {}: {}", uri, e);
+                    None
+                }
+            },
+            None => None,
+        };
+        Ok(embed::external::Main {
+            external: embed::external::External {
+                description: card.description,
+                thumb,
+                title: card.title,
+                uri: card.uri,
+            },
+        })
+    }
+
+    async fn upload_thumb(&self, image: &str) -> Result<BlobRef, anyhow::Error> {
+        let prepared = media::prepare(self.fetcher.fetch(image).await?, true)?;
+        let mut output = self
+            .agent
+            .api
+            .com
+            .atproto
+            .repo
+            .upload_blob(prepared.bytes)
+            .await?;
+        if let BlobRef::Typed(TypedBlobRef::Blob(blob)) = &mut output.blob {
+            blob.mime_type = prepared.mime.to_string();
+        }
+        Ok(output.blob)
+    }
+
+    // TODO: Reword this function to make create post args more flexible
+    // spans annotate args.text when the caller converted it already, they are
+    // detected in the text otherwise
+    async fn publish(
+        &self,
+        args: CreatePostArgs,
+        spans: Option<Vec<Span>>,
+        reply: Option<ReplyRef>,
+    ) -> Result<atrium_api::com::atproto::repo::create_record::Output, anyhow::Error> {
+        validate(&args).into_result()?;
         let mut images = Vec::new();
-        for image in &args.images {
-            if let Ok(mut file) = File::open(image).await {
-                let mut buf = Vec::new();
-                file.read_to_end(&mut buf).await.expect("read image file");
-                let output = self
-                    .agent
-                    .api
-                    .com
-                    .atproto
-                    .repo
-                    .upload_blob(buf)
-                    .await
-                    .expect("upload blob");
-                images.push(atrium_api::app::bsky::embed::images::Image {
-                    alt: image
+        for (i, image) in args.images.iter().enumerate() {
+            let mut file = File::open(image)
+                .await
+                .with_context(|| format!("open image {}", image.display()))?;
+            let mut buf = Vec::new();
+            file.read_to_end(&mut buf)
+                .await
+                .with_context(|| format!("read image {}", image.display()))?;
+            let prepared = media::prepare(buf, args.resize)
+                .with_context(|| format!("prepare image {}", image.display()))?;
+            let mut output = self
+                .agent
+                .api
+                .com
+                .atproto
+                .repo
+                .upload_blob(prepared.bytes)
+                .await
+                .with_context(|| format!("upload image {}", image.display()))?;
+            // The blob is described by its content rather than by its extension
+            if let BlobRef::Typed(TypedBlobRef::Blob(blob)) = &mut output.blob {
+                blob.mime_type = prepared.mime.to_string();
+            }
+            let aspect_ratio = match (
+                NonZeroU64::new(prepared.width.into()),
+                NonZeroU64::new(prepared.height.into()),
+            ) {
+                (Some(width), Some(height)) => Some(embed::images::AspectRatio { height, width }),
+                _ => None,
+            };
+            images.push(atrium_api::app::bsky::embed::images::Image {
+                alt: match args.alt.get(i) {
+                    Some(alt) => alt.clone(),
+                    None => image
                         .file_name()
                         .map(OsStr::to_string_lossy)
                         .unwrap_or_default()
                         .into(),
-                    aspect_ratio: None,
-                    image: output.blob,
+                },
+                aspect_ratio,
+                image: output.blob,
+            })
+        }
+        let quote = match &args.quote {
+            Some(uri) => {
+                let post = self.fetch_post(uri).await?;
+                Some(embed::record::Main {
+                    record: StrongRef {
+                        cid: post.cid,
+                        uri: post.uri,
+                    },
                 })
             }
-        }
-        let embed = Some(
-            atrium_api::app::bsky::feed::post::RecordEmbedEnum::AppBskyEmbedImagesMain(Box::new(
-                atrium_api::app::bsky::embed::images::Main { images },
+            None => None,
+        };
+        let (text, facets) = match spans {
+            Some(spans) => (args.text.clone(), resolve(self, spans).await?),
+            None if args.markdown => {
+                let (text, spans) = markdown::to_post(&args.text)?;
+                (text, resolve(self, spans).await?)
+            }
+            None => (args.text.clone(), facets(self, &args.text).await?),
+        };
+        let langs = post_langs(&args.langs, args.detect_lang, &self.config, &text)
+            .into_iter()
+            .map(|tag| {
+                Language::new(tag.clone())
+                    .map_err(|e| anyhow::Error::msg(format!("invalid language {}: {}", tag, e)))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        // Images take the place of the card of the first link
+        let link = facets.iter().flatten().find_map(|facet| {
+            facet.features.iter().find_map(|feature| match feature {
+                facet::MainFeaturesItem::Link(link) => Some(link.uri.clone()),
+                _ => None,
+            })
+        });
+        let media = match (images.is_empty(), link) {
+            (false, _) => Some(
+                embed::record_with_media::MainMediaEnum::AppBskyEmbedImagesMain(Box::new(
+                    embed::images::Main { images },
+                )),
+            ),
+            (true, Some(uri)) if !args.no_card => match self.external(&uri).await {
+                Ok(external) => Some(
+                    embed::record_with_media::MainMediaEnum::AppBskyEmbedExternalMain(Box::new(
+                        external,
+                    )),
+                ),
+                Err(e) => {
+                    warn!("posting without a card for {}: {}", uri, e);
+                    None
+                }
+            },
+            _ => None,
+        };
+        // A quote with images or a card is embedded as recordWithMedia
+        let embed = match (quote, media) {
+            (Some(record), None) => Some(RecordEmbedEnum::AppBskyEmbedRecordMain(Box::new(record))),
+            (Some(record), Some(media)) => Some(RecordEmbedEnum::AppBskyEmbedRecordWithMediaMain(
+                Box::new(embed::record_with_media::Main { media, record }),
             )),
-        );
-        let res = &self
+            (
+                None,
+                Some(embed::record_with_media::MainMediaEnum::AppBskyEmbedImagesMain(images)),
+            ) => Some(RecordEmbedEnum::AppBskyEmbedImagesMain(images)),
+            (
+                None,
+                Some(embed::record_with_media::MainMediaEnum::AppBskyEmbedExternalMain(external)),
+            ) => Some(RecordEmbedEnum::AppBskyEmbedExternalMain(external)),
+            (None, None) => None,
+        };
+        let res = self
             .agent
             .api
             .com
@@ -429,12 +721,12 @@ impl Runner {
                         created_at: Datetime::now(),
                         embed,
                         entities: None,
-                        facets: None,
+                        facets,
                         labels: None,
-                        langs: None,
-                        reply: None,
+                        langs: if langs.is_empty() { None } else { Some(langs) },
+                        reply,
                         tags: None,
-                        text: args.text,
+                        text,
                     },
                 )),
                 repo: self.handle.clone().with_context(|| "Not logged in")?.into(),
@@ -444,7 +736,7 @@ impl Runner {
             })
             .await?;
         info!("post executed succesffully returning {:?}", res);
-        Ok(())
+        Ok(res)
     }
 
     pub async fn _delete_post(&self, args: UriArgs) -> Result<(), anyhow::Error> {
@@ -457,7 +749,7 @@ impl Runner {
             .delete_record(atrium_api::com::atproto::repo::delete_record::Input {
                 collection: "app.bsky.feed.post".parse().expect("valid"),
                 repo: self.handle.clone().with_context(|| "Not logged in")?.into(),
-                rkey: args.uri.rkey.clone(),
+                rkey: args.uri.record_key()?,
                 swap_commit: None,
                 swap_record: None,
             })
@@ -466,4 +758,197 @@ impl Runner {
         info!("Successfully deleted post: {:?}, res: {:?}", rkey, res);
         Ok(())
     }
+
+    pub async fn _list_records(
+        &self,
+        args: ListRecordsArgs,
+    ) -> Result<atrium_api::com::atproto::repo::list_records::Output, anyhow::Error> {
+        let limit: LimitedNonZeroU8<100> = args.limit.try_into().map_err(anyhow::Error::msg)?;
+        Ok(self
+            .agent
+            .api
+            .com
+            .atproto
+            .repo
+            .list_records(atrium_api::com::atproto::repo::list_records::Parameters {
+                collection: args.uri.collection.parse().map_err(anyhow::Error::msg)?,
+                cursor: args.cursor,
+                limit: Some(limit),
+                repo: AtIdentifier::Did(args.uri.did.parse().map_err(anyhow::Error::msg)?),
+                reverse: Some(args.reverse),
+                rkey_end: None,
+                rkey_start: None,
+            })
+            .await?)
+    }
+
+    pub async fn _get_record(
+        &self,
+        args: GetRecordArgs,
+    ) -> Result<atrium_api::com::atproto::repo::get_record::Output, anyhow::Error> {
+        Ok(self
+            .agent
+            .api
+            .com
+            .atproto
+            .repo
+            .get_record(atrium_api::com::atproto::repo::get_record::Parameters {
+                cid: args.cid,
+                collection: args.uri.collection.parse().map_err(anyhow::Error::msg)?,
+                repo: AtIdentifier::Did(args.uri.did.parse().map_err(anyhow::Error::msg)?),
+                rkey: args.uri.record_key()?,
+            })
+            .await?)
+    }
+
+    // Writes the record at args.uri after checking it against its collection,
+    // the swap CIDs make the write fail if the record or the repository changed
+    pub async fn _put_record(
+        &self,
+        args: PutRecordArgs,
+    ) -> Result<atrium_api::com::atproto::repo::put_record::Output, anyhow::Error> {
+        let uri = args.target.uri;
+        let record = parse_record(&uri.collection, &args.record)?;
+        Ok(self
+            .agent
+            .api
+            .com
+            .atproto
+            .repo
+            .put_record(atrium_api::com::atproto::repo::put_record::Input {
+                collection: uri.collection.parse().map_err(anyhow::Error::msg)?,
+                record,
+                repo: AtIdentifier::Did(uri.did.parse().map_err(anyhow::Error::msg)?),
+                rkey: uri.record_key()?,
+                swap_commit: args.target.swap_commit,
+                swap_record: args.target.swap_record,
+                validate: None,
+            })
+            .await?)
+    }
+
+    pub async fn _delete_record(&self, args: RecordArgs) -> Result<(), anyhow::Error> {
+        self.agent
+            .api
+            .com
+            .atproto
+            .repo
+            .delete_record(atrium_api::com::atproto::repo::delete_record::Input {
+                collection: args.uri.collection.parse().map_err(anyhow::Error::msg)?,
+                repo: AtIdentifier::Did(args.uri.did.parse().map_err(anyhow::Error::msg)?),
+                rkey: args.uri.record_key()?,
+                swap_commit: args.swap_commit,
+                swap_record: args.swap_record,
+            })
+            .await?;
+        info!("Successfully deleted record: {}", args.uri);
+        Ok(())
+    }
+
+    pub async fn _resolve_handle(&self, handle: Handle) -> Result<Did, anyhow::Error> {
+        let output = self
+            .agent
+            .api
+            .com
+            .atproto
+            .identity
+            .resolve_handle(
+                atrium_api::com::atproto::identity::resolve_handle::Parameters { handle },
+            )
+            .await?;
+        Ok(output.did)
+    }
+
+    // Returns the uri of the like record, deleting that record unlikes the post
+    pub async fn _like(&self, subject: StrongRef) -> Result<String, anyhow::Error> {
+        let res = self
+            .agent
+            .api
+            .com
+            .atproto
+            .repo
+            .create_record(atrium_api::com::atproto::repo::create_record::Input {
+                collection: "app.bsky.feed.like".parse().expect("valid"),
+                record: atrium_api::records::Record::AppBskyFeedLike(Box::new(
+                    atrium_api::app::bsky::feed::like::Record {
+                        created_at: Datetime::now(),
+                        subject,
+                    },
+                )),
+                repo: self.handle.clone().with_context(|| "Not logged in")?.into(),
+                rkey: None,
+                swap_commit: None,
+                validate: None,
+            })
+            .await?;
+        info!("Successfully liked post, like: {:?}", res.uri);
+        Ok(res.uri)
+    }
+
+    pub async fn _unlike(&self, like: AtUri) -> Result<(), anyhow::Error> {
+        let res = self
+            .agent
+            .api
+            .com
+            .atproto
+            .repo
+            .delete_record(atrium_api::com::atproto::repo::delete_record::Input {
+                collection: "app.bsky.feed.like".parse().expect("valid"),
+                repo: self.handle.clone().with_context(|| "Not logged in")?.into(),
+                rkey: like.record_key()?,
+                swap_commit: None,
+                swap_record: None,
+            })
+            .await?;
+        info!("Successfully deleted like: {:?}, res: {:?}", like.rkey, res);
+        Ok(())
+    }
+
+    // Returns the uri of the repost record, deleting that record undoes the repost
+    pub async fn _repost(&self, subject: StrongRef) -> Result<String, anyhow::Error> {
+        let res = self
+            .agent
+            .api
+            .com
+            .atproto
+            .repo
+            .create_record(atrium_api::com::atproto::repo::create_record::Input {
+                collection: "app.bsky.feed.repost".parse().expect("valid"),
+                record: atrium_api::records::Record::AppBskyFeedRepost(Box::new(
+                    atrium_api::app::bsky::feed::repost::Record {
+                        created_at: Datetime::now(),
+                        subject,
+                    },
+                )),
+                repo: self.handle.clone().with_context(|| "Not logged in")?.into(),
+                rkey: None,
+                swap_commit: None,
+                validate: None,
+            })
+            .await?;
+        info!("Successfully reposted post, repost: {:?}", res.uri);
+        Ok(res.uri)
+    }
+
+    pub async fn _unrepost(&self, repost: AtUri) -> Result<(), anyhow::Error> {
+        let res = self
+            .agent
+            .api
+            .com
+            .atproto
+            .repo
+            .delete_record(atrium_api::com::atproto::repo::delete_record::Input {
+                collection: "app.bsky.feed.repost".parse().expect("valid"),
+                repo: self.handle.clone().with_context(|| "Not logged in")?.into(),
+                rkey: repost.record_key()?,
+                swap_commit: None,
+                swap_record: None,
+            })
+            .await?;
+        info!(
+            "Successfully deleted repost: {:?}, res: {:?}",
+            repost.rkey, res
+        );
+        Ok(())
+    }
 }
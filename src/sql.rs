@@ -1,69 +1,333 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::filter::TimelineFilter;
+use crate::notifications::StoredNotification;
+use crate::surreal::{SearchResult, TimelineCursor};
 use surrealdb::{engine::local::Db, Surreal};
 
+// Values of the $parameters referenced by a query
+pub type Bindings = BTreeMap<&'static str, serde_json::Value>;
+
 pub enum SqlQuery {
     SelectCreatedAt {
         cid: String,
     },
-    CountPostsOlderThan {
-        created_at: String,
+    GetPost {
+        cid: String,
     },
-    CountRecentPostsOlderThan {
-        created_at: String,
-        lower_limit: String,
+    ReadTimeline {
+        timeline: String,
+        filter: TimelineFilter,
+        limit: Option<i32>,
+    },
+    CountPostsBetween {
+        timeline: String,
+        newer_than: String,
+        older_than: String,
+    },
+    ReadCursors {
+        timeline: String,
+    },
+    PageWithCursor {
+        timeline: String,
+        cursor: String,
+    },
+    UpdatePageCursor {
+        timeline: String,
+        cursor: String,
+        next: Option<String>,
+    },
+    OverlappingChains {
+        timeline: String,
+        chain: String,
+        oldest: String,
+        newest: String,
+    },
+    ChainsSharingCids {
+        timeline: String,
+        chain: String,
+        cids: Vec<String>,
     },
-    CountPostsNewerThan {
+    MergeChains {
+        timeline: String,
+        from: String,
+        into: String,
+    },
+    ChainCovering {
+        timeline: String,
         created_at: String,
     },
-    GetPost {
+    ChainTail {
+        timeline: String,
+        chain: String,
+    },
+    NewestCreatedAt {
+        timeline: String,
+    },
+    CountStoredCids {
+        timeline: String,
+        cids: Vec<String>,
+    },
+    PruneEntries {
+        timeline: String,
+        older_than: String,
+    },
+    PruneCursors {
+        timeline: String,
+        older_than: String,
+    },
+    SearchPosts {
+        query: String,
+        limit: i32,
+    },
+    SelectUri {
         cid: String,
     },
-    ReadTimeline {
-        filter: Option<String>,
-        limit: Option<i32>,
+    RelateReply {
+        cid: String,
+        parent_cid: String,
+    },
+    CountStoredNotifications {
+        cids: Vec<String>,
+    },
+    ReadNotifications {
+        limit: i32,
+        unread_only: bool,
+    },
+    CountUnreadNotifications,
+    MarkNotificationsRead {
+        seen_at: String,
+    },
+    SelectNotifiedUris {
+        uris: Vec<String>,
     },
 }
 
+const FEED_VIEW: &str = "SELECT post[*], post.record.createdAt as createdAt, reply.parent as parent, reply.root as root, reason OMIT post.id, parent.id, root.id FROM feed";
+const FEED_FETCH: &str = "FETCH post.author, parent, root, parent.author, root.author";
+
 impl SqlQuery {
+    // The query text only references $parameters, their values are in bindings()
     pub fn to_sql(&self) -> String {
         match self {
-            SqlQuery::SelectCreatedAt { cid } => format!(
-                r#"SELECT post.record.createdAt as createdAt FROM feed WHERE post.cid={} LIMIT 1;"#,
-                cid
+            SqlQuery::SelectCreatedAt { .. } => String::from(
+                r#"SELECT post.record.createdAt as createdAt FROM feed WHERE post.cid = $cid LIMIT 1;"#,
             ),
-            SqlQuery::CountPostsOlderThan { created_at } => format!(
-                r#"SELECT COUNT() as c FROM feed WHERE post.record.createdAt <= '{}' GROUP ALL"#,
-                created_at
-            ),
-            SqlQuery::CountRecentPostsOlderThan {
-                created_at,
-                lower_limit,
-            } => format!(
-                r#"SELECT COUNT() as c FROM feed WHERE post.record.createdAt <= '{}' and post.record.createdAt > '{}' GROUP ALL"#,
-                created_at, lower_limit
-            ),
-            SqlQuery::CountPostsNewerThan { created_at } => format!(
-                r#"SELECT COUNT() as c FROM feed WHERE post.record.createdAt >= '{}' GROUP ALL"#,
-                created_at
-            ),
-            SqlQuery::GetPost { cid } => format!(
-                r#"SELECT post[*], post.record.createdAt as createdAt, reply.parent as parent, reply.root as root, reason OMIT post.id, parent.id, root.id FROM feed WHERE post.cid == {} FETCH post.author, parent, root, parent.author, root.author;"#,
-                cid
-            ),
-            SqlQuery::ReadTimeline { filter, limit } => {
-                let base_query = "SELECT post[*], post.record.createdAt as createdAt, reply.parent as parent, reply.root as root, reason OMIT post.id, parent.id, root.id FROM feed";
-                let mut query = base_query.to_string();
-                if let Some(f) = filter {
-                    query = format!("{} WHERE {}", query, f);
+            SqlQuery::GetPost { .. } => {
+                format!("{} WHERE post.cid = $cid {};", FEED_VIEW, FEED_FETCH)
+            }
+            SqlQuery::ReadTimeline { filter, limit, .. } => {
+                let mut query = format!(
+                    "{} WHERE id IN (SELECT VALUE feed FROM timeline_entry WHERE timeline = $timeline)",
+                    FEED_VIEW
+                );
+                if let Some(f) = filter.to_sql() {
+                    query = format!("{} AND ({})", query, f);
                 }
                 query.push_str(" ORDER BY createdAt DESC");
-                if let Some(l) = limit {
-                    query = format!("{} LIMIT {}", query, l);
+                if limit.is_some() {
+                    query.push_str(" LIMIT $limit");
+                }
+                format!("{} {};", query, FEED_FETCH)
+            }
+            SqlQuery::CountPostsBetween { .. } => String::from(
+                r#"SELECT COUNT() as c FROM timeline_entry WHERE timeline = $timeline AND createdAt >= $newer_than and createdAt < $older_than GROUP ALL"#,
+            ),
+            SqlQuery::ReadCursors { .. } => {
+                String::from(r#"SELECT * FROM cursor WHERE timeline = $timeline;"#)
+            }
+            SqlQuery::PageWithCursor { .. } => String::from(
+                r#"SELECT * FROM cursor WHERE timeline = $timeline AND cursor = $cursor LIMIT 1;"#,
+            ),
+            SqlQuery::UpdatePageCursor { .. } => String::from(
+                r#"UPDATE cursor SET cursor = $next WHERE timeline = $timeline AND cursor = $cursor;"#,
+            ),
+            SqlQuery::OverlappingChains { .. } => String::from(
+                r#"SELECT chain FROM cursor WHERE timeline = $timeline AND chain != $chain AND oldest <= $newest AND newest >= $oldest GROUP BY chain;"#,
+            ),
+            SqlQuery::ChainsSharingCids { .. } => String::from(
+                r#"SELECT chain FROM cursor WHERE timeline = $timeline AND chain != $chain AND cids CONTAINSANY $cids GROUP BY chain;"#,
+            ),
+            SqlQuery::MergeChains { .. } => String::from(
+                r#"UPDATE cursor SET chain = $into WHERE timeline = $timeline AND chain = $from;"#,
+            ),
+            SqlQuery::ChainCovering { .. } => String::from(
+                r#"SELECT * FROM cursor WHERE timeline = $timeline AND oldest <= $created_at AND newest >= $created_at LIMIT 1;"#,
+            ),
+            SqlQuery::ChainTail { .. } => String::from(
+                r#"SELECT * FROM cursor WHERE timeline = $timeline AND chain = $chain ORDER BY oldest ASC LIMIT 1;"#,
+            ),
+            SqlQuery::NewestCreatedAt { .. } => String::from(
+                r#"SELECT createdAt FROM timeline_entry WHERE timeline = $timeline ORDER BY createdAt DESC LIMIT 1;"#,
+            ),
+            SqlQuery::CountStoredCids { .. } => String::from(
+                r#"SELECT COUNT() as c FROM timeline_entry WHERE timeline = $timeline AND cid IN $cids GROUP ALL"#,
+            ),
+            SqlQuery::PruneEntries { .. } => String::from(
+                r#"DELETE timeline_entry WHERE timeline = $timeline AND createdAt < $older_than;"#,
+            ),
+            SqlQuery::PruneCursors { .. } => String::from(
+                r#"DELETE cursor WHERE timeline = $timeline AND newest < $older_than;"#,
+            ),
+            // Matches on the text are ranked above matches on the author name, the
+            // highlight wraps the matched words of the text in **
+            SqlQuery::SearchPosts { .. } => String::from(
+                r#"SELECT id AS post, search::score(1) * 2 + search::score(2) AS score, search::highlight('**', '**', 1) AS highlight FROM post WHERE record.text @1@ $query OR authorName @2@ $query ORDER BY score DESC LIMIT $limit FETCH post, post.author;"#,
+            ),
+            SqlQuery::SelectUri { .. } => {
+                String::from(r#"SELECT VALUE uri FROM type::thing('post', $cid);"#)
+            }
+            // A post replies to a single parent, the previous edge is replaced
+            SqlQuery::RelateReply { .. } => String::from(
+                r#"LET $child = type::thing('post', $cid); LET $parent = type::thing('post', $parent_cid); DELETE replies_to WHERE in = $child; RELATE $child->replies_to->$parent;"#,
+            ),
+            SqlQuery::CountStoredNotifications { .. } => String::from(
+                r#"SELECT COUNT() as c FROM notification WHERE cid IN $cids GROUP ALL"#,
+            ),
+            SqlQuery::ReadNotifications { unread_only, .. } => {
+                let mut query = String::from("SELECT * OMIT id, post.id FROM notification");
+                if *unread_only {
+                    query.push_str(" WHERE isRead = false");
+                }
+                format!(
+                    "{} ORDER BY indexedAt DESC LIMIT $limit FETCH author, post, post.author;",
+                    query
+                )
+            }
+            SqlQuery::CountUnreadNotifications => String::from(
+                r#"SELECT COUNT() as c FROM notification WHERE isRead = false GROUP ALL"#,
+            ),
+            SqlQuery::MarkNotificationsRead { .. } => String::from(
+                r#"UPDATE notification SET isRead = true WHERE isRead = false AND indexedAt <= $seen_at;"#,
+            ),
+            SqlQuery::SelectNotifiedUris { .. } => {
+                String::from(r#"SELECT VALUE uri FROM notified WHERE uri IN $uris;"#)
+            }
+        }
+    }
+
+    pub fn bindings(&self) -> Bindings {
+        let mut bindings = Bindings::new();
+        match self {
+            SqlQuery::SelectCreatedAt { cid }
+            | SqlQuery::GetPost { cid }
+            | SqlQuery::SelectUri { cid } => {
+                bindings.insert("cid", cid.clone().into());
+            }
+            SqlQuery::ChainCovering {
+                timeline,
+                created_at,
+            } => {
+                bindings.insert("timeline", timeline.clone().into());
+                bindings.insert("created_at", created_at.clone().into());
+            }
+            SqlQuery::ReadTimeline {
+                timeline,
+                filter,
+                limit,
+            } => {
+                bindings.insert("timeline", timeline.clone().into());
+                if let Some(limit) = limit {
+                    bindings.insert("limit", (*limit).into());
                 }
-                query.push_str(" FETCH post.author, parent, root, parent.author, root.author;");
-                query
+                bindings.extend(filter.bindings());
+            }
+            SqlQuery::CountPostsBetween {
+                timeline,
+                newer_than,
+                older_than,
+            } => {
+                bindings.insert("timeline", timeline.clone().into());
+                bindings.insert("newer_than", newer_than.clone().into());
+                bindings.insert("older_than", older_than.clone().into());
+            }
+            SqlQuery::ReadCursors { timeline } | SqlQuery::NewestCreatedAt { timeline } => {
+                bindings.insert("timeline", timeline.clone().into());
+            }
+            SqlQuery::PageWithCursor { timeline, cursor } => {
+                bindings.insert("timeline", timeline.clone().into());
+                bindings.insert("cursor", cursor.clone().into());
+            }
+            SqlQuery::UpdatePageCursor {
+                timeline,
+                cursor,
+                next,
+            } => {
+                bindings.insert("timeline", timeline.clone().into());
+                bindings.insert("cursor", cursor.clone().into());
+                bindings.insert("next", next.clone().into());
+            }
+            SqlQuery::OverlappingChains {
+                timeline,
+                chain,
+                oldest,
+                newest,
+            } => {
+                bindings.insert("timeline", timeline.clone().into());
+                bindings.insert("chain", chain.clone().into());
+                bindings.insert("oldest", oldest.clone().into());
+                bindings.insert("newest", newest.clone().into());
+            }
+            SqlQuery::ChainsSharingCids {
+                timeline,
+                chain,
+                cids,
+            } => {
+                bindings.insert("timeline", timeline.clone().into());
+                bindings.insert("chain", chain.clone().into());
+                bindings.insert("cids", cids.clone().into());
+            }
+            SqlQuery::MergeChains {
+                timeline,
+                from,
+                into,
+            } => {
+                bindings.insert("timeline", timeline.clone().into());
+                bindings.insert("from", from.clone().into());
+                bindings.insert("into", into.clone().into());
+            }
+            SqlQuery::ChainTail { timeline, chain } => {
+                bindings.insert("timeline", timeline.clone().into());
+                bindings.insert("chain", chain.clone().into());
+            }
+            SqlQuery::CountStoredCids { timeline, cids } => {
+                bindings.insert("timeline", timeline.clone().into());
+                bindings.insert("cids", cids.clone().into());
+            }
+            SqlQuery::PruneEntries {
+                timeline,
+                older_than,
+            }
+            | SqlQuery::PruneCursors {
+                timeline,
+                older_than,
+            } => {
+                bindings.insert("timeline", timeline.clone().into());
+                bindings.insert("older_than", older_than.clone().into());
+            }
+            SqlQuery::SearchPosts { query, limit } => {
+                bindings.insert("query", query.clone().into());
+                bindings.insert("limit", (*limit).into());
+            }
+            SqlQuery::RelateReply { cid, parent_cid } => {
+                bindings.insert("cid", cid.clone().into());
+                bindings.insert("parent_cid", parent_cid.clone().into());
+            }
+            SqlQuery::CountStoredNotifications { cids } => {
+                bindings.insert("cids", cids.clone().into());
+            }
+            SqlQuery::ReadNotifications { limit, .. } => {
+                bindings.insert("limit", (*limit).into());
+            }
+            SqlQuery::CountUnreadNotifications => {}
+            SqlQuery::MarkNotificationsRead { seen_at } => {
+                bindings.insert("seen_at", seen_at.clone().into());
+            }
+            SqlQuery::SelectNotifiedUris { uris } => {
+                bindings.insert("uris", uris.clone().into());
             }
         }
+        bindings
     }
 }
 
@@ -76,38 +340,77 @@ impl Querier {
         Querier { db }
     }
 
-    pub async fn run_query(&self, query: &str) -> Result<surrealdb::Response, anyhow::Error> {
-        let result: surrealdb::Response = self.db.query(query).await?;
+    pub async fn run_query(&self, query: &SqlQuery) -> Result<surrealdb::Response, anyhow::Error> {
+        let mut request = self.db.query(query.to_sql());
+        for binding in query.bindings() {
+            request = request.bind(binding);
+        }
+        let result: surrealdb::Response = request.await?;
         Ok(result)
     }
 
-    pub async fn count_posts_older_than(&self, created_at: &str) -> Result<i32, anyhow::Error> {
-        let query = SqlQuery::CountPostsOlderThan {
-            created_at: created_at.to_string(),
+    pub async fn read_timeline(
+        &self,
+        timeline: &str,
+        filter: TimelineFilter,
+        limit: Option<i32>,
+    ) -> Result<Vec<crate::nvim::FeedViewPostFlat>, anyhow::Error> {
+        let query = SqlQuery::ReadTimeline {
+            timeline: timeline.to_string(),
+            filter,
+            limit,
         };
-        let sql = query.to_sql();
-        let mut result: surrealdb::Response = self.run_query(&sql).await?;
-        let count_map: Option<HashMap<String, i32>> = result.take(0)?;
-        if let Some(count_map) = count_map {
-            if let Some(count) = count_map.get("c") {
-                return Ok(*count);
+        let mut result = self.run_query(&query).await?;
+        let value: Vec<crate::nvim::FeedViewPostFlat> = result.take(0)?;
+        Ok(value)
+    }
+
+    pub async fn select_created_at(&self, cid: &str) -> Result<String, anyhow::Error> {
+        let query = SqlQuery::SelectCreatedAt {
+            cid: cid.to_string(),
+        };
+        let mut result = self.run_query(&query).await?;
+        let result_map: Option<HashMap<String, String>> = result.take(0)?;
+        if let Some(record) = result_map {
+            if let Some(created_at) = record.get("createdAt") {
+                return Ok(created_at.to_string());
             }
         }
-        Ok(0)
-        // Err(anyhow::Error::msg(format!("Failed to get count for {sql}")))
+        Err(anyhow::Error::msg(format!(
+            "Failed to get {} with cid {}",
+            query.to_sql(),
+            cid
+        )))
     }
 
-    pub async fn count_recent_posts_older_than(
+    pub async fn get_post(
         &self,
-        created_at: &str,
-        lower_limit: &str,
+        cid: String,
+    ) -> Result<Option<crate::nvim::FeedViewPostFlat>, anyhow::Error> {
+        let query = SqlQuery::GetPost {
+            cid: cid.to_string(),
+        };
+        let mut result = self.run_query(&query).await?;
+        let value: Vec<crate::nvim::FeedViewPostFlat> = result.take(0)?;
+        if value.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(value[0].clone()))
+        }
+    }
+
+    pub async fn count_posts_between(
+        &self,
+        timeline: &str,
+        newer_than: &str,
+        older_than: &str,
     ) -> Result<i32, anyhow::Error> {
-        let query = SqlQuery::CountRecentPostsOlderThan {
-            created_at: created_at.to_string(),
-            lower_limit: lower_limit.to_string(),
+        let query = SqlQuery::CountPostsBetween {
+            timeline: timeline.to_string(),
+            newer_than: newer_than.to_string(),
+            older_than: older_than.to_string(),
         };
-        let sql = query.to_sql();
-        let mut result: surrealdb::Response = self.run_query(&sql).await?;
+        let mut result = self.run_query(&query).await?;
         let count_map: Option<HashMap<String, i32>> = result.take(0)?;
         if let Some(count_map) = count_map {
             if let Some(count) = count_map.get("c") {
@@ -115,15 +418,160 @@ impl Querier {
             }
         }
         Ok(0)
-        // Err(anyhow::Error::msg(format!("Failed to get count for {sql}")))
     }
 
-    pub async fn count_posts_newer_than(&self, created_at: &str) -> Result<i32, anyhow::Error> {
-        let query = SqlQuery::CountPostsNewerThan {
+    pub async fn read_cursors(&self, timeline: &str) -> Result<Vec<TimelineCursor>, anyhow::Error> {
+        let query = SqlQuery::ReadCursors {
+            timeline: timeline.to_string(),
+        };
+        let mut result = self.run_query(&query).await?;
+        let value: Vec<TimelineCursor> = result.take(0)?;
+        Ok(value)
+    }
+
+    pub async fn page_with_cursor(
+        &self,
+        timeline: &str,
+        cursor: &str,
+    ) -> Result<Option<TimelineCursor>, anyhow::Error> {
+        let query = SqlQuery::PageWithCursor {
+            timeline: timeline.to_string(),
+            cursor: cursor.to_string(),
+        };
+        let mut result = self.run_query(&query).await?;
+        let value: Option<TimelineCursor> = result.take(0)?;
+        Ok(value)
+    }
+
+    pub async fn update_page_cursor(
+        &self,
+        timeline: &str,
+        cursor: &str,
+        next: Option<String>,
+    ) -> Result<(), anyhow::Error> {
+        let query = SqlQuery::UpdatePageCursor {
+            timeline: timeline.to_string(),
+            cursor: cursor.to_string(),
+            next,
+        };
+        self.run_query(&query).await?;
+        Ok(())
+    }
+
+    pub async fn overlapping_chains(
+        &self,
+        timeline: &str,
+        chain: &str,
+        oldest: &str,
+        newest: &str,
+    ) -> Result<Vec<String>, anyhow::Error> {
+        let query = SqlQuery::OverlappingChains {
+            timeline: timeline.to_string(),
+            chain: chain.to_string(),
+            oldest: oldest.to_string(),
+            newest: newest.to_string(),
+        };
+        let mut result = self.run_query(&query).await?;
+        let chains: Vec<HashMap<String, String>> = result.take(0)?;
+        Ok(chains
+            .into_iter()
+            .filter_map(|mut c| c.remove("chain"))
+            .collect())
+    }
+
+    // Returns the other chains with a page that contains one of cids
+    pub async fn chains_sharing_cids(
+        &self,
+        timeline: &str,
+        chain: &str,
+        cids: Vec<String>,
+    ) -> Result<Vec<String>, anyhow::Error> {
+        if cids.is_empty() {
+            return Ok(vec![]);
+        }
+        let query = SqlQuery::ChainsSharingCids {
+            timeline: timeline.to_string(),
+            chain: chain.to_string(),
+            cids,
+        };
+        let mut result = self.run_query(&query).await?;
+        let chains: Vec<HashMap<String, String>> = result.take(0)?;
+        Ok(chains
+            .into_iter()
+            .filter_map(|mut c| c.remove("chain"))
+            .collect())
+    }
+
+    pub async fn merge_chains(
+        &self,
+        timeline: &str,
+        from: &str,
+        into: &str,
+    ) -> Result<(), anyhow::Error> {
+        let query = SqlQuery::MergeChains {
+            timeline: timeline.to_string(),
+            from: from.to_string(),
+            into: into.to_string(),
+        };
+        self.run_query(&query).await?;
+        Ok(())
+    }
+
+    // Returns the tail (oldest page) of the chain that contains created_at
+    pub async fn chain_tail_covering(
+        &self,
+        timeline: &str,
+        created_at: &str,
+    ) -> Result<Option<TimelineCursor>, anyhow::Error> {
+        let query = SqlQuery::ChainCovering {
+            timeline: timeline.to_string(),
             created_at: created_at.to_string(),
         };
-        let sql = query.to_sql();
-        let mut result = self.run_query(&sql).await?;
+        let mut result = self.run_query(&query).await?;
+        let covering: Option<TimelineCursor> = result.take(0)?;
+        match covering {
+            Some(page) => self.chain_tail(timeline, &page.chain).await,
+            None => Ok(None),
+        }
+    }
+
+    pub async fn chain_tail(
+        &self,
+        timeline: &str,
+        chain: &str,
+    ) -> Result<Option<TimelineCursor>, anyhow::Error> {
+        let query = SqlQuery::ChainTail {
+            timeline: timeline.to_string(),
+            chain: chain.to_string(),
+        };
+        let mut result = self.run_query(&query).await?;
+        let tail: Option<TimelineCursor> = result.take(0)?;
+        Ok(tail)
+    }
+
+    // Returns the createdAt of the newest post stored in the timeline
+    pub async fn newest_created_at(&self, timeline: &str) -> Result<Option<String>, anyhow::Error> {
+        let query = SqlQuery::NewestCreatedAt {
+            timeline: timeline.to_string(),
+        };
+        let mut result = self.run_query(&query).await?;
+        let result_map: Option<HashMap<String, String>> = result.take(0)?;
+        Ok(result_map.and_then(|mut record| record.remove("createdAt")))
+    }
+
+    pub async fn count_stored_cids(
+        &self,
+        timeline: &str,
+        cids: Vec<String>,
+    ) -> Result<i32, anyhow::Error> {
+        if cids.is_empty() {
+            return Ok(0);
+        }
+        let query = SqlQuery::CountStoredCids {
+            timeline: timeline.to_string(),
+            cids,
+        };
+        let mut result = self.run_query(&query).await?;
         let count_map: Option<HashMap<String, i32>> = result.take(0)?;
         if let Some(count_map) = count_map {
             if let Some(count) = count_map.get("c") {
@@ -131,50 +579,140 @@ impl Querier {
             }
         }
         Ok(0)
-        // Err(anyhow::Error::msg(format!("Failed to get count for {sql}")))
     }
 
-    pub async fn read_timeline(
+    // Drops the posts older than older_than from the timeline, along with the pages
+    // that only covered them, the posts themselves stay in the feed table since
+    // other timelines may still reference them
+    pub async fn prune_timeline(
         &self,
-        filter: Option<String>,
-        limit: Option<i32>,
-    ) -> Result<Vec<crate::nvim::FeedViewPostFlat>, anyhow::Error> {
-        let query = SqlQuery::ReadTimeline { filter, limit };
-        let sql = query.to_sql();
-        let mut result = self.db.query(&sql).await?;
-        let value: Vec<crate::nvim::FeedViewPostFlat> = result.take(0)?;
+        timeline: &str,
+        older_than: &str,
+    ) -> Result<(), anyhow::Error> {
+        let query = SqlQuery::PruneEntries {
+            timeline: timeline.to_string(),
+            older_than: older_than.to_string(),
+        };
+        self.run_query(&query).await?;
+        let query = SqlQuery::PruneCursors {
+            timeline: timeline.to_string(),
+            older_than: older_than.to_string(),
+        };
+        self.run_query(&query).await?;
+        Ok(())
+    }
+
+    pub async fn search_posts(
+        &self,
+        query: &str,
+        limit: i32,
+    ) -> Result<Vec<SearchResult>, anyhow::Error> {
+        let query = SqlQuery::SearchPosts {
+            query: query.to_string(),
+            limit,
+        };
+        let mut result = self.run_query(&query).await?;
+        let value: Vec<SearchResult> = result.take(0)?;
         Ok(value)
     }
 
-    pub async fn select_created_at(&self, cid: &str) -> Result<String, anyhow::Error> {
-        let query = SqlQuery::SelectCreatedAt {
+    pub async fn select_uri(&self, cid: &str) -> Result<Option<String>, anyhow::Error> {
+        let query = SqlQuery::SelectUri {
             cid: cid.to_string(),
         };
-        let sql = query.to_sql();
-        let mut result = self.run_query(&sql).await?;
-        let result_map: Option<HashMap<String, String>> = result.take(0)?;
-        if let Some(record) = result_map {
-            if let Some(created_at) = record.get("createdAt") {
-                return Ok(created_at.to_string());
-            }
+        let mut result = self.run_query(&query).await?;
+        let value: Option<String> = result.take(0)?;
+        Ok(value)
+    }
+
+    pub async fn relate_reply(&self, cid: &str, parent_cid: &str) -> Result<(), anyhow::Error> {
+        let query = SqlQuery::RelateReply {
+            cid: cid.to_string(),
+            parent_cid: parent_cid.to_string(),
+        };
+        self.run_query(&query).await?.check()?;
+        Ok(())
+    }
+
+    pub async fn count_stored_notifications(
+        &self,
+        cids: Vec<String>,
+    ) -> Result<i32, anyhow::Error> {
+        if cids.is_empty() {
+            return Ok(0);
         }
-        Err(anyhow::Error::msg(format!("Failed to get {sql}")))
+        let query = SqlQuery::CountStoredNotifications { cids };
+        let mut result = self.run_query(&query).await?;
+        let count_map: Option<HashMap<String, i32>> = result.take(0)?;
+        Ok(count_map.and_then(|c| c.get("c").copied()).unwrap_or(0))
     }
 
-    pub async fn get_post(
+    // Returns the stored notifications from the newest, with their author and post
+    pub async fn read_notifications(
         &self,
-        cid: String,
-    ) -> Result<Option<crate::nvim::FeedViewPostFlat>, anyhow::Error> {
-        let query = SqlQuery::GetPost {
-            cid: cid.to_string(),
+        limit: i32,
+        unread_only: bool,
+    ) -> Result<Vec<StoredNotification>, anyhow::Error> {
+        let query = SqlQuery::ReadNotifications { limit, unread_only };
+        let mut result = self.run_query(&query).await?;
+        let value: Vec<StoredNotification> = result.take(0)?;
+        Ok(value)
+    }
+
+    pub async fn count_unread_notifications(&self) -> Result<i32, anyhow::Error> {
+        let query = SqlQuery::CountUnreadNotifications;
+        let mut result = self.run_query(&query).await?;
+        let count_map: Option<HashMap<String, i32>> = result.take(0)?;
+        Ok(count_map.and_then(|c| c.get("c").copied()).unwrap_or(0))
+    }
+
+    pub async fn mark_notifications_read(&self, seen_at: &str) -> Result<(), anyhow::Error> {
+        let query = SqlQuery::MarkNotificationsRead {
+            seen_at: seen_at.to_string(),
+        };
+        self.run_query(&query).await?.check()?;
+        Ok(())
+    }
+
+    // Returns the uris among the given ones that already raised an alert
+    pub async fn select_notified_uris(
+        &self,
+        uris: Vec<String>,
+    ) -> Result<Vec<String>, anyhow::Error> {
+        let query = SqlQuery::SelectNotifiedUris { uris };
+        let mut result = self.run_query(&query).await?;
+        let value: Vec<String> = result.take(0)?;
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_are_bound_not_interpolated() {
+        let hostile = "x'; DELETE feed; --";
+        let query = SqlQuery::ReadTimeline {
+            timeline: hostile.to_string(),
+            filter: TimelineFilter::new()
+                .created_before(hostile)
+                .created_since(hostile),
+            limit: Some(10),
         };
         let sql = query.to_sql();
-        let mut result = self.run_query(&sql).await?;
-        let value: Vec<crate::nvim::FeedViewPostFlat> = result.take(0)?;
-        if value.is_empty() {
-            Ok(None)
-        } else {
-            Ok(Some(value[0].clone()))
-        }
+        assert!(!sql.contains(hostile));
+        let bindings = query.bindings();
+        assert_eq!(bindings["timeline"], hostile);
+        assert_eq!(bindings["filter_created_before"], hostile);
+        assert_eq!(bindings["filter_created_since"], hostile);
+        assert_eq!(bindings["limit"], 10);
+
+        let query = SqlQuery::CountStoredCids {
+            timeline: String::from("default"),
+            cids: vec![hostile.to_string()],
+        };
+        assert!(!query.to_sql().contains(hostile));
+        assert_eq!(query.bindings()["cids"], serde_json::json!([hostile]));
     }
 }
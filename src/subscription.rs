@@ -0,0 +1,145 @@
+use std::str::FromStr;
+
+use atrium_api::app::bsky::feed::defs::FeedViewPost;
+use atrium_api::types::string::AtIdentifier;
+
+use crate::commands::{AtUri, GetAuthorFeedArgs, GetTimelineArgs, UriArgs};
+use crate::runner::Runner;
+
+// A source of posts paged with server cursors and stored as its own timeline,
+// the home timeline is stored as "default" and the others are named after their
+// source, e.g. feed:at://did:plc:xxx/app.bsky.feed.generator/whats-hot
+#[derive(Debug, Clone)]
+pub enum Subscription {
+    Home,
+    Feed(AtUri),
+    List(AtUri),
+    Author(AtIdentifier),
+}
+
+pub struct FeedPage {
+    pub feed: Vec<FeedViewPost>,
+    pub cursor: Option<String>,
+}
+
+impl Subscription {
+    // Author timelines are named after the DID, so that the handle and the DID
+    // of an account share one timeline, see resolve
+    pub fn name(&self) -> String {
+        match self {
+            Subscription::Home => String::from("default"),
+            Subscription::Feed(uri) => format!("feed:{}", uri),
+            Subscription::List(uri) => format!("list:{}", uri),
+            Subscription::Author(actor) => format!("author:{}", actor.as_ref()),
+        }
+    }
+
+    // Replaces the handle of an author subscription with its DID
+    pub async fn resolve(self, runner: &Runner) -> Result<Self, anyhow::Error> {
+        match self {
+            Subscription::Author(actor) => Ok(Subscription::Author(AtIdentifier::Did(
+                runner.resolve_actor(Some(actor)).await?,
+            ))),
+            subscription => Ok(subscription),
+        }
+    }
+
+    // Whether the posts come newest first, a feed generator orders them as it
+    // likes so only the CIDs tell where its pages overlap
+    pub fn is_chronological(&self) -> bool {
+        !matches!(self, Subscription::Feed(_))
+    }
+
+    // Fetches the page of the source that starts at cursor
+    pub async fn fetch(
+        &self,
+        runner: &Runner,
+        cursor: Option<String>,
+        limit: u8,
+    ) -> Result<FeedPage, anyhow::Error> {
+        let page = match self {
+            Subscription::Home => {
+                let output = runner
+                    ._get_timeline(GetTimelineArgs {
+                        algorithm: String::from("reverse-chronological"),
+                        cursor,
+                        limit,
+                    })
+                    .await?;
+                FeedPage {
+                    feed: output.feed,
+                    cursor: output.cursor,
+                }
+            }
+            Subscription::Feed(uri) => {
+                let output = runner
+                    ._get_feed(UriArgs {
+                        cursor,
+                        limit,
+                        uri: uri.clone(),
+                    })
+                    .await?;
+                FeedPage {
+                    feed: output.feed,
+                    cursor: output.cursor,
+                }
+            }
+            Subscription::List(uri) => {
+                let output = runner
+                    ._get_list_feed(UriArgs {
+                        cursor,
+                        limit,
+                        uri: uri.clone(),
+                    })
+                    .await?;
+                FeedPage {
+                    feed: output.feed,
+                    cursor: output.cursor,
+                }
+            }
+            Subscription::Author(actor) => {
+                let output = runner
+                    ._get_author_feed(GetAuthorFeedArgs {
+                        actor: Some(actor.clone()),
+                        cursor,
+                        filter: None,
+                        limit,
+                        store: false,
+                    })
+                    .await?;
+                FeedPage {
+                    feed: output.feed,
+                    cursor: output.cursor,
+                }
+            }
+        };
+        Ok(page)
+    }
+}
+
+impl FromStr for Subscription {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "default" || s == "home" {
+            return Ok(Subscription::Home);
+        }
+        match s.split_once(':') {
+            Some(("feed", uri)) => Ok(Subscription::Feed(AtUri::from_str(uri)?)),
+            Some(("list", uri)) => Ok(Subscription::List(AtUri::from_str(uri)?)),
+            Some(("author", actor)) => Ok(Subscription::Author(
+                AtIdentifier::from_str(actor).map_err(String::from)?,
+            )),
+            _ => Err(format!(
+                r#"invalid timeline "{}", expected default, feed:<uri>, list:<uri> or author:<actor>"#,
+                s
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for Subscription {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
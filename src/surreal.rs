@@ -1,38 +1,78 @@
 use anyhow::{Context, Result};
 use atrium_api::app::bsky;
 use atrium_api::app::bsky::feed;
+use atrium_api::app::bsky::notification::list_notifications::Notification;
 use atrium_api::records::Record;
 use chrono::{DateTime, ParseError, Utc};
 use log::{error, info, trace, warn};
 use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
+use std::path::PathBuf;
 use surrealdb::engine::local::{Db, RocksDb};
 use surrealdb::Surreal;
 use tokio::fs::create_dir_all;
 
+use crate::filter::TimelineFilter;
 use crate::nvim::FeedViewPostFlat;
 use crate::sql::Querier;
+use crate::subscription::{FeedPage, Subscription};
+use crate::thread::thread_posts;
 
 #[derive(Clone)]
 pub struct SurrealDB {
     pub db: Surreal<Db>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+// One row per page fetched from the server, the cursor is the one returned with
+// the page and points to the next (older) page, request_cursor is the one used to
+// fetch it. Pages fetched one after the other share the same chain, two chains
+// that do not overlap in time have a gap between them.
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TimelineCursor {
-    cursor: String,
-    timeline: String,
+    pub cursor: Option<String>,
+    pub timeline: String,
+    #[serde(default)]
+    pub request_cursor: Option<String>,
+    #[serde(default)]
+    pub chain: String,
+    #[serde(default)]
+    pub oldest: Option<String>,
+    #[serde(default)]
+    pub newest: Option<String>,
+    // CIDs of the posts of the page, the pages of a feed are joined on them
+    #[serde(default)]
+    pub cids: Vec<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct TimelineResponse {}
 
-// Implementation on TimelineCursor full ChatGPT, did not read them
-// Optionally, implement `Ord` if you want total ordering and are sure every comparison will be valid
+// A post matching a full text search, highlight is the text of the post with the
+// matched words wrapped in **, it is empty when only the author name matched
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SearchResult {
+    pub post: feed::defs::PostView,
+    pub score: f64,
+    #[serde(default)]
+    pub highlight: Option<String>,
+}
+
+// Membership of a post in a named timeline, the post itself is stored once in the
+// feed table and shared by every timeline it shows up in. createdAt is copied from
+// the post so a timeline can be paged and pruned without touching the feed table.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TimelineEntry {
+    pub timeline: String,
+    pub cid: String,
+    #[serde(rename = "createdAt")]
+    pub created_at: String,
+    // Cursor of the request that returned the post, None for the newest page
+    pub cursor: Option<String>,
+}
+
 impl PartialEq for TimelineCursor {
     fn eq(&self, other: &Self) -> bool {
-        self.timeline == other.timeline
-            && parse_datetime(&self.cursor) == parse_datetime(&other.cursor)
+        self.timeline == other.timeline && self.cursor == other.cursor
     }
 }
 
@@ -42,21 +82,50 @@ fn parse_datetime(s: &str) -> Result<DateTime<Utc>, ParseError> {
     s.parse::<DateTime<Utc>>()
 }
 
-// Since you might want to sort or store `TimelineCursor` in a hash set,
-// you should ideally implement `Eq` and `PartialOrd` + `Ord` as well.
+impl TimelineCursor {
+    // Creation date of the oldest post of the page, None when it is missing or
+    // unreadable
+    pub fn oldest_date(&self) -> Option<DateTime<Utc>> {
+        self.oldest
+            .as_deref()
+            .and_then(|oldest| parse_datetime(oldest).ok())
+    }
+}
 
-impl PartialOrd for TimelineCursor {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        match (parse_datetime(&self.cursor), parse_datetime(&other.cursor)) {
-            (Ok(self_dt), Ok(other_dt)) => Some(self_dt.cmp(&other_dt)),
-            _ => None,
-        }
+// Orders the pages by the creation date of their oldest post, pages without a
+// date come first and the cursor breaks the ties
+fn compare_pages(a: &TimelineCursor, b: &TimelineCursor) -> Ordering {
+    a.oldest_date()
+        .cmp(&b.oldest_date())
+        .then_with(|| a.cursor.cmp(&b.cursor))
+}
+
+// Whether the posts of the timeline are ordered by date, the timelines that are
+// not named after a subscription are
+pub fn is_chronological(timeline_name: &str) -> bool {
+    match timeline_name.parse::<Subscription>() {
+        Ok(subscription) => subscription.is_chronological(),
+        Err(_) => true,
     }
 }
 
-impl Ord for TimelineCursor {
-    fn cmp(&self, other: &Self) -> Ordering {
-        self.partial_cmp(other).unwrap_or(Ordering::Equal)
+// Returns the (oldest, newest) createdAt of the posts in a page
+fn page_bounds(
+    feed: &[feed::defs::FeedViewPost],
+) -> Result<Option<(String, String)>, anyhow::Error> {
+    let dates: Vec<&atrium_api::types::string::Datetime> = feed
+        .iter()
+        .filter_map(|f| match &f.post.record {
+            Record::AppBskyFeedPost(post) => Some(&post.created_at),
+            _ => None,
+        })
+        .collect();
+    match (dates.iter().min(), dates.iter().max()) {
+        (Some(oldest), Some(newest)) => Ok(Some((
+            serde_json::to_string(oldest)?.trim_matches('"').to_string(),
+            serde_json::to_string(newest)?.trim_matches('"').to_string(),
+        ))),
+        _ => Ok(None),
     }
 }
 
@@ -88,9 +157,35 @@ impl SurrealDB {
             .with_context(|| format!("No config dir: {:?}", dirs::config_dir()))?;
         let dir = config_dir.join("bsky");
         create_dir_all(&dir).await?;
-        let path = dir.join("bsky.db");
-        let db = Surreal::new::<RocksDb>(path.clone()).await?;
-        Ok(SurrealDB { db })
+        SurrealDB::open(dir.join("bsky.db")).await
+    }
+
+    pub async fn open(path: PathBuf) -> Result<Self> {
+        let db = Surreal::new::<RocksDb>(path).await?;
+        let surreal = SurrealDB { db };
+        surreal.define_search_index().await?;
+        Ok(surreal)
+    }
+
+    // Full text indexes on the post text and the author display name, they are only
+    // defined once since redefining an index rebuilds it
+    async fn define_search_index(&self) -> Result<(), anyhow::Error> {
+        let _ = self.db.use_ns("bsky").use_db("timeline").await;
+        let mut response = self.db.query("INFO FOR TABLE post;").await?;
+        let table: surrealdb::sql::Value = response.take(0)?;
+        if table.to_string().contains("post_text_search") {
+            return Ok(());
+        }
+        info!("defining the full text search indexes");
+        self.db
+            .query(
+                r#"DEFINE ANALYZER post_analyzer TOKENIZERS blank, class, punct FILTERS lowercase, ascii;
+                DEFINE INDEX post_text_search ON post FIELDS record.text SEARCH ANALYZER post_analyzer BM25 HIGHLIGHTS;
+                DEFINE INDEX post_author_search ON post FIELDS authorName SEARCH ANALYZER post_analyzer BM25;"#,
+            )
+            .await?
+            .check()?;
+        Ok(())
     }
 
     pub async fn store_post(
@@ -102,35 +197,37 @@ impl SurrealDB {
             .trim_matches('"')
             .to_string();
         let did: String = post.author.did.to_string().clone();
-        let sql = format!(
-            r#"UPDATE post:{} CONTENT {{
-                cid: "{}",
-                author: author:⟨{}⟩,
-                indexedAt: {},
-                labels: {},
-                likeCount: {},
-                record: {},
-                replyCount: {},
-                repostCount: {},
-                uri: {},
-                viewer: {},
-                embed: {},
-        }};"#,
-            cid.clone(),
-            cid.clone(),
-            did.clone(),
-            serde_json::to_string(&post.indexed_at)?,
-            serde_json::to_string(&post.labels)?,
-            serde_json::to_string(&post.like_count)?,
-            serde_json::to_string(&post.record)?,
-            serde_json::to_string(&post.reply_count)?,
-            serde_json::to_string(&post.repost_count)?,
-            serde_json::to_string(&post.uri)?,
-            serde_json::to_string(&post.viewer)?,
-            serde_json::to_string(&post.embed)?,
-        );
-        trace!("storing post: {}", sql);
-        let _created = self.db.query(sql).await?;
+        let sql = r#"UPDATE type::thing('post', $cid) CONTENT {
+                cid: $cid,
+                author: type::thing('author', $did),
+                indexedAt: $indexed_at,
+                labels: $labels,
+                likeCount: $like_count,
+                record: $record,
+                replyCount: $reply_count,
+                repostCount: $repost_count,
+                uri: $uri,
+                viewer: $viewer,
+                embed: $embed,
+                authorName: $author_name,
+        };"#;
+        trace!("storing post: {}", cid);
+        let _created = self
+            .db
+            .query(sql)
+            .bind(("cid", cid))
+            .bind(("did", did))
+            .bind(("author_name", post.author.display_name.clone()))
+            .bind(("indexed_at", serde_json::to_value(&post.indexed_at)?))
+            .bind(("labels", serde_json::to_value(&post.labels)?))
+            .bind(("like_count", serde_json::to_value(post.like_count)?))
+            .bind(("record", serde_json::to_value(&post.record)?))
+            .bind(("reply_count", serde_json::to_value(post.reply_count)?))
+            .bind(("repost_count", serde_json::to_value(post.repost_count)?))
+            .bind(("uri", post.uri))
+            .bind(("viewer", serde_json::to_value(&post.viewer)?))
+            .bind(("embed", serde_json::to_value(&post.embed)?))
+            .await?;
         Ok(())
     }
 
@@ -207,45 +304,186 @@ impl SurrealDB {
                 feed::defs::FeedViewPostReasonEnum::ReasonRepost(reason) => {}
             }
         }
+        let reason = serde_json::to_value(&f.reason)?;
         match (cid_root, cid_parent) {
             (Some(root), Some(parent)) => {
-                let sql = format!(
-                    r#"UPDATE feed:{} CONTENT {{
-                        post: post:{},
-                        reply: {{
-                          parent: post:⟨{}⟩,
-                          root: post:⟨{}⟩,
-                        }},
-                        reason: {},
-                }};"#,
-                    cid,
-                    cid,
-                    serde_json::to_string(&parent)?
-                        .trim_matches('"')
-                        .to_string(),
-                    serde_json::to_string(&root)?.trim_matches('"').to_string(),
-                    serde_json::to_string(&f.reason)?,
-                );
-                trace!("storing feedviewpost: {}", sql);
-                let _created = self.db.query(sql).await?;
+                let sql = r#"UPDATE type::thing('feed', $cid) CONTENT {
+                        post: type::thing('post', $cid),
+                        reply: {
+                          parent: type::thing('post', $parent),
+                          root: type::thing('post', $root),
+                        },
+                        reason: $reason,
+                };"#;
+                trace!("storing feedviewpost: {}", cid);
+                let _created = self
+                    .db
+                    .query(sql)
+                    .bind(("cid", cid))
+                    .bind(("parent", parent))
+                    .bind(("root", root))
+                    .bind(("reason", reason))
+                    .await?;
             }
             _ => {
-                let sql = format!(
-                    r#"UPDATE feed:{} CONTENT {{
-                        post: post:{},
-                        reason: {},
-                }};"#,
-                    cid,
-                    cid,
-                    serde_json::to_string(&f.reason)?,
-                );
-                trace!("storing feedviewpost: {}", sql);
-                let _created = self.db.query(sql).await?;
+                let sql = r#"UPDATE type::thing('feed', $cid) CONTENT {
+                        post: type::thing('post', $cid),
+                        reason: $reason,
+                };"#;
+                trace!("storing feedviewpost: {}", cid);
+                let _created = self
+                    .db
+                    .query(sql)
+                    .bind(("cid", cid))
+                    .bind(("reason", reason))
+                    .await?;
             }
         }
         Ok(())
     }
 
+    // Stores a notification linked to its author, post_cid is the cid of the
+    // stored post the notification is about
+    pub async fn store_notification(
+        &self,
+        notification: Notification,
+        post_cid: Option<String>,
+    ) -> Result<(), anyhow::Error> {
+        let _ = self.db.use_ns("bsky").use_db("timeline").await;
+        let author: bsky::actor::defs::ProfileViewBasic =
+            serde_json::from_value(serde_json::to_value(&notification.author)?)?;
+        self.store_author(author).await?;
+        let cid: String = serde_json::to_string(&notification.cid)?
+            .trim_matches('"')
+            .to_string();
+        let sql = match post_cid {
+            Some(_) => {
+                r#"UPDATE type::thing('notification', $cid) CONTENT {
+                        uri: $uri,
+                        cid: $cid,
+                        reason: $reason,
+                        reasonSubject: $reason_subject,
+                        isRead: $is_read,
+                        indexedAt: $indexed_at,
+                        record: $record,
+                        author: type::thing('author', $did),
+                        post: type::thing('post', $post_cid),
+                };"#
+            }
+            None => {
+                r#"UPDATE type::thing('notification', $cid) CONTENT {
+                        uri: $uri,
+                        cid: $cid,
+                        reason: $reason,
+                        reasonSubject: $reason_subject,
+                        isRead: $is_read,
+                        indexedAt: $indexed_at,
+                        record: $record,
+                        author: type::thing('author', $did),
+                };"#
+            }
+        };
+        trace!("storing notification: {}", cid);
+        self.db
+            .query(sql)
+            .bind(("cid", cid))
+            .bind(("uri", notification.uri))
+            .bind(("reason", notification.reason))
+            .bind(("reason_subject", notification.reason_subject))
+            .bind(("is_read", notification.is_read))
+            .bind((
+                "indexed_at",
+                serde_json::to_string(&notification.indexed_at)?
+                    .trim_matches('"')
+                    .to_string(),
+            ))
+            .bind(("record", serde_json::to_value(&notification.record)?))
+            .bind(("did", notification.author.did.to_string()))
+            .bind(("post_cid", post_cid))
+            .await?
+            .check()?;
+        Ok(())
+    }
+
+    // Marks the notifications indexed up to seen_at as read
+    pub async fn mark_notifications_read(&self, seen_at: String) -> Result<(), anyhow::Error> {
+        let _ = self.db.use_ns("bsky").use_db("timeline").await;
+        Querier::new(self.db.clone())
+            .mark_notifications_read(&seen_at)
+            .await
+    }
+
+    // Remembers that a notification raised an alert so that it raises only one
+    pub async fn store_notified(&self, uri: String) -> Result<(), anyhow::Error> {
+        let _ = self.db.use_ns("bsky").use_db("timeline").await;
+        let sql = r#"UPDATE type::thing('notified', $uri) CONTENT {
+                uri: $uri,
+                notifiedAt: time::now(),
+        };"#;
+        self.db.query(sql).bind(("uri", uri)).await?.check()?;
+        Ok(())
+    }
+
+    // Stores a post read from a repository export in repo_post, the export has
+    // no author profile nor counts so it is kept apart from the post views
+    pub async fn store_repo_post(
+        &self,
+        uri: String,
+        cid: String,
+        did: String,
+        record: serde_json::Value,
+    ) -> Result<(), anyhow::Error> {
+        let _ = self.db.use_ns("bsky").use_db("timeline").await;
+        let sql = r#"UPDATE type::thing('repo_post', $cid) CONTENT {
+                cid: $cid,
+                uri: $uri,
+                author: type::thing('author', $did),
+                record: $record,
+        };"#;
+        trace!("storing repo post: {}", uri);
+        self.db
+            .query(sql)
+            .bind(("cid", cid))
+            .bind(("uri", uri))
+            .bind(("did", did))
+            .bind(("record", record))
+            .await?
+            .check()?;
+        Ok(())
+    }
+
+    // Stores a like or a follow read from a repository export in table
+    pub async fn store_repo_subject(
+        &self,
+        table: &str,
+        uri: String,
+        cid: String,
+        did: String,
+        subject: serde_json::Value,
+        created_at: String,
+    ) -> Result<(), anyhow::Error> {
+        let _ = self.db.use_ns("bsky").use_db("timeline").await;
+        let sql = r#"UPDATE type::thing($table, $cid) CONTENT {
+                cid: $cid,
+                uri: $uri,
+                author: type::thing('author', $did),
+                subject: $subject,
+                createdAt: $created_at,
+        };"#;
+        trace!("storing repo {}: {}", table, uri);
+        self.db
+            .query(sql)
+            .bind(("table", table.to_string()))
+            .bind(("cid", cid))
+            .bind(("uri", uri))
+            .bind(("did", did))
+            .bind(("subject", subject))
+            .bind(("created_at", created_at))
+            .await?
+            .check()?;
+        Ok(())
+    }
+
     pub async fn store_feed_post_raw(
         &self,
         feed: Vec<atrium_api::app::bsky::feed::defs::FeedViewPost>,
@@ -262,74 +500,220 @@ impl SurrealDB {
         Ok(())
     }
 
-    // TODO:use timeline name to split timelines:
-    // either through use_db or feed:{timeline_name}
     pub async fn store_timeline(
         &self,
         timeline_data: feed::get_timeline::Output,
         timeline_name: String,
     ) -> Result<(), anyhow::Error> {
+        self.store_timeline_page(timeline_data, timeline_name, None)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn store_timeline_page(
+        &self,
+        timeline_data: feed::get_timeline::Output,
+        timeline_name: String,
+        request_cursor: Option<String>,
+    ) -> Result<Option<TimelineCursor>, anyhow::Error> {
+        let feed_page = FeedPage {
+            feed: timeline_data.feed,
+            cursor: timeline_data.cursor,
+        };
+        self.store_feed_page(feed_page, timeline_name, request_cursor)
+            .await
+    }
+
+    // Stores a page fetched with request_cursor and records it in the cursor table,
+    // the page extends the chain of the page that returned request_cursor, any
+    // other chain it overlaps with is merged into it since there is no gap left.
+    pub async fn store_feed_page(
+        &self,
+        feed_page: FeedPage,
+        timeline_name: String,
+        request_cursor: Option<String>,
+    ) -> Result<Option<TimelineCursor>, anyhow::Error> {
         let _ = self.db.use_ns("bsky").use_db("timeline").await;
-        let feed: Vec<feed::defs::FeedViewPost> = timeline_data.feed;
+        let feed: Vec<feed::defs::FeedViewPost> = feed_page.feed;
         info!(
             "Inserting into {:?} timeline Db: {:?}",
             timeline_name,
             feed.len()
         );
+        let bounds = page_bounds(&feed)?;
+        let mut cids = Vec::new();
         for f in feed {
+            let cid = serde_json::to_string(&f.post.cid)?
+                .trim_matches('"')
+                .to_string();
+            cids.push(cid.clone());
+            let entry = TimelineEntry {
+                timeline: timeline_name.clone(),
+                cid,
+                created_at: match &f.post.record {
+                    Record::AppBskyFeedPost(post) => serde_json::to_string(&post.created_at)?,
+                    _ => serde_json::to_string(&f.post.indexed_at)?,
+                }
+                .trim_matches('"')
+                .to_string(),
+                cursor: request_cursor.clone(),
+            };
             self.store_feed_post_view(f).await?;
+            self.store_timeline_entry(entry).await?;
         }
-        self.store_cursor(timeline_data.cursor, timeline_name)
+
+        let querier = Querier::new(self.db.clone());
+        let previous = match &request_cursor {
+            Some(c) => querier.page_with_cursor(&timeline_name, c).await?,
+            None => None,
+        };
+        let (oldest, newest) = match bounds {
+            Some(bounds) => bounds,
+            None => {
+                // Nothing to cover, skip over the empty page
+                if let (Some(previous), Some(c)) = (previous, request_cursor) {
+                    info!(
+                        "empty page in chain {}, moving cursor to {:?}",
+                        previous.chain, feed_page.cursor
+                    );
+                    querier
+                        .update_page_cursor(&timeline_name, &c, feed_page.cursor)
+                        .await?;
+                }
+                return Ok(None);
+            }
+        };
+        let chain = match previous {
+            Some(previous) => previous.chain,
+            None => Utc::now().timestamp_micros().to_string(),
+        };
+        // A feed is not ordered by date, only a post in common shows that two
+        // chains meet
+        let others = if is_chronological(&timeline_name) {
+            querier
+                .overlapping_chains(&timeline_name, &chain, &oldest, &newest)
+                .await?
+        } else {
+            querier
+                .chains_sharing_cids(&timeline_name, &chain, cids.clone())
+                .await?
+        };
+        for other in others {
+            info!("chain {} overlaps chain {}, merging", other, chain);
+            querier.merge_chains(&timeline_name, &other, &chain).await?;
+        }
+        let page = TimelineCursor {
+            cursor: feed_page.cursor,
+            timeline: timeline_name,
+            request_cursor,
+            chain,
+            oldest: Some(oldest),
+            newest: Some(newest),
+            cids,
+        };
+        self.store_cursor(page.clone()).await?;
+        Ok(Some(page))
+    }
+
+    pub async fn store_timeline_entry(&self, entry: TimelineEntry) -> Result<(), anyhow::Error> {
+        let sql = r#"UPDATE type::thing('timeline_entry', [$timeline, $cid]) CONTENT {
+                timeline: $timeline,
+                cid: $cid,
+                feed: type::thing('feed', $cid),
+                createdAt: $created_at,
+                cursor: $cursor,
+        };"#;
+        trace!("storing timeline entry: {:?}", entry);
+        let _created = self
+            .db
+            .query(sql)
+            .bind(("timeline", entry.timeline))
+            .bind(("cid", entry.cid))
+            .bind(("created_at", entry.created_at))
+            .bind(("cursor", entry.cursor))
             .await?;
         Ok(())
     }
 
-    pub async fn store_cursor(
-        &self,
-        cursor: Option<String>,
-        timeline_name: String,
-    ) -> Result<(), anyhow::Error> {
-        match cursor {
-            Some(cursor) => {
-                trace!("Inserting: {:?}", cursor);
-                let created: Vec<TimelineCursor> = self
-                    .db
-                    .create("cursor")
-                    .content(TimelineCursor {
-                        cursor: String::from(cursor),
-                        timeline: timeline_name,
-                    })
-                    .await?;
-                trace!("Inserted in DB: {:?}", created);
-            }
-            None => warn!("cursor is none"),
+    pub async fn store_cursor(&self, page: TimelineCursor) -> Result<(), anyhow::Error> {
+        if page.cursor.is_none() {
+            warn!("cursor is none, reached the end of {}", page.timeline);
         }
+        trace!("Inserting: {:?}", page);
+        let created: Vec<TimelineCursor> = self.db.create("cursor").content(page).await?;
+        trace!("Inserted in DB: {:?}", created);
         Ok(())
     }
 
-    // TODO:use timeline name to split timelines:
-    // either through use_db or feed:{timeline_name}
     pub async fn read_timeline(
         &self,
         timeline_name: String,
-        filter: Option<String>,
+        filter: TimelineFilter,
         limit: Option<i32>,
     ) -> Result<Vec<FeedViewPostFlat>, anyhow::Error> {
         let _ = self.db.use_ns("bsky").use_db("timeline").await;
         let value: Vec<FeedViewPostFlat> = Querier::new(self.db.clone())
-            .read_timeline(filter, limit)
+            .read_timeline(&timeline_name, filter, limit)
             .await?;
         Ok(value)
     }
 
-    // TODO:use timeline name to split timelines:
-    // either through use_db or feed:{timeline_name}
+    pub async fn search_posts(
+        &self,
+        query: String,
+        limit: i32,
+    ) -> Result<Vec<SearchResult>, anyhow::Error> {
+        let _ = self.db.use_ns("bsky").use_db("timeline").await;
+        let results: Vec<SearchResult> = Querier::new(self.db.clone())
+            .search_posts(&query, limit)
+            .await?;
+        info!("{} posts match {:?}", results.len(), query);
+        Ok(results)
+    }
+
+    // Stores every post of the thread, each reply is linked to its parent with a
+    // replies_to edge, e.g. SELECT <-replies_to<-post FROM post:⟨cid⟩
+    pub async fn store_thread(&self, thread: &feed::defs::ThreadViewPost) -> Result<usize> {
+        let _ = self.db.use_ns("bsky").use_db("timeline").await;
+        let posts = thread_posts(thread);
+        let querier = Querier::new(self.db.clone());
+        for (post, parent) in &posts {
+            self.store_post_view(post.clone()).await?;
+            if let Some(parent) = parent {
+                let cid: String = serde_json::to_string(&post.cid)?
+                    .trim_matches('"')
+                    .to_string();
+                let parent_cid: String = serde_json::to_string(&parent.cid)?
+                    .trim_matches('"')
+                    .to_string();
+                querier.relate_reply(&cid, &parent_cid).await?;
+            }
+        }
+        info!("stored {} posts of thread {}", posts.len(), thread.post.uri);
+        Ok(posts.len())
+    }
+
+    pub async fn prune_timeline(
+        &self,
+        timeline_name: String,
+        older_than: String,
+    ) -> Result<(), anyhow::Error> {
+        let _ = self.db.use_ns("bsky").use_db("timeline").await;
+        info!("pruning {} posts older than {}", timeline_name, older_than);
+        Querier::new(self.db.clone())
+            .prune_timeline(&timeline_name, &older_than)
+            .await?;
+        Ok(())
+    }
+
     pub async fn read_cursor(
         &self,
         timeline_name: String,
     ) -> Result<Vec<TimelineCursor>, anyhow::Error> {
         let _ = self.db.use_ns("bsky").use_db("timeline").await;
-        let cursor: Vec<TimelineCursor> = self.db.select("cursor").await?;
+        let cursor: Vec<TimelineCursor> = Querier::new(self.db.clone())
+            .read_cursors(&timeline_name)
+            .await?;
         info!("Reading into cursor timeline Db: {:?}", cursor);
         Ok(cursor)
     }
@@ -340,11 +724,11 @@ impl SurrealDB {
     ) -> Result<Option<String>, anyhow::Error> {
         let cursors: Vec<TimelineCursor> = self.read_cursor(timeline_name.clone()).await?;
         info!("Reading cursors: {:?}", cursors);
-        let max = cursors.into_iter().max();
+        let max = cursors.into_iter().max_by(compare_pages);
         match max {
             Some(m) => {
                 info!("max cursor: {:?}", m);
-                Ok(Some(m.cursor))
+                Ok(m.cursor)
             }
             _ => {
                 error!("No cursors found");
@@ -353,3 +737,191 @@ impl SurrealDB {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{cid, feed_view_post, CREATED_AT};
+
+    const HOSTILE: &str = r#"'; DELETE feed; --" } ⟩ $cid"#;
+
+    fn page(cursor: &str, oldest: &str) -> TimelineCursor {
+        TimelineCursor {
+            cursor: Some(cursor.to_string()),
+            timeline: String::from("default"),
+            request_cursor: None,
+            chain: String::from("1"),
+            oldest: Some(oldest.to_string()),
+            newest: None,
+            cids: vec![],
+        }
+    }
+
+    #[test]
+    fn pages_are_ordered_by_date_then_cursor() {
+        let mut pages = vec![
+            page("b", "2024-03-01T10:00:00.000Z"),
+            page("a", "2024-03-01T10:00:00.000Z"),
+            page("c", "not a date"),
+            page("d", "2024-03-01T11:00:00+02:00"),
+        ];
+        pages.sort_by(compare_pages);
+        let cursors: Vec<_> = pages.iter().filter_map(|p| p.cursor.as_deref()).collect();
+        assert_eq!(cursors, vec!["c", "d", "a", "b"]);
+    }
+
+    #[tokio::test]
+    async fn hostile_text_and_timeline_are_stored_verbatim() -> Result<(), anyhow::Error> {
+        let path = std::env::temp_dir().join(format!("rbsky-test-{}.db", std::process::id()));
+        let db = SurrealDB::open(path.clone()).await?;
+        let feed_page = FeedPage {
+            feed: vec![feed_view_post("3kabc", HOSTILE, CREATED_AT)],
+            cursor: Some(String::from(HOSTILE)),
+        };
+        db.store_feed_page(
+            feed_page,
+            String::from(HOSTILE),
+            Some(String::from(HOSTILE)),
+        )
+        .await?;
+
+        let filter = TimelineFilter::new().created_before(HOSTILE);
+        let posts = db
+            .read_timeline(String::from(HOSTILE), filter, None)
+            .await?;
+        assert_eq!(posts.len(), 1);
+        match &posts[0].post.record {
+            Record::AppBskyFeedPost(post) => assert_eq!(post.text, HOSTILE),
+            other => panic!("unexpected record {:?}", other),
+        }
+        let cursors = db.read_cursor(String::from(HOSTILE)).await?;
+        assert_eq!(cursors.len(), 1);
+        assert_eq!(cursors[0].cursor.as_deref(), Some(HOSTILE));
+
+        let others = db
+            .read_timeline(String::from("default"), TimelineFilter::default(), None)
+            .await?;
+        assert!(others.is_empty());
+        std::fs::remove_dir_all(path).ok();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn repo_posts_leave_the_post_views_alone() -> Result<(), anyhow::Error> {
+        let path =
+            std::env::temp_dir().join(format!("rbsky-repo-post-test-{}.db", std::process::id()));
+        let db = SurrealDB::open(path.clone()).await?;
+        let feed_page = FeedPage {
+            feed: vec![feed_view_post("3kabc", "fetched", CREATED_AT)],
+            cursor: None,
+        };
+        db.store_feed_page(feed_page, String::from("default"), None)
+            .await?;
+        for rkey in ["3kabc", "3kdef"] {
+            let record = serde_json::json!({
+                "$type": "app.bsky.feed.post",
+                "text": "imported",
+                "createdAt": CREATED_AT,
+            });
+            let uri = format!("at://did:plc:alice/app.bsky.feed.post/{}", rkey);
+            db.store_repo_post(uri, cid(rkey), String::from("did:plc:alice"), record)
+                .await?;
+        }
+
+        let posts = db
+            .read_timeline(String::from("default"), TimelineFilter::default(), None)
+            .await?;
+        assert_eq!(posts.len(), 1);
+        match &posts[0].post.record {
+            Record::AppBskyFeedPost(post) => assert_eq!(post.text, "fetched"),
+            other => panic!("unexpected record {:?}", other),
+        }
+        assert!(db
+            .search_posts(String::from("imported"), 10)
+            .await?
+            .is_empty());
+        std::fs::remove_dir_all(path).ok();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn search_ranks_and_highlights_the_text() -> Result<(), anyhow::Error> {
+        let path =
+            std::env::temp_dir().join(format!("rbsky-search-test-{}.db", std::process::id()));
+        let db = SurrealDB::open(path.clone()).await?;
+        let feed_page = FeedPage {
+            feed: vec![feed_view_post(
+                "3kabc",
+                "Writing a Bluesky client in Rust",
+                CREATED_AT,
+            )],
+            cursor: None,
+        };
+        db.store_feed_page(feed_page, String::from("default"), None)
+            .await?;
+
+        let results = db.search_posts(String::from("rust"), 10).await?;
+        assert_eq!(results.len(), 1);
+        assert!(results[0].score > 0.0);
+        assert_eq!(
+            results[0].highlight.as_deref(),
+            Some("Writing a Bluesky client in **Rust**")
+        );
+        assert!(db
+            .search_posts(String::from("python"), 10)
+            .await?
+            .is_empty());
+        std::fs::remove_dir_all(path).ok();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn feed_chains_only_merge_on_a_shared_post() -> Result<(), anyhow::Error> {
+        let path = std::env::temp_dir().join(format!("rbsky-chain-test-{}.db", std::process::id()));
+        let db = SurrealDB::open(path.clone()).await?;
+        let page = |posts: &[(&str, u32)]| FeedPage {
+            feed: posts
+                .iter()
+                .map(|(rkey, minute)| {
+                    feed_view_post(
+                        rkey,
+                        "post",
+                        &format!("2024-03-01T10:{:02}:00.000Z", minute),
+                    )
+                })
+                .collect(),
+            cursor: None,
+        };
+        let feed = String::from("feed:at://did:plc:alice/app.bsky.feed.generator/hot");
+        for timeline in [String::from("default"), feed.clone()] {
+            db.store_feed_page(page(&[("3ka", 9), ("3kb", 5)]), timeline.clone(), None)
+                .await?;
+            db.store_feed_page(page(&[("3kc", 8), ("3kd", 6)]), timeline.clone(), None)
+                .await?;
+        }
+        let chains = |cursors: Vec<TimelineCursor>| {
+            let mut chains: Vec<String> = cursors.into_iter().map(|c| c.chain).collect();
+            chains.sort();
+            chains.dedup();
+            chains.len()
+        };
+        // The pages overlap in time, only the home timeline is ordered by it
+        assert_eq!(chains(db.read_cursor(String::from("default")).await?), 1);
+        assert_eq!(chains(db.read_cursor(feed.clone()).await?), 2);
+
+        db.store_feed_page(page(&[("3ke", 7), ("3kb", 5)]), feed.clone(), None)
+            .await?;
+        let cursors = db.read_cursor(feed.clone()).await?;
+        let chain = |rkey: &str| {
+            let cid = cid(rkey);
+            cursors
+                .iter()
+                .find(|c| c.cids.contains(&cid))
+                .map(|c| c.chain.clone())
+        };
+        assert_eq!(chain("3ke"), chain("3ka"));
+        assert_ne!(chain("3ke"), chain("3kc"));
+        std::fs::remove_dir_all(path).ok();
+        Ok(())
+    }
+}
@@ -0,0 +1,135 @@
+use atrium_api::app::bsky::feed::defs::{
+    PostView, ThreadViewPost, ThreadViewPostParentEnum, ThreadViewPostRepliesItem,
+};
+use serde::{Deserialize, Serialize};
+
+// One line of a thread flattened for display, depth is the indentation level,
+// the root of the thread is at 0 and focus marks the post the thread was asked for
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ThreadLine {
+    pub depth: usize,
+    pub focus: bool,
+    pub post: PostView,
+}
+
+fn parents(thread: &ThreadViewPost) -> Vec<&ThreadViewPost> {
+    let mut parents = Vec::new();
+    let mut current = thread;
+    while let Some(ThreadViewPostParentEnum::ThreadViewPost(parent)) = &current.parent {
+        parents.push(parent.as_ref());
+        current = parent;
+    }
+    parents.reverse();
+    parents
+}
+
+fn replies(thread: &ThreadViewPost) -> Vec<&ThreadViewPost> {
+    thread
+        .replies
+        .iter()
+        .flatten()
+        .filter_map(|reply| match reply {
+            ThreadViewPostRepliesItem::ThreadViewPost(reply) => Some(reply.as_ref()),
+            _ => None,
+        })
+        .collect()
+}
+
+// Returns the parents from the root down, the focused post, then its replies
+// depth first, each reply one level deeper than the post it answers
+pub fn flatten_thread(thread: &ThreadViewPost) -> Vec<ThreadLine> {
+    let mut lines: Vec<ThreadLine> = parents(thread)
+        .into_iter()
+        .enumerate()
+        .map(|(depth, parent)| ThreadLine {
+            depth,
+            focus: false,
+            post: parent.post.clone(),
+        })
+        .collect();
+    let mut stack = vec![(lines.len(), true, thread)];
+    while let Some((depth, focus, current)) = stack.pop() {
+        lines.push(ThreadLine {
+            depth,
+            focus,
+            post: current.post.clone(),
+        });
+        for reply in replies(current).into_iter().rev() {
+            stack.push((depth + 1, false, reply));
+        }
+    }
+    lines
+}
+
+// Returns every post of the thread along with the post it replies to, parents
+// come before their replies so they can be stored in order
+pub fn thread_posts(thread: &ThreadViewPost) -> Vec<(PostView, Option<PostView>)> {
+    let mut posts = Vec::new();
+    let mut previous: Option<&PostView> = None;
+    for parent in parents(thread) {
+        posts.push((parent.post.clone(), previous.cloned()));
+        previous = Some(&parent.post);
+    }
+    let mut stack = vec![(thread, previous)];
+    while let Some((current, parent)) = stack.pop() {
+        posts.push((current.post.clone(), parent.cloned()));
+        for reply in replies(current) {
+            stack.push((reply, Some(&current.post)));
+        }
+    }
+    posts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{post_view_json, CREATED_AT};
+
+    fn thread_view(
+        text: &str,
+        parent: Option<serde_json::Value>,
+        replies: Vec<serde_json::Value>,
+    ) -> serde_json::Value {
+        let mut thread = serde_json::json!({
+            "$type": "app.bsky.feed.defs#threadViewPost",
+            "post": post_view_json(text, text, CREATED_AT),
+            "replies": replies,
+        });
+        if let Some(parent) = parent {
+            thread["parent"] = parent;
+        }
+        thread
+    }
+
+    #[test]
+    fn replies_are_indented_under_their_parent() {
+        let root = thread_view("root", None, vec![]);
+        let a = thread_view("a", None, vec![thread_view("a1", None, vec![])]);
+        let b = thread_view("b", None, vec![]);
+        let thread: ThreadViewPost =
+            serde_json::from_value(thread_view("focus", Some(root), vec![a, b]))
+                .expect("valid thread view post");
+
+        let lines: Vec<(usize, bool, String)> = flatten_thread(&thread)
+            .into_iter()
+            .map(|line| (line.depth, line.focus, line.post.uri))
+            .collect();
+        let expected = [
+            (0, false, "root"),
+            (1, true, "focus"),
+            (2, false, "a"),
+            (3, false, "a1"),
+            (2, false, "b"),
+        ];
+        assert_eq!(lines.len(), expected.len());
+        for (line, (depth, focus, text)) in lines.iter().zip(expected) {
+            assert_eq!((line.0, line.1), (depth, focus));
+            assert!(line.2.ends_with(text), "{} is not {}", line.2, text);
+        }
+
+        let posts = thread_posts(&thread);
+        assert_eq!(posts.len(), 5);
+        assert!(posts[0].1.is_none());
+        assert!(posts.iter().skip(1).all(|(_, parent)| parent.is_some()));
+    }
+}
@@ -0,0 +1,320 @@
+use std::io::Read;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::commands::CreatePostArgs;
+use crate::composer::{graphemes, split, MAX_GRAPHEMES};
+use crate::lang::{self, MAX_LANGS};
+use crate::markdown;
+
+pub const MAX_IMAGES: usize = 4;
+// Largest image blob accepted by the server
+pub const MAX_IMAGE_BYTES: u64 = 1_000_000;
+
+// A problem that makes the server reject the post
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ValidationError {
+    Empty,
+    TooLong { graphemes: usize, max: usize },
+    Markdown { message: String },
+    TooManyImages { count: usize, max: usize },
+    UnreadableImage { path: String, message: String },
+    ImageTooLarge { path: String, size: u64, max: u64 },
+    UnsupportedImageType { path: String },
+    TooManyAltTexts { count: usize, images: usize },
+    InvalidLanguage { tag: String },
+    TooManyLanguages { count: usize, max: usize },
+}
+
+// A problem the post can be sent with
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ValidationWarning {
+    MissingAltText { path: String },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Validation {
+    pub graphemes: usize,
+    pub max_graphemes: usize,
+    // Number of posts the text is sent as, more than one for a thread
+    pub posts: usize,
+    pub errors: Vec<ValidationError>,
+    pub warnings: Vec<ValidationWarning>,
+}
+
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValidationError::Empty => write!(f, "the post is empty"),
+            ValidationError::TooLong { graphemes, max } => {
+                write!(
+                    f,
+                    "the post is {} characters long, at most {}",
+                    graphemes, max
+                )
+            }
+            ValidationError::Markdown { message } => write!(f, "{}", message),
+            ValidationError::TooManyImages { count, max } => {
+                write!(f, "{} images attached, at most {}", count, max)
+            }
+            ValidationError::UnreadableImage { path, message } => {
+                write!(f, "{}: {}", path, message)
+            }
+            ValidationError::ImageTooLarge { path, size, max } => {
+                write!(f, "{} is {} bytes, at most {}", path, size, max)
+            }
+            ValidationError::UnsupportedImageType { path } => {
+                write!(f, "{} is not a jpeg, png, webp or gif image", path)
+            }
+            ValidationError::TooManyAltTexts { count, images } => {
+                write!(f, "{} alt texts for {} images", count, images)
+            }
+            ValidationError::InvalidLanguage { tag } => {
+                write!(f, "{} is not a language tag, e.g. en or pt-BR", tag)
+            }
+            ValidationError::TooManyLanguages { count, max } => {
+                write!(f, "{} languages, at most {}", count, max)
+            }
+        }
+    }
+}
+
+impl Validation {
+    pub fn is_valid(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    // Returns the errors as a single error, one per line
+    pub fn into_result(self) -> Result<(), anyhow::Error> {
+        if self.is_valid() {
+            return Ok(());
+        }
+        let lines: Vec<String> = self.errors.iter().map(ToString::to_string).collect();
+        Err(anyhow::Error::msg(lines.join("\n")))
+    }
+}
+
+// Returns the MIME type of an image from its first bytes
+pub fn image_mime(header: &[u8]) -> Option<&'static str> {
+    match header {
+        [0xFF, 0xD8, 0xFF, ..] => Some("image/jpeg"),
+        [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, ..] => Some("image/png"),
+        [b'G', b'I', b'F', b'8', ..] => Some("image/gif"),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some("image/webp"),
+        _ => None,
+    }
+}
+
+// Images over the size limit are accepted when they are resized before upload
+fn validate_image(path: &Path, resize: bool) -> Result<(), ValidationError> {
+    let display = path.display().to_string();
+    let unreadable = |e: std::io::Error| ValidationError::UnreadableImage {
+        path: display.clone(),
+        message: e.to_string(),
+    };
+    let mut file = std::fs::File::open(path).map_err(unreadable)?;
+    let size = file.metadata().map_err(unreadable)?.len();
+    let mut header = Vec::with_capacity(12);
+    file.by_ref()
+        .take(12)
+        .read_to_end(&mut header)
+        .map_err(unreadable)?;
+    if image_mime(&header).is_none() {
+        return Err(ValidationError::UnsupportedImageType { path: display });
+    }
+    if size > MAX_IMAGE_BYTES && !resize {
+        return Err(ValidationError::ImageTooLarge {
+            path: display,
+            size,
+            max: MAX_IMAGE_BYTES,
+        });
+    }
+    Ok(())
+}
+
+// Checks a post before it is sent, the text is counted as it will be posted,
+// i.e. after the Markdown conversion when args.markdown is set
+pub fn validate(args: &CreatePostArgs) -> Validation {
+    let mut errors = Vec::new();
+    let mut warnings = Vec::new();
+    let text = if args.markdown {
+        match markdown::to_post(&args.text) {
+            Ok((text, _)) => text,
+            Err(e) => {
+                errors.push(ValidationError::Markdown {
+                    message: e.to_string(),
+                });
+                args.text.clone()
+            }
+        }
+    } else {
+        args.text.clone()
+    };
+    let count = graphemes(&text);
+    if text.trim().is_empty() && args.images.is_empty() && args.quote.is_none() {
+        errors.push(ValidationError::Empty);
+    }
+    if count > MAX_GRAPHEMES {
+        errors.push(ValidationError::TooLong {
+            graphemes: count,
+            max: MAX_GRAPHEMES,
+        });
+    }
+    if args.images.len() > MAX_IMAGES {
+        errors.push(ValidationError::TooManyImages {
+            count: args.images.len(),
+            max: MAX_IMAGES,
+        });
+    }
+    if args.alt.len() > args.images.len() {
+        errors.push(ValidationError::TooManyAltTexts {
+            count: args.alt.len(),
+            images: args.images.len(),
+        });
+    }
+    if args.langs.len() > MAX_LANGS {
+        errors.push(ValidationError::TooManyLanguages {
+            count: args.langs.len(),
+            max: MAX_LANGS,
+        });
+    }
+    for tag in args.langs.iter().filter(|tag| !lang::is_valid(tag)) {
+        errors.push(ValidationError::InvalidLanguage { tag: tag.clone() });
+    }
+    for (i, image) in args.images.iter().enumerate() {
+        if let Err(e) = validate_image(image, args.resize) {
+            errors.push(e);
+        }
+        let has_alt = matches!(args.alt.get(i), Some(alt) if !alt.trim().is_empty());
+        if !has_alt {
+            warnings.push(ValidationWarning::MissingAltText {
+                path: image.display().to_string(),
+            });
+        }
+    }
+    Validation {
+        graphemes: count,
+        max_graphemes: MAX_GRAPHEMES,
+        posts: 1,
+        errors,
+        warnings,
+    }
+}
+
+// Checks a text that is split into a thread when it is too long, each post is
+// checked as it will be sent and graphemes counts the last one
+pub fn validate_thread(args: &CreatePostArgs, numbered: bool) -> Validation {
+    let text = if args.markdown {
+        match markdown::to_post(&args.text) {
+            Ok((text, _)) => text,
+            Err(_) => return validate(args),
+        }
+    } else {
+        args.text.clone()
+    };
+    let segments = split(&text, numbered);
+    if segments.len() < 2 {
+        return validate(args);
+    }
+    let mut thread = Validation {
+        graphemes: 0,
+        max_graphemes: MAX_GRAPHEMES,
+        posts: segments.len(),
+        errors: Vec::new(),
+        warnings: Vec::new(),
+    };
+    for (i, segment) in segments.into_iter().enumerate() {
+        let first = i == 0;
+        let validation = validate(&CreatePostArgs {
+            images: if first { args.images.clone() } else { vec![] },
+            alt: if first { args.alt.clone() } else { vec![] },
+            quote: if first { args.quote.clone() } else { None },
+            markdown: false,
+            resize: args.resize,
+            no_card: args.no_card,
+            langs: args.langs.clone(),
+            detect_lang: args.detect_lang,
+            ..CreatePostArgs::markdown(segment)
+        });
+        thread.graphemes = validation.graphemes;
+        for error in validation.errors {
+            if !thread.errors.contains(&error) {
+                thread.errors.push(error);
+            }
+        }
+        thread.warnings.extend(validation.warnings);
+    }
+    thread
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn post(text: &str) -> CreatePostArgs {
+        CreatePostArgs::markdown(text.to_string())
+    }
+
+    #[test]
+    fn graphemes_are_counted_after_the_markdown_conversion() {
+        // Each family emoji is a single grapheme of several code points
+        let text = format!("[{}](https://example.com/a/long/path)", "👩‍👩‍👧".repeat(300));
+        let validation = validate(&post(&text));
+        assert_eq!(validation.graphemes, 300);
+        assert!(validation.is_valid(), "{:?}", validation.errors);
+
+        let validation = validate(&post(&"é".repeat(301)));
+        assert_eq!(
+            validation.errors,
+            vec![ValidationError::TooLong {
+                graphemes: 301,
+                max: MAX_GRAPHEMES
+            }]
+        );
+    }
+
+    #[test]
+    fn a_text_split_into_a_thread_is_valid() {
+        let text = "A sentence of a long post. ".repeat(30);
+        let validation = validate_thread(&post(&text), true);
+        assert!(validation.is_valid(), "{:?}", validation.errors);
+        assert_eq!(validation.posts, 3);
+        assert!(validation.graphemes <= MAX_GRAPHEMES);
+
+        let validation = validate_thread(&post("short"), true);
+        assert_eq!((validation.posts, validation.graphemes), (1, 5));
+        assert!(!validate_thread(&post(" "), true).is_valid());
+    }
+
+    #[test]
+    fn images_are_checked_by_count_type_and_alt_text() {
+        let dir = std::env::temp_dir().join(format!("rbsky-validate-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let png = dir.join("image.png");
+        std::fs::write(&png, b"\x89PNG\x0D\x0A\x1A\x0A rest of the image").unwrap();
+        let text = dir.join("notes.png");
+        std::fs::write(&text, b"not an image").unwrap();
+
+        let mut args = post("pictures");
+        args.images = vec![png.clone(), text.clone(), png.clone(), png.clone(), png];
+        args.alt = vec![String::from("a cat")];
+        let validation = validate(&args);
+        assert_eq!(
+            validation.errors,
+            vec![
+                ValidationError::TooManyImages {
+                    count: 5,
+                    max: MAX_IMAGES
+                },
+                ValidationError::UnsupportedImageType {
+                    path: text.display().to_string()
+                },
+            ]
+        );
+        assert_eq!(validation.warnings.len(), 4);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}